@@ -2,8 +2,9 @@ use async_trait::async_trait;
 use colored::*;
 
 use crate::actions::Runnable;
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
+use crate::error::Error;
 use crate::interpolator;
 
 #[derive(Clone)]
@@ -31,14 +32,15 @@ impl Runnable for Assert {
     _reports: &mut Reports,
     _pool: &Pool,
     config: &Config,
-  ) {
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
     let interpolator = interpolator::Interpolator::new(context);
     let eval = format!("{{{{ {} }}}}", &self.key);
 
     let lhs = &self.value;
-    let rhs = interpolator.resolve(&eval);
+    let rhs = interpolator.resolve(&eval)?;
 
-    if !config.quiet {
+    if config.output.steps() {
       println!(
         "{:width$} {}={}",
         self.name.green(),
@@ -48,52 +50,64 @@ impl Runnable for Assert {
       );
     }
 
-    if !eq(lhs, rhs.clone(), &interpolator) {
-      panic!("Assertion mismatched: {} != {}", lhs, rhs);
+    if !eq(lhs, rhs.clone(), &interpolator)? {
+      return Err(Error::AssertionMismatch {
+        expected: lhs.to_string(),
+        actual: rhs,
+      });
     }
 
-    if !config.quiet {
+    if config.output.steps() {
       println!("{:width$}", "Assertion successful".red(), width = 25);
     }
+
+    Ok(())
   }
 }
 
+/// Compares the expected value with the resolved one. Null never matches,
+/// and neither do values that don't parse as the expected type.
 fn eq(
   lhs: &serde_json::Value,
   rhs: String,
   interpolator: &interpolator::Interpolator,
-) -> bool {
-  match lhs {
-    serde_json::Value::Null => panic!("Can't compare null values!"),
-    serde_json::Value::Bool(b) => b.eq(&rhs.parse::<bool>().unwrap()),
+) -> Result<bool, Error> {
+  Ok(match lhs {
+    serde_json::Value::Null => false,
+    serde_json::Value::Bool(b) => rhs.parse::<bool>().is_ok_and(|r| *b == r),
     serde_json::Value::Number(n) => {
-      n.as_f64().unwrap().eq(&rhs.parse::<f64>().unwrap())
+      rhs.parse::<f64>().is_ok_and(|r| n.as_f64() == Some(r))
     }
-    serde_json::Value::String(s) => interpolator.resolve(s).eq(&rhs),
+    serde_json::Value::String(s) => interpolator.resolve(s)?.eq(&rhs),
     serde_json::Value::Array(arr) => {
-      let deser_rhs = serde_json::from_str::<Vec<String>>(&rhs).unwrap();
-      arr
-        .iter()
-        .zip(deser_rhs)
-        .map(|(lhs, rhs)| eq(lhs, rhs, interpolator))
-        .all(|b| b)
+      match serde_json::from_str::<Vec<String>>(&rhs) {
+        Ok(deser_rhs) => {
+          for (lhs, rhs) in arr.iter().zip(deser_rhs) {
+            if !eq(lhs, rhs, interpolator)? {
+              return Ok(false);
+            }
+          }
+          true
+        }
+        Err(_) => false,
+      }
     }
     serde_json::Value::Object(ob) => {
-      let deser_rhs = serde_json::from_str::<
+      match serde_json::from_str::<
         serde_json::Map<String, serde_json::Value>,
       >(&rhs)
-      .unwrap();
-      ob.iter()
-        .zip(deser_rhs)
-        .map(|(lhs, rhs)| {
-          [
-            lhs.0.eq(&rhs.0),
-            eq(lhs.1, serde_json::to_string(&rhs.1).unwrap(), interpolator),
-          ]
-          .iter()
-          .all(|b| *b)
-        })
-        .all(|b| b)
+      {
+        Ok(deser_rhs) => {
+          for (lhs, rhs) in ob.iter().zip(deser_rhs) {
+            if !lhs.0.eq(&rhs.0) || !eq(lhs.1, rhs.1.to_string(), interpolator)?
+            {
+              return Ok(false);
+            }
+          }
+          true
+        }
+        Err(_) => false,
+      }
     }
-  }
+  })
 }
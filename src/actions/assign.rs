@@ -2,8 +2,9 @@ use async_trait::async_trait;
 use colored::*;
 
 use crate::actions::Runnable;
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
+use crate::error::Error;
 
 #[derive(Clone)]
 pub struct Assign {
@@ -30,17 +31,23 @@ impl Runnable for Assign {
     _reports: &mut Reports,
     _pool: &Pool,
     config: &Config,
-  ) {
-    if !config.quiet {
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    context.insert(self.key.to_owned(), self.value.to_owned());
+
+    if config.output.steps() {
+      let value = serde_json::to_string(&self.value).unwrap();
       println!(
         "{:width$} {}={}",
         self.name.green(),
         self.key.cyan().bold(),
-        serde_json::to_string(&self.value).unwrap().magenta(),
+        config
+          .redact_values(&value, &config.redacted_values(context))
+          .magenta(),
         width = 25
       );
     }
 
-    context.insert(self.key.to_owned(), self.value.to_owned());
+    Ok(())
   }
 }
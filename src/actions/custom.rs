@@ -0,0 +1,129 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use async_trait::async_trait;
+use colored::*;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use super::plugin::{StepRequest, StepResponse};
+use crate::actions::Runnable;
+use crate::benchmark::{Context, Pool, Reports, Rng};
+use crate::config::Config;
+use crate::error::Error;
+
+/// Step handled by an external program. Programs are started once and kept
+/// running, reading one JSON request per line from stdin and answering each
+/// with one JSON response line on stdout. Concurrent iterations get their
+/// own process, reused afterwards.
+#[derive(Clone)]
+pub struct Custom {
+  name: String,
+  command: String,
+  args: Value,
+  idle: Arc<Mutex<Vec<Worker>>>,
+}
+
+struct Worker {
+  // Kept so the process is killed when the worker is dropped
+  _child: Child,
+  stdin: ChildStdin,
+  stdout: BufReader<ChildStdout>,
+}
+
+impl Worker {
+  fn spawn(command: &str, dir: &Path) -> Result<Worker, String> {
+    let mut child = Command::new("bash")
+      .args(["-c", "--", command])
+      .current_dir(dir)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .kill_on_drop(true)
+      .spawn()
+      .map_err(|err| err.to_string())?;
+
+    Ok(Worker {
+      stdin: child.stdin.take().unwrap(),
+      stdout: BufReader::new(child.stdout.take().unwrap()),
+      _child: child,
+    })
+  }
+
+  async fn exchange(
+    &mut self,
+    request: &[u8],
+  ) -> Result<StepResponse, String> {
+    self.stdin.write_all(request).await.map_err(|err| err.to_string())?;
+    self.stdin.write_all(b"\n").await.map_err(|err| err.to_string())?;
+    self.stdin.flush().await.map_err(|err| err.to_string())?;
+
+    let mut line = String::new();
+    let read =
+      self.stdout.read_line(&mut line).await.map_err(|err| err.to_string())?;
+    if read == 0 {
+      return Err("the process exited".to_owned());
+    }
+
+    serde_json::from_str(&line)
+      .map_err(|err| format!("invalid response {}: {}", line.trim_end(), err))
+  }
+}
+
+impl Custom {
+  pub fn new(name: String, command: String, args: Value) -> Self {
+    Self {
+      name,
+      command,
+      args,
+      idle: Arc::new(Mutex::new(Vec::new())),
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for Custom {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    if config.output.steps() {
+      println!(
+        "{:width$} {}",
+        self.name.green(),
+        self.command.cyan().bold(),
+        width = 25
+      );
+    }
+
+    let idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner).pop();
+    let mut worker = match idle {
+      Some(worker) => worker,
+      None => Worker::spawn(&self.command, &config.dir)
+        .map_err(|err| Error::step(&self.name, err))?,
+    };
+
+    let request = serde_json::to_vec(&StepRequest {
+      name: &self.name,
+      args: &self.args,
+      context,
+    })
+    .map_err(|err| Error::step(&self.name, err))?;
+
+    // A failed worker is dropped, killing its process
+    let response = worker
+      .exchange(&request)
+      .await
+      .map_err(|err| Error::step(&self.name, err))?;
+    self.idle.lock().unwrap_or_else(PoisonError::into_inner).push(worker);
+
+    response.apply(&self.name, context, reports, config);
+
+    Ok(())
+  }
+}
@@ -1,15 +1,16 @@
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
 use crate::db::DB;
+use crate::error::Error;
 use crate::interpolator;
 use crate::parse::WithItems;
 use async_trait::async_trait;
 use colored::Colorize;
-use serde::ser::{SerializeMap, SerializeSeq};
+use futures::TryStreamExt;
+use serde::ser::{Error as _, SerializeMap};
 use serde::Serialize;
-use serde_json::json;
 use sqlx::postgres::PgRow;
-use sqlx::{Column, Executor, PgPool, Row, ValueRef};
+use sqlx::{Column, Executor, Row, ValueRef};
 
 use super::Runnable;
 
@@ -19,6 +20,7 @@ pub struct DbQuery {
   assign: Option<String>,
   target: String,
   query: String,
+  max_rows: Option<usize>,
 }
 
 impl DbQuery {
@@ -28,12 +30,14 @@ impl DbQuery {
     target: String,
     query: String,
     _with_items: Option<WithItems>,
+    max_rows: Option<usize>,
   ) -> Self {
     Self {
       name,
       target,
       query,
       assign,
+      max_rows,
     }
   }
 }
@@ -46,14 +50,15 @@ impl Runnable for DbQuery {
     _reports: &mut Reports,
     _pool: &Pool,
     config: &Config,
-  ) {
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
     let interpolator = interpolator::Interpolator::new(context);
     let db = config
       .dbs
       .get(&self.target)
-      .unwrap_or_else(|| panic!("No such DB: {}", self.target))
-      .to_db(&interpolator);
-    if !config.quiet {
+      .ok_or_else(|| Error::UnknownDatabase(self.target.clone()))?
+      .to_db(&interpolator)?;
+    if config.output.steps() {
       println!(
         "{:width$} {} <= {}...",
         self.name.green(),
@@ -71,44 +76,37 @@ impl Runnable for DbQuery {
       );
     }
 
-    let final_query = interpolator.resolve(&self.query);
+    let final_query = interpolator.resolve(&self.query)?;
 
-    let results = match db {
-      DB::Postgres(pool) => QueryResults::Postgres(
-        execute_postgres_query(&final_query, &pool).await,
-      ),
-    };
+    // Rows are serialized as they are fetched, and the query is dropped
+    // once `max_rows` of them were read
+    let max_rows = self.max_rows.unwrap_or(usize::MAX);
+    let mut fetched = 0;
+    let mut rows = Vec::new();
+    match db {
+      DB::Postgres(pool) => {
+        let mut stream = pool.fetch(final_query.as_str());
+        while fetched < max_rows {
+          let row = match stream.try_next().await {
+            Ok(Some(row)) => row,
+            Ok(None) => break,
+            Err(err) => return Err(Error::step(&self.name, err)),
+          };
+          fetched += 1;
+          if self.assign.is_some() {
+            let value = serde_json::to_value(PostgresRow(&row))
+              .map_err(|err| Error::step(&self.name, err))?;
+            rows.push(value);
+          }
+        }
+      }
+    }
 
     if let Some(key) = &self.assign {
-      context.insert(key.to_owned(), json!(results));
+      context.insert(key.to_owned(), serde_json::Value::Array(rows));
     }
-  }
-}
-
-async fn execute_postgres_query(query: &str, pool: &PgPool) -> Vec<PgRow> {
-  pool.fetch_all(query).await.unwrap_or_else(|_| {
-    panic!("Query execution failed ({})", query.split_at(10).0)
-  })
-}
-
-pub enum QueryResults {
-  Postgres(Vec<PgRow>),
-}
 
-impl Serialize for QueryResults {
-  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-  where
-    S: serde::Serializer,
-  {
-    match self {
-      QueryResults::Postgres(v) => {
-        let mut seq = serializer.serialize_seq(Some(v.len()))?;
-        for e in v {
-          seq.serialize_element(&PostgresRow(e))?;
-        }
-        seq.end()
-      }
-    }
+    Ok(())
   }
 }
 
@@ -123,17 +121,14 @@ impl<'a> Serialize for PostgresRow<'a> {
     let mut map = serializer.serialize_map(Some(columns_len))?;
     for col in 0..columns_len {
       let key = self.0.column(col).name();
-      let val = self
-        .0
-        .try_get_raw(col)
-        .map(|val| {
-          if val.is_null() {
-            "null"
-          } else {
-            val.as_str().unwrap()
-          }
-        })
-        .unwrap_or_else(|_| panic!("Failed to get value from column {}", col));
+      let raw = self.0.try_get_raw(col).map_err(S::Error::custom)?;
+      let val = if raw.is_null() {
+        "null"
+      } else {
+        raw.as_str().map_err(|_| {
+          S::Error::custom(format!("Failed to get value from column {col}"))
+        })?
+      };
       map.serialize_entry(key, val)?;
     }
     map.end()
@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use colored::*;
+use serde_json::{json, Map, Value};
+
+use crate::actions::Runnable;
+use crate::benchmark::{Context, Pool, Reports, Rng};
+use crate::config::Config;
+use crate::error::Error;
+
+/// Prints context keys at its point of the plan, with the secrets and the
+/// `redact` values hidden
+#[derive(Clone)]
+pub struct Debug {
+  name: String,
+  keys: Vec<String>,
+}
+
+impl Debug {
+  pub fn new(name: String, keys: Vec<String>) -> Self {
+    Self {
+      name,
+      keys,
+    }
+  }
+
+  /// Values of the keys found in the context, the whole context without keys,
+  /// along with the keys that weren't found
+  fn select(&self, context: &Context) -> (Value, Vec<&str>) {
+    if self.keys.is_empty() {
+      return (json!(context), Vec::new());
+    }
+
+    let context = json!(context);
+    let mut selected = Map::new();
+    let mut missing = Vec::new();
+    for key in &self.keys {
+      // Same paths as interpolations, like `user.roles[0]`
+      let pointer =
+        format!("/{}", key.replace(['.', '['], "/").replace(']', ""));
+      match context.pointer(&pointer) {
+        Some(value) => {
+          selected.insert(key.to_owned(), value.clone());
+        }
+        None => missing.push(key.as_str()),
+      }
+    }
+    (Value::Object(selected), missing)
+  }
+}
+
+#[async_trait]
+impl Runnable for Debug {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    _reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    if !config.output.steps() {
+      return Ok(());
+    }
+
+    let (selected, missing) = self.select(context);
+    let dump = serde_json::to_string_pretty(&selected).unwrap_or_default();
+    let dump = config.redact_values(&dump, &config.redacted_values(context));
+
+    println!(
+      "{:width$} {}",
+      self.name.green(),
+      "debug".cyan().bold(),
+      width = 25
+    );
+    println!("{dump}");
+    if !missing.is_empty() {
+      println!("{} {}", "missing:".yellow(), missing.join(", "));
+    }
+
+    Ok(())
+  }
+}
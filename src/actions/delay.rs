@@ -1,24 +1,52 @@
+use std::f64::consts::TAU;
+
 use async_trait::async_trait;
 use colored::*;
+use rand::Rng as _;
 use tokio::time::sleep;
 
 use crate::actions::Runnable;
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
+use crate::error::Error;
+use crate::parse::DelaySpec;
 
 use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Delay {
   name: String,
-  seconds: u64,
+  delay: DelaySpec,
 }
 
 impl Delay {
-  pub fn new(name: String, seconds: u64) -> Self {
+  pub fn new(name: String, delay: DelaySpec) -> Self {
     Self {
       name,
-      seconds,
+      delay,
+    }
+  }
+
+  /// Draws the duration of this execution, out of the iteration generator
+  /// so seeded runs wait the same
+  fn duration(&self, rng: &mut Rng) -> Duration {
+    match self.delay {
+      DelaySpec::Fixed(duration) => duration,
+      DelaySpec::Uniform {
+        min,
+        max,
+      } => rng.gen_range(min..=max),
+      DelaySpec::Normal {
+        mean,
+        stddev,
+      } => {
+        // Box-Muller transform, negative draws are clamped to no delay
+        let u1: f64 = 1.0 - rng.gen::<f64>();
+        let u2: f64 = rng.gen();
+        let z = (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos();
+        let seconds = mean.as_secs_f64() + z * stddev.as_secs_f64();
+        Duration::from_secs_f64(seconds.max(0.0))
+      }
     }
   }
 }
@@ -31,17 +59,24 @@ impl Runnable for Delay {
     _reports: &mut Reports,
     _pool: &Pool,
     config: &Config,
-  ) {
-    sleep(Duration::from_secs(self.seconds)).await;
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let duration = self.duration(rng);
+    sleep(duration).await;
 
-    if !config.quiet {
+    if config.output.steps() {
+      let waited = match self.delay {
+        DelaySpec::Fixed(_) => duration.as_secs_f64().to_string() + "s",
+        _ => config.time_unit.format(duration.as_secs_f64() * 1_000.0),
+      };
       println!(
-        "{:width$} {}{}",
+        "{:width$} {}",
         self.name.green(),
-        self.seconds.to_string().cyan().bold(),
-        "s".magenta(),
+        waited.cyan().bold(),
         width = 25
       );
     }
+
+    Ok(())
   }
 }
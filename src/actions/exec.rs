@@ -1,28 +1,127 @@
 use async_trait::async_trait;
 use colored::*;
-use serde_json::json;
-use std::process::Command;
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::timeout;
 
 use crate::actions::Runnable;
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
-use crate::interpolator;
+use crate::error::Error;
+use crate::interpolator::{Interpolator, INTERPOLATION_REGEX};
+use crate::parse::Shell;
 
 #[derive(Clone)]
 pub struct Exec {
   name: String,
   command: String,
   pub assign: Option<String>,
+  timeout: Option<Duration>,
+  detach: bool,
+  fail_on_nonzero: bool,
+  shell: Shell,
+}
+
+#[derive(Serialize)]
+struct AssignedExec {
+  /// Missing when the command was killed by a signal
+  exit_code: Option<i32>,
+  stdout: String,
+  stderr: String,
 }
 
 impl Exec {
-  pub fn new(name: String, assign: Option<String>, command: String) -> Self {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    name: String,
+    assign: Option<String>,
+    command: String,
+    timeout: Option<Duration>,
+    detach: bool,
+    fail_on_nonzero: bool,
+    shell: Shell,
+  ) -> Self {
     Self {
       name,
       command,
       assign,
+      timeout,
+      detach,
+      fail_on_nonzero,
+      shell,
     }
   }
+
+  /// Builds the command line for the configured shell. Without a shell, the
+  /// command is split into words before interpolating them, so values with
+  /// spaces stay single arguments.
+  fn build_command(&self, context: &Context) -> Result<Command, Error> {
+    let interpolator = Interpolator::new(context);
+    let (program, args) = match invocation(self.shell) {
+      Some((program, flags)) => {
+        let mut args: Vec<String> =
+          flags.iter().map(|flag| flag.to_string()).collect();
+        args.push(interpolator.resolve(&self.command)?);
+        (program.to_owned(), args)
+      }
+      None => {
+        // Interpolations lose their inner spaces so each stays in one word
+        let command = INTERPOLATION_REGEX.replace_all(&self.command, "{{$1}}");
+        let words = shlex::split(&command)
+          .filter(|words| !words.is_empty())
+          .ok_or_else(|| Error::step(&self.name, "invalid command line"))?;
+        let mut words = words
+          .iter()
+          .map(|word| interpolator.resolve(word))
+          .collect::<Result<Vec<_>, _>>()?;
+        let program = words.remove(0);
+        (program, words)
+      }
+    };
+
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+  }
+
+  /// Runs the command in the background, discarding its output. It is
+  /// killed once the timeout expires, but otherwise may outlive the run.
+  fn spawn(&self, mut command: Command) -> Result<(), Error> {
+    let mut child = command
+      .stdout(Stdio::null())
+      .spawn()
+      .map_err(|err| Error::step(&self.name, err))?;
+    let limit = self.timeout;
+
+    tokio::spawn(async move {
+      match limit {
+        Some(duration) => {
+          if timeout(duration, child.wait()).await.is_err() {
+            let _ = child.kill().await;
+          }
+        }
+        None => {
+          let _ = child.wait().await;
+        }
+      }
+    });
+
+    Ok(())
+  }
+}
+
+/// Program and flags running a command line with the given shell
+fn invocation(shell: Shell) -> Option<(&'static str, &'static [&'static str])> {
+  match shell {
+    Shell::Sh => Some(("sh", &["-c"])),
+    Shell::Bash => Some(("bash", &["-c", "--"])),
+    Shell::Pwsh => {
+      Some(("pwsh", &["-NoProfile", "-NonInteractive", "-Command"]))
+    }
+    Shell::None => None,
+  }
 }
 
 #[async_trait]
@@ -33,8 +132,9 @@ impl Runnable for Exec {
     _reports: &mut Reports,
     _pool: &Pool,
     config: &Config,
-  ) {
-    if !config.quiet {
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    if config.output.steps() {
       println!(
         "{:width$} {}",
         self.name.green(),
@@ -43,19 +143,45 @@ impl Runnable for Exec {
       );
     }
 
-    let final_command =
-      interpolator::Interpolator::new(context).resolve(&self.command);
+    let mut command = self.build_command(context)?;
+    command.current_dir(&config.dir);
 
-    let args = ["bash", "-c", "--", final_command.as_str()];
+    if self.detach {
+      return self.spawn(command);
+    }
 
-    let execution =
-      Command::new(args[0]).args(&args[1..]).output().expect("Couldn't run it");
+    // Dropping the output future on timeout kills the command
+    command.kill_on_drop(true);
 
-    let output = String::from_utf8_lossy(&execution.stdout);
-    let output = output.trim_end();
+    let output = command.output();
+    let execution = match self.timeout {
+      Some(duration) => timeout(duration, output).await.map_err(|_| {
+        let seconds = duration.as_secs_f64();
+        Error::step(&self.name, format!("timed out after {seconds}s"))
+      })?,
+      None => output.await,
+    }
+    .map_err(|err| Error::step(&self.name, err))?;
+
+    let assigned = AssignedExec {
+      exit_code: execution.status.code(),
+      stdout: String::from_utf8_lossy(&execution.stdout).trim_end().to_owned(),
+      stderr: String::from_utf8_lossy(&execution.stderr).trim_end().to_owned(),
+    };
+
+    if self.fail_on_nonzero && !execution.status.success() {
+      let reason = match assigned.stderr.as_str() {
+        "" => execution.status.to_string(),
+        stderr => format!("{}: {}", execution.status, stderr),
+      };
+      return Err(Error::step(&self.name, reason));
+    }
 
     if let Some(key) = &self.assign {
-      context.insert(key.to_owned(), json!(output));
+      let value = serde_json::to_value(assigned).unwrap();
+      context.insert(key.to_owned(), value);
     }
+
+    Ok(())
   }
 }
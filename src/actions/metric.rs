@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::actions::{Report, Runnable};
+use crate::benchmark::{Context, Pool, Reports, Rng};
+use crate::config::Config;
+use crate::error::Error;
+use crate::interpolator;
+use crate::parse::MetricKind;
+
+/// Value recorded for a custom metric, carried by its report
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct MetricSample {
+  pub kind: MetricKind,
+  pub value: f64,
+}
+
+/// Records a sample of a custom metric, computed from the context
+#[derive(Clone)]
+pub struct Metric {
+  name: String,
+  metric: String,
+  kind: MetricKind,
+  value: Value,
+}
+
+impl Metric {
+  pub fn new(
+    name: String,
+    metric: String,
+    kind: MetricKind,
+    value: Value,
+  ) -> Self {
+    Self {
+      name,
+      metric,
+      kind,
+      value,
+    }
+  }
+
+  fn resolve_value(&self, context: &Context) -> Result<f64, Error> {
+    let value = match &self.value {
+      Value::String(value) => {
+        interpolator::Interpolator::new(context).resolve(value)?
+      }
+      value => value.to_string(),
+    };
+    match value.trim() {
+      "true" => Ok(1.0),
+      "false" => Ok(0.0),
+      number => number.parse().map_err(|_| {
+        let message = format!("metric value '{value}' isn't a number");
+        Error::step(&self.name, message)
+      }),
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for Metric {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let value = self.resolve_value(context)?;
+
+    if config.output.steps() {
+      println!(
+        "{:width$} {}={}",
+        self.name.green(),
+        self.metric.cyan().bold(),
+        value.to_string().magenta(),
+        width = 25
+      );
+    }
+
+    reports.push(Report::metric(self.metric.clone(), self.kind, value));
+
+    Ok(())
+  }
+}
@@ -1,37 +1,69 @@
 use async_trait::async_trait;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 mod assert;
 mod assign;
+mod custom;
 mod db_query;
+mod debug;
 mod delay;
 mod exec;
+mod metric;
+mod on_error;
+mod plugin;
 mod request;
+mod script;
+mod section;
+mod shared;
+mod source;
+mod template;
+mod transaction;
+mod when;
 
 pub use self::assert::Assert;
 pub use self::assign::Assign;
+pub use self::custom::Custom;
 pub use self::db_query::DbQuery;
+pub use self::debug::Debug;
 pub use self::delay::Delay;
 pub use self::exec::Exec;
-pub use self::request::Request;
+pub use self::metric::{Metric, MetricSample};
+pub use self::on_error::OnError;
+pub use self::plugin::Plugin;
+pub use self::request::{
+  fill_path_params, prewarm_connections, sni_address, Hop, Request,
+};
+pub use self::script::Script;
+pub use self::section::Section;
+pub use self::shared::{CounterInc, SharedSet};
+pub use self::source::Source;
+pub use self::template::Template;
+pub use self::transaction::Transaction;
+pub use self::when::When;
 
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
+use crate::error::Error;
+use crate::parse::MetricKind;
 
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[async_trait]
 pub trait Runnable {
+  /// Runs the step. An error aborts the rest of the iteration.
   async fn execute(
     &self,
     context: &mut Context,
     reports: &mut Reports,
     pool: &Pool,
     config: &Config,
-  );
+    rng: &mut Rng,
+  ) -> Result<(), Error>;
 }
 
 #[derive(Deserialize)]
+#[allow(dead_code)]
 pub enum WithOps {
   #[serde(rename(deserialize = "with_items"))]
   Items,
@@ -51,25 +83,93 @@ impl From<&str> for WithOps {
   }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Report {
   pub name: String,
+  /// Unix time, in seconds, the step started at
+  #[serde(default)]
+  pub started_at: f64,
   pub duration: f64,
+  /// Iteration the step ran in
+  #[serde(default)]
+  pub iteration: u64,
+  #[serde(default)]
   pub status: u16,
+  /// Whether the status is one of the `success_status`
+  #[serde(default)]
+  pub success: bool,
+  /// Whether the status is one of the `expected_failures` instead
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub expected_failure: bool,
+  /// Redirects followed on the way, with `capture_redirects: report`
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub redirects: Vec<Hop>,
+  /// Combined report of the steps of a `transaction`
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub transaction: bool,
+  /// Id sent in the `request_id` header, to find the request in server logs
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
+  /// Included file the step comes from, see `Source`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub source: Option<String>,
+  /// Whether the request took longer than `--slow-threshold`
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub slow: bool,
+  /// Whether the iteration of the step was retried, see `iteration_retries`
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub retried: bool,
+  /// Whether the step ran in a failed attempt its iteration was retried
+  /// after, left out of the stats
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub superseded: bool,
+  /// Sample of a custom metric, named after it, rather than of a step
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub metric: Option<MetricSample>,
 }
 
-impl fmt::Debug for Report {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "\n- name: {}\n  duration: {}\n", self.name, self.duration)
+impl Report {
+  /// Report of a step that just finished, after `duration` ms
+  pub fn new(name: String, duration: f64, status: u16, success: bool) -> Self {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    Report {
+      name,
+      started_at: now.as_secs_f64() - duration / 1_000.0,
+      duration,
+      iteration: 0,
+      status,
+      success,
+      expected_failure: false,
+      redirects: Vec::new(),
+      transaction: false,
+      request_id: None,
+      source: None,
+      slow: false,
+      retried: false,
+      superseded: false,
+      metric: None,
+    }
+  }
+
+  /// Sample of the custom metric `name`
+  pub fn metric(name: String, kind: MetricKind, value: f64) -> Self {
+    let mut report = Report::new(name, 0.0, 0, true);
+    report.metric = Some(MetricSample {
+      kind,
+      value,
+    });
+    report
+  }
+
+  /// Whether the step failed, the `expected_failures` aside
+  pub fn failed(&self) -> bool {
+    !self.success && !self.expected_failure
   }
 }
 
-impl fmt::Display for Report {
+impl fmt::Debug for Report {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(
-      f,
-      "\n- name: {}\n  duration: {}\n  status: {}\n",
-      self.name, self.duration, self.status
-    )
+    write!(f, "\n- name: {}\n  duration: {}\n", self.name, self.duration)
   }
 }
+
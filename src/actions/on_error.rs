@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+use colored::*;
+
+use crate::actions::Runnable;
+use crate::benchmark::{run_steps, Context, Pool, Reports, Rng, Runner, Steps};
+use crate::config::Config;
+use crate::error::Error;
+use crate::parse::Recovery;
+
+/// Runs recovery steps when the wrapped step fails, either with an error or
+/// with a non-2xx response, and then aborts, continues or retries
+pub struct OnError {
+  name: String,
+  step: Runner,
+  steps: Steps,
+  then: Recovery,
+}
+
+impl OnError {
+  pub fn new(name: String, step: Runner, steps: Steps, then: Recovery) -> Self {
+    Self {
+      name,
+      step,
+      steps,
+      then,
+    }
+  }
+
+  async fn attempt(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let first_report = reports.len();
+    self.step.execute(context, reports, pool, config, rng).await?;
+
+    match reports[first_report..].iter().find(|r| r.failed()) {
+      Some(report) => Err(Error::step(
+        &self.name,
+        format!("responded with status {}", report.status),
+      )),
+      None => Ok(()),
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for OnError {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let err = match self.attempt(context, reports, pool, config, rng).await {
+      Ok(()) => return Ok(()),
+      Err(err) => err,
+    };
+
+    if config.output.errors() {
+      println!(
+        "{:width$} {}",
+        self.name.green(),
+        config.redact(&format!("{err}, recovering")).yellow(),
+        width = 25
+      );
+    }
+    run_steps(&self.steps, context, reports, pool, config, rng).await?;
+
+    match self.then {
+      Recovery::Abort => Err(err),
+      Recovery::Continue => Ok(()),
+      Recovery::Retry => {
+        self.attempt(context, reports, pool, config, rng).await
+      }
+    }
+  }
+}
@@ -0,0 +1,124 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::actions::{Report, Runnable};
+use crate::benchmark::{Context, Pool, Reports, Rng};
+use crate::config::Config;
+use crate::error::Error;
+
+#[derive(Clone)]
+pub struct Plugin {
+  name: String,
+  plugin: String,
+  args: Value,
+}
+
+/// Document sent to plugins and custom steps on every execution
+#[derive(Serialize)]
+pub(super) struct StepRequest<'a> {
+  pub name: &'a str,
+  pub args: &'a Value,
+  pub context: &'a Context,
+}
+
+/// Document plugins and custom steps answer with
+#[derive(Deserialize)]
+pub(super) struct StepResponse {
+  #[serde(default)]
+  context: Map<String, Value>,
+  #[serde(default)]
+  reports: Vec<StepReport>,
+}
+
+#[derive(Deserialize)]
+struct StepReport {
+  name: Option<String>,
+  #[serde(default)]
+  duration: f64,
+  #[serde(default = "default_status")]
+  status: u16,
+}
+
+fn default_status() -> u16 {
+  200
+}
+
+impl StepResponse {
+  /// Sets the returned keys in the context and records the reports
+  pub fn apply(
+    self,
+    name: &str,
+    context: &mut Context,
+    reports: &mut Reports,
+    config: &Config,
+  ) {
+    context.extend(self.context);
+    reports.extend(self.reports.into_iter().map(|report| {
+      let name = report.name.unwrap_or_else(|| name.to_owned());
+      let success = config.success_status.matches(report.status);
+      Report::new(name, report.duration, report.status, success)
+    }));
+  }
+}
+
+impl Plugin {
+  pub fn new(name: String, plugin: String, args: Value) -> Self {
+    Self {
+      name,
+      plugin,
+      args,
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for Plugin {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let plugin = config.plugins.get(&self.plugin).ok_or_else(|| {
+      Error::step(&self.name, format!("unknown plugin '{}'", self.plugin))
+    })?;
+
+    let request = serde_json::to_vec(&StepRequest {
+      name: &self.name,
+      args: &self.args,
+      context,
+    })
+    .map_err(|err| Error::step(&self.name, err))?;
+
+    let begin = Instant::now();
+    let response = plugin.call(&request).and_then(|response| {
+      serde_json::from_slice::<StepResponse>(&response)
+        .map_err(|err| format!("invalid response: {err}"))
+    });
+    let duration_ms = begin.elapsed().as_secs_f64() * 1000.0;
+
+    let response = response.map_err(|err| {
+      Error::step(&self.name, format!("plugin '{}': {}", self.plugin, err))
+    })?;
+
+    if config.output.steps() {
+      println!(
+        "{:width$} {} {}",
+        self.name.green(),
+        self.plugin.cyan().bold(),
+        format!("{}ms", duration_ms.round()).cyan(),
+        width = 25
+      );
+    }
+
+    response.apply(&self.name, context, reports, config);
+
+    Ok(())
+  }
+}
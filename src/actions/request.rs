@@ -1,30 +1,50 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::str::FromStr;
-use std::time::{Duration, Instant};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use colored::Colorize;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::Rng as _;
 use reqwest::{
   header::{self, HeaderMap, HeaderName, HeaderValue},
-  ClientBuilder, Method, Response,
+  redirect, Client, ClientBuilder, Method, Response, StatusCode,
 };
 use std::fmt::Write;
-use url::Url;
+use url::{Host, Url};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
 use serde_json::{json, Map, Value};
 
-use crate::benchmark::{Context, Pool, Reports};
+use crate::benchmark::{Context, Pool, Reports, Rng};
 use crate::config::Config;
-use crate::interpolator;
-use crate::parse::{Pick, WithItems};
+use crate::dns::Resolver;
+use crate::error::Error;
+use crate::writer::{self, RequestLogLine};
+use crate::interpolator::{self, INTERPOLATION_REGEX};
+use crate::extract::{self, Paths};
+use crate::parse::{
+  AssignOnly, Download, Pick, RedirectCapture, Resample, Strategy,
+  SuccessStatus, WithItems,
+};
 
 use crate::actions::{Report, Runnable};
 
 static USER_AGENT: &str = "drill";
+// Same limit as the client when it follows them itself
+const MAX_REDIRECTS: usize = 10;
+// Reported for the responses aborted past `max_response_bytes`, out of the
+// statuses servers actually send
+const RESPONSE_TOO_LARGE_STATUS: u16 = 599;
+
+/// Items drawn `per_iteration`, with the iteration, by worker slot. Slots
+/// are bounded by the concurrency and run one iteration at a time.
+type DrawnItems = HashMap<u64, (u64, Vec<usize>)>;
 
 #[derive(Clone)]
 #[allow(dead_code)]
@@ -32,21 +52,93 @@ pub struct Request {
   name: String,
   base: Option<String>,
   url: String,
+  path_params: HashMap<String, String>,
+  sni: Option<String>,
   _time: f64,
   method: String,
   headers: HashMap<String, String>,
-  body: Option<String>,
-  with_items: Option<Vec<serde_yaml::Value>>,
+  body: Option<RequestBody>,
+  discard_body: bool,
+  max_body_bytes: Option<usize>,
+  max_response_bytes: Option<usize>,
+  success_status: Option<SuccessStatus>,
+  expected_failures: Option<SuccessStatus>,
+  expect_content_type: Option<String>,
+  capture_redirects: Option<RedirectCapture>,
+  download: Option<Download>,
+  /// Paths of the JSON body kept by `assign_only`
+  body_paths: Option<Arc<Paths>>,
+  /// Headers kept by `assign_only`
+  kept_headers: Option<Vec<String>>,
+  /// Shared by every iteration, which only pick indices out of it
+  with_items: Option<Arc<[serde_yaml::Value]>>,
   shuffle: Option<bool>,
   pick: Option<Pick>,
+  strategy: Option<Strategy>,
+  resample: Option<Resample>,
+  cursor: Arc<AtomicUsize>,
+  drawn: Arc<Mutex<DrawnItems>>,
   assign: Option<String>,
 }
 
+/// Outcome of sending a request, without a response when it couldn't be
+/// sent or answered
+struct Sent {
+  response: Option<Response>,
+  duration_ms: f64,
+  hops: Vec<Hop>,
+  /// Url the request was sent to, after interpolation
+  url: String,
+  error: Option<String>,
+  /// Values of the redacted context keys and headers, see `redact`
+  hidden: Vec<String>,
+}
+
+/// Bodies without interpolations are built once and shared by every request,
+/// files are streamed from disk on every request
+#[derive(Clone)]
+enum RequestBody {
+  Template(String),
+  Static(Bytes),
+  File(String),
+}
+
+/// What became of the body of a response
+enum Body {
+  /// Left unread, the connection being dropped
+  Discarded,
+  Read(String),
+  /// Paths kept by `assign_only`, along with the size of the body
+  Extracted(Value, usize),
+  Downloaded(DownloadStats),
+}
+
 #[derive(Serialize, Deserialize)]
 struct AssignedRequest {
   status: u16,
   body: Value,
   headers: Map<String, Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  redirects: Option<Vec<Hop>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  download: Option<DownloadStats>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DownloadStats {
+  path: String,
+  bytes: usize,
+  /// Bytes per second, from the first byte of the body to the last
+  rate: f64,
+}
+
+/// Redirect answered on the way to the final response of a request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hop {
+  pub url: String,
+  pub status: u16,
+  /// Milliseconds the redirect took to be answered
+  pub duration: f64,
 }
 
 impl Request {
@@ -55,38 +147,307 @@ impl Request {
     name: String,
     base: Option<String>,
     url: String,
+    path_params: HashMap<String, String>,
+    sni: Option<String>,
     _time: f64,
     method: String,
     headers: HashMap<String, String>,
     body: Option<String>,
+    body_file: Option<String>,
+    discard_body: bool,
+    max_body_bytes: Option<usize>,
+    max_response_bytes: Option<usize>,
+    success_status: Option<SuccessStatus>,
+    expected_failures: Option<SuccessStatus>,
+    expect_content_type: Option<String>,
+    capture_redirects: Option<RedirectCapture>,
+    download: Option<Download>,
+    assign_only: Option<AssignOnly>,
     with_items: Option<WithItems>,
     assign: Option<String>,
   ) -> Self {
+    let body = match (body, body_file) {
+      (_, Some(path)) => Some(RequestBody::File(path)),
+      (Some(body), None) if INTERPOLATION_REGEX.is_match(&body) => {
+        Some(RequestBody::Template(body))
+      }
+      (Some(body), None) => Some(RequestBody::Static(Bytes::from(body))),
+      (None, None) => None,
+    };
     let shuffle = with_items.as_ref().map(|wi| wi.shuffle);
     let pick = with_items.as_ref().map(|wi| wi.pick);
-    let with_items = with_items.map(|wi| wi.items);
+    let strategy = with_items.as_ref().and_then(|wi| wi.strategy);
+    let resample = with_items.as_ref().map(|wi| wi.resample);
+    let with_items = with_items.map(|wi| Arc::from(wi.items));
+    let body_paths =
+      assign_only.as_ref().map(|only| Arc::new(Paths::new(&only.body)));
+    let kept_headers = assign_only.map(|only| only.headers);
 
     Self {
       name,
       base,
       url,
+      path_params,
+      sni,
       _time,
       method,
       headers,
       body,
+      discard_body,
+      max_body_bytes,
+      max_response_bytes,
+      success_status,
+      expected_failures,
+      expect_content_type,
+      capture_redirects,
+      download,
+      body_paths,
+      kept_headers,
       with_items,
       shuffle,
       pick,
+      strategy,
+      resample,
+      cursor: Arc::new(AtomicUsize::new(0)),
+      drawn: Arc::default(),
       assign,
     }
   }
 
-  fn format_time(tdiff: f64, nanosec: bool) -> String {
-    if nanosec {
-      (1_000_000.0 * tdiff).round().to_string() + "ns"
-    } else {
-      tdiff.round().to_string() + "ms"
+  /// Takes the indices of the items for one execution out of the data
+  /// source, following the given strategy. Returns `None` once a `once`
+  /// source is exhausted.
+  fn consume_items(
+    &self,
+    strategy: Strategy,
+    len: usize,
+    rng: &mut Rng,
+  ) -> Option<Vec<usize>> {
+    let count = self.pick.unwrap().count(len).max(1);
+    match strategy {
+      Strategy::Circular => {
+        let start = self.cursor.fetch_add(count, Ordering::SeqCst);
+        Some((start..start + count).map(|i| i % len).collect())
+      }
+      Strategy::Once => {
+        let start = self.cursor.fetch_add(count, Ordering::SeqCst);
+        if start >= len {
+          return None;
+        }
+        Some((start..(start + count).min(len)).collect())
+      }
+      Strategy::Random => {
+        Some((0..count).map(|_| rng.gen_range(0..len)).collect())
+      }
+    }
+  }
+
+  /// Draws the indices of the items for one execution, following the
+  /// strategy, shuffle and pick options. Returns `None` once a `once`
+  /// source is exhausted.
+  fn draw_items(&self, len: usize, rng: &mut Rng) -> Option<Vec<usize>> {
+    let mut indices = match self.strategy {
+      Some(strategy) => self.consume_items(strategy, len, rng)?,
+      None => (0..len).collect(),
+    };
+    if self.shuffle.unwrap() {
+      indices.shuffle(rng);
+    }
+    let take = match self.pick.unwrap().count(len) {
+      0 => indices.len(),
+      count => count,
+    };
+    indices.truncate(take);
+    Some(indices)
+  }
+
+  /// Draws the items once per iteration, reusing them when the request runs
+  /// again within the iteration. Draws are kept by worker slot, replacing
+  /// the one of the previous iteration of the slot.
+  fn draw_items_per_iteration(
+    &self,
+    context: &Context,
+    len: usize,
+    rng: &mut Rng,
+  ) -> Option<Vec<usize>> {
+    let number = |key: &str| context.get(key).and_then(Value::as_u64);
+    let worker = number("worker_id").unwrap_or_default();
+    let iteration = number("iteration").unwrap_or_default();
+
+    let mut drawn = self.drawn.lock().unwrap_or_else(PoisonError::into_inner);
+    match drawn.get(&worker) {
+      Some((drawn_in, indices)) if *drawn_in == iteration => {
+        Some(indices.clone())
+      }
+      _ => {
+        let indices = self.draw_items(len, rng)?;
+        drawn.insert(worker, (iteration, indices.clone()));
+        Some(indices)
+      }
+    }
+  }
+
+  /// Fails once `bytes` of the response have been received past
+  /// `max_response_bytes`
+  fn check_response_size(&self, bytes: usize) -> Result<(), Error> {
+    match self.max_response_bytes {
+      Some(limit) if bytes > limit => Err(Error::ResponseTooLarge {
+        step: self.name.clone(),
+        limit,
+      }),
+      _ => Ok(()),
+    }
+  }
+
+  /// Reads the response body, up to `max_body_bytes` when set
+  async fn read_body(&self, mut response: Response) -> Result<String, Error> {
+    let mut data = Vec::new();
+    while let Some(chunk) =
+      response.chunk().await.map_err(|err| Error::step(&self.name, err))?
+    {
+      self.check_response_size(data.len() + chunk.len())?;
+      match self.max_body_bytes {
+        Some(max) if data.len() + chunk.len() >= max => {
+          data.extend_from_slice(&chunk[..max - data.len()]);
+          break;
+        }
+        _ => data.extend_from_slice(&chunk),
+      }
+    }
+    Ok(String::from_utf8_lossy(&data).into_owned())
+  }
+
+  /// Streams the response body into a parser keeping only the `assign_only`
+  /// paths, so it's never buffered. Bodies that aren't JSON are null.
+  async fn extract_body(
+    &self,
+    mut response: Response,
+    paths: &Arc<Paths>,
+  ) -> Result<Body, Error> {
+    if paths.is_empty() {
+      return Ok(Body::Discarded);
+    }
+
+    let (chunks, parser) = extract::spawn_parser(paths.clone());
+    let mut bytes = 0;
+    while let Some(chunk) =
+      response.chunk().await.map_err(|err| Error::step(&self.name, err))?
+    {
+      bytes += chunk.len();
+      self.check_response_size(bytes)?;
+      // The parser gave up, the body isn't JSON
+      if chunks.send(chunk).await.is_err() {
+        break;
+      }
     }
+    drop(chunks);
+    let value = parser.await.ok().flatten().unwrap_or(Value::Null);
+    Ok(Body::Extracted(value, bytes))
+  }
+
+  /// Streams the response body into the `download` path, checking its
+  /// SHA-256 when one is expected
+  async fn download(
+    &self,
+    mut response: Response,
+    download: &Download,
+    context: &Context,
+    config: &Config,
+  ) -> Result<DownloadStats, Error> {
+    let interpolator = interpolator::Interpolator::new(context);
+    let path = interpolator.resolve(&download.path)?;
+    let io_error =
+      |err: std::io::Error| Error::step(&self.name, format!("{path}: {err}"));
+
+    let file = config.dir.join(&path);
+    let mut file = tokio::fs::File::create(file).await.map_err(io_error)?;
+    let mut hasher = download.sha256.as_ref().map(|_| Sha256::new());
+    let mut bytes = 0;
+    let begin = Instant::now();
+    while let Some(chunk) =
+      response.chunk().await.map_err(|err| Error::step(&self.name, err))?
+    {
+      self.check_response_size(bytes + chunk.len())?;
+      if let Some(hasher) = hasher.as_mut() {
+        hasher.update(&chunk);
+      }
+      file.write_all(&chunk).await.map_err(io_error)?;
+      bytes += chunk.len();
+    }
+    file.flush().await.map_err(io_error)?;
+    let rate = bytes as f64 / begin.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    if config.output.requests() {
+      println!(
+        "{:width$} {} {} at {}",
+        self.name.green(),
+        path.cyan(),
+        format!("{bytes} bytes").purple(),
+        format!("{:.2} MB/s", rate / 1_000_000.0).cyan(),
+        width = 25
+      );
+    }
+
+    if let (Some(expected), Some(hasher)) = (&download.sha256, hasher) {
+      let actual = format!("{:x}", hasher.finalize());
+      if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(Error::step(
+          &self.name,
+          format!("{path}: sha256 is {actual}, expected {expected}"),
+        ));
+      }
+    }
+
+    Ok(DownloadStats {
+      path,
+      bytes,
+      rate,
+    })
+  }
+
+  /// Resolves the url of the request, joining it to its base when it has
+  /// one, or to the benchmark `base_url` when it's relative
+  fn resolve_url(
+    &self,
+    context: &Context,
+    interpolator: &interpolator::Interpolator,
+    config: &Config,
+  ) -> Result<String, Error> {
+    let url = interpolator.resolve(&self.url)?;
+    let url = if self.path_params.is_empty() {
+      url
+    } else {
+      let mut params = HashMap::with_capacity(self.path_params.len());
+      for (name, value) in &self.path_params {
+        params.insert(name.as_str(), interpolator.resolve(value)?);
+      }
+      fill_path_params(&url, &params)
+    };
+    let base = match (self.base.as_ref(), config.base_url.as_ref()) {
+      (Some(base_url), _) => context
+        .get("urls")
+        .and_then(Value::as_object)
+        .and_then(|url_map| url_map.get(base_url))
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::UnknownBaseUrl {
+          step: self.name.clone(),
+          base: base_url.clone(),
+        })?,
+      (None, Some(default_base))
+        if Url::parse(&url) == Err(url::ParseError::RelativeUrlWithoutBase) =>
+      {
+        default_base
+      }
+      (None, _) => return Ok(url),
+    };
+    let base = interpolator.resolve(base)?;
+
+    let joined_url =
+      config.url_join.join(&base, &url).map_err(|err| Error::InvalidUrl {
+        url: config.redact(&format!("{base} + {url}")),
+        reason: err.to_string(),
+      })?;
+    Ok(joined_url.into())
   }
 
   async fn send_request(
@@ -95,66 +456,50 @@ impl Request {
     pool: &Pool,
     config: &Config,
     with_item: Option<&serde_yaml::Value>,
-  ) -> (Option<Response>, f64) {
+    request_id: Option<&str>,
+  ) -> Result<Sent, Error> {
     // Adding extra params as needed
     if let Some(val) = with_item {
-      let map = val.as_mapping().unwrap();
+      let map = val.as_mapping().ok_or_else(|| {
+        Error::step(&self.name, "with_items entries should be mappings")
+      })?;
       for (key, val) in map {
-        context.insert(
-          key.clone().as_str().unwrap().to_owned(),
-          serde_json::Value::String(val.clone().as_str().unwrap().to_owned()),
-        );
+        match (key.as_str(), val.as_str()) {
+          (Some(key), Some(val)) => {
+            context.insert(key.to_owned(), json!(val));
+          }
+          _ => {
+            return Err(Error::step(
+              &self.name,
+              "with_items entries should map names to strings",
+            ))
+          }
+        }
       }
     }
 
     let interpolator = interpolator::Interpolator::new(context);
 
     // Resolve relative urls
-    let interpolated_base_url = if let Some(base_url) = self.base.clone() {
-      match context.get("urls") {
-        Some(value) => {
-          if let Some(url_map) = value.as_object() {
-            let mut joined_url = PathBuf::from_str(
-              url_map
-                .get(&base_url)
-                .unwrap_or_else(|| {
-                  panic!("No such key in \"urls\" object: {}", base_url)
-                })
-                .as_str()
-                .unwrap(),
-            )
-            .unwrap();
-            joined_url.push(self.url.clone());
-            interpolator.resolve(joined_url.to_str().unwrap())
-          } else {
-            panic!(
-              "{} Wrong type for 'urls' variable.",
-              "ERROR:".yellow().bold()
-            );
-          }
-        }
-        _ => {
-          panic!(
-            "{} Request '{}' references a non-existent base url named '{}'",
-            "ERROR:".yellow().bold(),
-            self.name.green(),
-            base_url.magenta().bold()
-          );
-        }
-      }
-    } else {
-      interpolator.resolve(&self.url)
-    };
+    let interpolated_base_url =
+      self.resolve_url(context, &interpolator, config)?;
 
-    let url = Url::parse(&interpolated_base_url).expect("Invalid url");
-    let domain = format!(
-      "{}://{}:{}",
-      url.scheme(),
-      url.host_str().unwrap(),
-      url.port().unwrap_or(0)
-    ); // Unique domain key for keep-alive
+    let mut url =
+      Url::parse(&interpolated_base_url).map_err(|err| Error::InvalidUrl {
+        url: config.redact(&interpolated_base_url),
+        reason: err.to_string(),
+      })?;
 
-    let interpolated_body;
+    let mut route = Route {
+      local_address: config.local_address(self.base.as_deref()),
+      sni: None,
+    };
+    if let Some(sni) = &self.sni {
+      let sni = interpolator.resolve(sni)?;
+      let address = sni_address(&mut url, &sni)
+        .map_err(|err| Error::step(&self.name, err))?;
+      route.sni = Some((sni, address));
+    }
 
     // Method
     let method = match self.method.to_uppercase().as_ref() {
@@ -164,83 +509,135 @@ impl Request {
       "PATCH" => Method::PATCH,
       "DELETE" => Method::DELETE,
       "HEAD" => Method::HEAD,
-      _ => panic!("Unknown method '{}'", self.method),
+      _ => return Err(Error::UnknownMethod(self.method.clone())),
     };
 
     // Resolve the body
-    let (client, request) = {
-      let mut pool2 = pool.lock().unwrap();
-      let client = pool2.entry(domain).or_insert_with(|| {
-        ClientBuilder::default()
-          .danger_accept_invalid_certs(config.no_check_certificate)
-          .build()
-          .unwrap()
-      });
+    let interpolated_body = match self.body.as_ref() {
+      Some(RequestBody::Template(body)) => {
+        Some(reqwest::Body::from(interpolator.resolve(body)?))
+      }
+      Some(RequestBody::Static(body)) => {
+        Some(reqwest::Body::from(body.clone()))
+      }
+      Some(RequestBody::File(path)) => {
+        let file = tokio::fs::File::open(path)
+          .await
+          .map_err(|err| Error::step(&self.name, format!("{path}: {err}")))?;
+        Some(reqwest::Body::from(file))
+      }
+      None => None,
+    };
 
-      let request = if let Some(body) = self.body.as_ref() {
-        interpolated_body = interpolator.resolve(body);
+    let revalidates = config.conditional_requests
+      && matches!(method, Method::GET | Method::HEAD);
+    let follows = self.capture_redirects.is_none();
+    let client = pooled_client(pool, &url, config, follows, &route)
+      .map_err(|err| Error::step(&self.name, err))?;
 
-        client
-          .request(method, interpolated_base_url.as_str())
-          .body(interpolated_body)
-      } else {
-        client.request(method, interpolated_base_url.as_str())
-      };
-
-      (client.clone(), request)
+    let request = client.request(method, url);
+    let request = match interpolated_body {
+      Some(body) => request.body(body),
+      None => request,
     };
 
     // Headers
     let mut headers = HeaderMap::new();
-    headers
-      .insert(header::USER_AGENT, HeaderValue::from_str(USER_AGENT).unwrap());
+    headers.insert(header::USER_AGENT, HeaderValue::from_static(USER_AGENT));
 
-    if let Some(cookies) = context.get("cookies") {
-      let cookies: Map<String, Value> =
-        serde_json::from_value(cookies.clone()).unwrap();
+    if let Some(cookies) = context.get("cookies").and_then(Value::as_object) {
       let cookie = cookies
         .iter()
         .map(|(key, value)| format!("{key}={value}"))
         .collect::<Vec<_>>()
         .join(";");
 
-      headers.insert(header::COOKIE, HeaderValue::from_str(&cookie).unwrap());
+      let value =
+        HeaderValue::from_str(&cookie).map_err(|err| Error::InvalidHeader {
+          name: header::COOKIE.to_string(),
+          reason: err.to_string(),
+        })?;
+      headers.insert(header::COOKIE, value);
     }
 
-    // Resolve headers
-    for (key, val) in self.headers.iter() {
-      let interpolated_header = interpolator.resolve(val);
+    // Resolve headers, the request ones overriding the defaults
+    let default_headers = config.default_headers(self.base.as_deref());
+    for (key, val) in default_headers.iter().chain(self.headers.iter()) {
+      let interpolated_header = interpolator.resolve(val)?;
+      let invalid_header = |reason: String| Error::InvalidHeader {
+        name: key.clone(),
+        reason,
+      };
       headers.insert(
-        HeaderName::from_bytes(key.as_bytes()).unwrap(),
-        HeaderValue::from_str(&interpolated_header).unwrap(),
+        HeaderName::from_bytes(key.as_bytes())
+          .map_err(|err| invalid_header(err.to_string()))?,
+        HeaderValue::from_str(&interpolated_header)
+          .map_err(|err| invalid_header(err.to_string()))?,
       );
     }
 
+    if revalidates {
+      add_validators(context, &interpolated_base_url, &mut headers);
+    }
+    if let Some(trace_id) = context.get("trace_id").and_then(Value::as_str) {
+      add_trace_context(trace_id, &config.run_id, &mut headers);
+    }
+    if let (Some(name), Some(id)) = (&config.request_id, request_id) {
+      let name = HeaderName::from_bytes(name.as_bytes()).map_err(|err| {
+        Error::InvalidHeader {
+          name: name.clone(),
+          reason: err.to_string(),
+        }
+      })?;
+      headers.insert(name, HeaderValue::from_str(id).unwrap());
+    }
+
+    let mut hidden = config.redacted_values(context);
+    hidden.extend(redacted_headers(&headers, config));
+
     let request_builder =
-      request.headers(headers).timeout(Duration::from_secs(config.timeout));
-    let request = request_builder.build().expect("Cannot create request");
+      request.headers(headers).timeout(config.timeout);
+    let request = request_builder.build().map_err(|err| Error::InvalidUrl {
+      url: config.redact_values(&interpolated_base_url, &hidden),
+      reason: err.to_string(),
+    })?;
 
     if config.verbose {
-      log_request(&request);
+      log_request(&request, config, &hidden);
     }
 
     let begin = Instant::now();
-    let response_result = client.execute(request).await;
+    let (response_result, hops) = match self.capture_redirects {
+      Some(_) => follow_redirects(request, pool, config, &route).await,
+      None => (client.execute(request).await, Vec::new()),
+    };
     let duration_ms = begin.elapsed().as_secs_f64() * 1000.0;
 
     match response_result {
       Err(e) => {
-        if !config.quiet || config.verbose {
-          println!(
-            "Error connecting '{}': {:?}",
-            interpolated_base_url.as_str(),
-            e
-          );
+        if config.output.errors() || config.verbose {
+          let message =
+            format!("Error connecting '{}': {:?}", interpolated_base_url, e);
+          println!("{}", config.redact_values(&message, &hidden));
         }
-        (None, duration_ms)
+        Ok(Sent {
+          response: None,
+          duration_ms,
+          hops,
+          url: interpolated_base_url,
+          error: Some(e.to_string()),
+          hidden,
+        })
       }
       Ok(response) => {
-        if !config.quiet {
+        if revalidates {
+          remember_validators(context, &interpolated_base_url, &response);
+        }
+        hidden.extend(redacted_headers(response.headers(), config));
+        config.connections.record(&response);
+        // Slow requests are shown even when the others are hidden
+        let slow = config.is_slow(duration_ms);
+        if config.output.requests() || (slow && config.output.errors()) {
           let status = response.status();
           let status_text = if status.is_server_error() {
             status.to_string().red()
@@ -250,21 +647,67 @@ impl Request {
             status.to_string().yellow()
           };
 
+          let redirects = match hops.len() {
+            0 => String::new(),
+            1 => " (1 redirect)".to_owned(),
+            count => format!(" ({count} redirects)"),
+          };
+          let flag = if slow { " SLOW" } else { "" };
           println!(
-            "{:width$} {} {} {}",
+            "{:width$} {} {} {}{}{}",
             self.name.green(),
-            interpolated_base_url.blue().bold(),
+            config
+              .redact_values(&interpolated_base_url, &hidden)
+              .blue()
+              .bold(),
             status_text,
-            Request::format_time(duration_ms, config.nanosec).cyan(),
+            config.time_unit.format(duration_ms).cyan(),
+            redirects.yellow(),
+            flag.red().bold(),
             width = 25
           );
         }
 
-        (Some(response), duration_ms)
+        Ok(Sent {
+          response: Some(response),
+          duration_ms,
+          hops,
+          url: interpolated_base_url,
+          error: None,
+          hidden,
+        })
       }
     }
   }
 
+  /// Writes the line of a request to the `--request-log`, if any
+  fn log_line(
+    &self,
+    config: &Config,
+    sent: &Sent,
+    status: Option<u16>,
+    bytes: Option<usize>,
+    error: Option<String>,
+  ) {
+    if let Some(log) = &config.request_log {
+      let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+      let url = config.redact_values(&sent.url, &sent.hidden);
+      let line = RequestLogLine {
+        timestamp: now.as_secs_f64() - sent.duration_ms / 1_000.0,
+        name: &self.name,
+        method: &self.method.to_uppercase(),
+        url: &url,
+        status,
+        duration: sent.duration_ms,
+        bytes,
+        error: error
+          .or_else(|| sent.error.clone())
+          .map(|error| config.redact_values(&error, &sent.hidden)),
+      };
+      writer::log_request(log, &line);
+    }
+  }
+
   async fn execute_one_request(
     &self,
     context: &mut Context,
@@ -272,9 +715,20 @@ impl Request {
     config: &Config,
     reports: &mut Reports,
     with_item: Option<&serde_yaml::Value>,
-  ) {
-    let (res, duration_ms) =
-      self.send_request(context, pool, config, with_item).await;
+  ) -> Result<(), Error> {
+    // Unless the request sets the header itself
+    let request_id = config
+      .request_id
+      .as_ref()
+      .filter(|name| {
+        !self.headers.keys().any(|key| key.eq_ignore_ascii_case(name))
+      })
+      .map(|_| random_uuid());
+    let mut sent = self
+      .send_request(context, pool, config, with_item, request_id.as_deref())
+      .await?;
+    let (duration_ms, hops) = (sent.duration_ms, std::mem::take(&mut sent.hops));
+    let res = sent.response.take();
 
     let log_message_response = if config.verbose {
       Some(log_message_response(&res, duration_ms))
@@ -283,64 +737,191 @@ impl Request {
     };
 
     match res {
-      None => reports.push(Report {
-        name: self.name.to_owned(),
-        duration: duration_ms,
-        status: 520u16,
-      }),
+      None => {
+        let mut report =
+          Report::new(self.name.to_owned(), duration_ms, 520, false);
+        report.request_id = request_id;
+        report.slow = config.is_slow(duration_ms);
+        reports.push(report);
+        self.log_line(config, &sent, None, None, None);
+      }
       Some(response) => {
         let status = response.status().as_u16();
 
-        reports.push(Report {
-          name: self.name.to_owned(),
-          duration: duration_ms,
-          status,
-        });
+        let content_type = response
+          .headers()
+          .get(header::CONTENT_TYPE)
+          .and_then(|value| value.to_str().ok())
+          .unwrap_or_default();
+        let expected_type = match &self.expect_content_type {
+          Some(expected) => media_type_matches(expected, content_type),
+          None => true,
+        };
+        if !expected_type && config.output.errors() {
+          println!(
+            "{:width$} {} '{}', expected '{}'",
+            self.name.green(),
+            "Unexpected content type".red(),
+            content_type,
+            self.expect_content_type.as_deref().unwrap_or_default(),
+            width = 25
+          );
+        }
+        // Only conditional requests get a 304, their cached copy being valid
+        let not_modified = config.conditional_requests && status == 304;
+        let success = not_modified
+          || (expected_type
+            && self
+              .success_status
+              .as_ref()
+              .unwrap_or(&config.success_status)
+              .matches(status));
+        let expected_failure = !success
+          && self
+            .expected_failures
+            .as_ref()
+            .or(config.expected_failures.as_ref())
+            .is_some_and(|expected| expected.matches(status));
+        let mut report =
+          Report::new(self.name.to_owned(), duration_ms, status, success);
+        report.expected_failure = expected_failure;
+        if self.capture_redirects == Some(RedirectCapture::Report) {
+          report.redirects = hops
+            .iter()
+            .map(|hop| Hop {
+              url: config.redact_values(&hop.url, &sent.hidden),
+              ..hop.clone()
+            })
+            .collect();
+        }
+        report.request_id = request_id;
+        report.slow = config.is_slow(duration_ms);
+        let report_index = reports.len();
+        reports.push(report);
+        let failure = (config.output.failure_details()
+          && !success
+          && !expected_failure)
+          .then(|| failure_message(&self.name, &response, duration_ms, config));
 
         for cookie in response.cookies() {
-          let cookies = context
-            .entry("cookies")
-            .or_insert_with(|| json!({}))
-            .as_object_mut()
-            .unwrap();
-          cookies.insert(
-            cookie.name().to_string(),
-            json!(cookie.value().to_string()),
-          );
+          let cookies = context.entry("cookies").or_insert_with(|| json!({}));
+          if let Some(cookies) = cookies.as_object_mut() {
+            cookies.insert(
+              cookie.name().to_string(),
+              json!(cookie.value().to_string()),
+            );
+          }
         }
 
-        let data = if let Some(key) = &self.assign {
+        let kept = |header: &HeaderName| match &self.kept_headers {
+          Some(kept) => {
+            kept.iter().any(|name| header.as_str().eq_ignore_ascii_case(name))
+          }
+          None => true,
+        };
+        let headers = self.assign.as_ref().map(|_| {
           let mut headers = Map::new();
+          response.headers().iter().filter(|(header, _)| kept(header)).for_each(
+            |(header, value)| {
+              headers.insert(
+                header.to_string(),
+                json!(String::from_utf8_lossy(value.as_bytes())),
+              );
+            },
+          );
+          headers
+        });
 
-          response.headers().iter().for_each(|(header, value)| {
-            headers.insert(header.to_string(), json!(value.to_str().unwrap()));
-          });
-
-          let data = response.text().await.unwrap();
+        // Discarded bodies are never downloaded, the connection is dropped,
+        // but for the failures printed by `--only-errors`. Those and the
+        // verbose ones aren't streamed through `assign_only` either.
+        let declared_bytes = response.content_length().map(|len| len as usize);
+        let declared_size = declared_bytes
+          .map_or(Ok(()), |bytes| self.check_response_size(bytes));
+        let streamed = failure.is_none() && !config.verbose;
+        let read = match (&self.download, &self.body_paths) {
+          (None, _) if self.discard_body && failure.is_none() => {
+            Ok(Body::Discarded)
+          }
+          // Not worth receiving when it's declared too large already
+          _ if declared_size.is_err() => declared_size.map(|_| Body::Discarded),
+          (Some(download), _) => self
+            .download(response, download, context, config)
+            .await
+            .map(Body::Downloaded),
+          (None, Some(paths)) if streamed => {
+            self.extract_body(response, paths).await
+          }
+          (None, _) => self.read_body(response).await.map(Body::Read),
+        };
+        let bytes = match &read {
+          Ok(Body::Read(data)) => Some(data.len()),
+          Ok(Body::Extracted(_, bytes)) => Some(*bytes),
+          Ok(Body::Downloaded(download)) => Some(download.bytes),
+          Ok(Body::Discarded) => declared_bytes,
+          Err(_) => None,
+        };
+        let error = read.as_ref().err().map(ToString::to_string);
+        if let Err(Error::ResponseTooLarge {
+          ..
+        }) = &read
+        {
+          let report = &mut reports[report_index];
+          report.status = RESPONSE_TOO_LARGE_STATUS;
+          report.success = false;
+          report.expected_failure = false;
+        }
+        self.log_line(config, &sent, Some(status), bytes, error);
+        if let Some(mut message) = failure {
+          if let Ok(Body::Read(data)) = &read {
+            write!(message, "\n  {} {}", "BODY:".bold(), snippet(data))
+              .unwrap();
+          }
+          println!("{}", config.redact_values(&message, &sent.hidden));
+        }
+        // The report holds the failure, the iteration goes on
+        let read = match read {
+          Err(Error::ResponseTooLarge {
+            ..
+          }) => Ok(Body::Discarded),
+          read => read,
+        };
+        let (data, extracted, download) = match read? {
+          Body::Discarded => (None, None, None),
+          Body::Read(data) => (Some(data), None, None),
+          Body::Extracted(value, _) => (None, Some(value), None),
+          Body::Downloaded(download) => (None, None, Some(download)),
+        };
 
-          let body: Value =
-            serde_json::from_str(&data).unwrap_or(serde_json::Value::Null);
+        if let (Some(key), Some(headers)) = (&self.assign, headers) {
+          let body = match (extracted, data.as_deref(), &self.body_paths) {
+            (Some(value), _, _) => Some(value),
+            (None, Some(data), Some(paths)) => paths.extract(data),
+            (None, Some(data), None) => serde_json::from_str(data).ok(),
+            (None, None, _) => None,
+          };
+          let body = body.unwrap_or(Value::Null);
 
           let assigned = AssignedRequest {
             status,
             body,
             headers,
+            redirects: self.capture_redirects.map(|_| hops),
+            download,
           };
 
           let value = serde_json::to_value(assigned).unwrap();
 
           context.insert(key.to_owned(), value);
-
-          Some(data)
-        } else {
-          None
-        };
+        }
 
         if let Some(msg) = log_message_response {
-          log_response(msg, &data)
+          log_response(msg, &data, config, &sent.hidden)
         }
       }
     }
+
+    Ok(())
   }
 }
 
@@ -352,38 +933,385 @@ impl Runnable for Request {
     reports: &mut Reports,
     pool: &Pool,
     config: &Config,
-  ) {
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
     if let Some(with_items) =
-      self.with_items.clone().filter(|vec| !vec.is_empty())
+      self.with_items.as_ref().filter(|items| !items.is_empty())
     {
-      let mut with_items = with_items.clone();
-      if self.shuffle.unwrap() {
-        let mut rng = thread_rng();
-        with_items.shuffle(&mut rng);
-      }
-      let take = if self.pick.unwrap().inner() == 0 {
-        with_items.len()
-      } else {
-        self.pick.unwrap().inner()
+      let len = with_items.len();
+      let drawn = match self.resample {
+        Some(Resample::PerIteration) => {
+          self.draw_items_per_iteration(context, len, rng)
+        }
+        _ => self.draw_items(len, rng),
       };
-      for with_item in with_items.iter().take(take) {
+      let indices = match drawn {
+        Some(indices) => indices,
+        None => {
+          if config.output.errors() {
+            println!(
+              "{:width$} {}",
+              self.name.green(),
+              "No data left, stopping".yellow(),
+              width = 25
+            );
+          }
+          config.halt();
+          return Ok(());
+        }
+      };
+      for index in indices {
+        let with_item = Some(&with_items[index]);
         self
-          .execute_one_request(context, pool, config, reports, Some(with_item))
-          .await;
+          .execute_one_request(context, pool, config, reports, with_item)
+          .await?;
       }
+      Ok(())
     } else {
-      self.execute_one_request(context, pool, config, reports, None).await;
+      self.execute_one_request(context, pool, config, reports, None).await
+    }
+  }
+}
+
+/// Whether a `Content-Type` header, like `text/html; charset=utf-8`, has
+/// the expected media type, whose subtype may be `*`
+fn media_type_matches(expected: &str, content_type: &str) -> bool {
+  let media_type = content_type.split(';').next().unwrap_or_default().trim();
+  let expected = expected.trim();
+  match expected.strip_suffix("/*") {
+    Some(kind) => media_type
+      .split_once('/')
+      .is_some_and(|(actual, _)| actual.eq_ignore_ascii_case(kind)),
+    None => media_type.eq_ignore_ascii_case(expected),
+  }
+}
+
+/// Follows the redirects by hand, as the client would, timing every hop.
+/// Requests with a streamed body stop at the first redirect, as they can't
+/// be sent twice.
+async fn follow_redirects(
+  mut request: reqwest::Request,
+  pool: &Pool,
+  config: &Config,
+  route: &Route,
+) -> (Result<Response, reqwest::Error>, Vec<Hop>) {
+  let mut hops = Vec::new();
+  loop {
+    let client = match pooled_client(pool, request.url(), config, false, route)
+    {
+      Ok(client) => client,
+      Err(err) => return (Err(err), hops),
+    };
+    let next = request.try_clone();
+    let begin = Instant::now();
+    let response = match client.execute(request).await {
+      Ok(response) => response,
+      Err(err) => return (Err(err), hops),
+    };
+
+    let status = response.status();
+    let location = response
+      .headers()
+      .get(header::LOCATION)
+      .and_then(|location| location.to_str().ok())
+      .and_then(|location| response.url().join(location).ok());
+    let (mut next, location) = match (next, location) {
+      (Some(next), Some(location))
+        if status.is_redirection() && hops.len() < MAX_REDIRECTS =>
+      {
+        (next, location)
+      }
+      _ => return (Ok(response), hops),
+    };
+    hops.push(Hop {
+      url: response.url().to_string(),
+      status: status.as_u16(),
+      duration: begin.elapsed().as_secs_f64() * 1000.0,
+    });
+
+    let moved = status == StatusCode::MOVED_PERMANENTLY
+      || status == StatusCode::FOUND;
+    if status == StatusCode::SEE_OTHER || moved && next.method() == Method::POST
+    {
+      *next.method_mut() = Method::GET;
+      *next.body_mut() = None;
+      next.headers_mut().remove(header::CONTENT_TYPE);
+      next.headers_mut().remove(header::CONTENT_LENGTH);
+    }
+    // Credentials aren't sent to other hosts
+    if location.host_str() != next.url().host_str() {
+      next.headers_mut().remove(header::AUTHORIZATION);
+      next.headers_mut().remove(header::COOKIE);
+    }
+    *next.url_mut() = location;
+    request = next;
+  }
+}
+
+/// Adds the W3C `traceparent` of a new span of the iteration trace, and a
+/// `tracestate` naming the run, unless the request sets its own
+fn add_trace_context(trace_id: &str, run_id: &str, headers: &mut HeaderMap) {
+  let span_id = rand::random::<u64>().max(1);
+  let traceparent = format!("00-{trace_id}-{span_id:016x}-01");
+  let tracestate = format!("drill={run_id}");
+  let trace_context = [
+    (HeaderName::from_static("traceparent"), traceparent),
+    (HeaderName::from_static("tracestate"), tracestate),
+  ];
+  for (name, value) in trace_context {
+    if let Ok(value) = HeaderValue::from_str(&value) {
+      headers.entry(name).or_insert(value);
+    }
+  }
+}
+
+/// Random version 4 UUID, like `3f2b8c1e-7d4a-4b9e-a1c2-5e6f7a8b9c0d`
+fn random_uuid() -> String {
+  let mut bytes: [u8; 16] = rand::random();
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+  format!(
+    "{}-{}-{}-{}-{}",
+    &hex[..8],
+    &hex[8..12],
+    &hex[12..16],
+    &hex[16..20],
+    &hex[20..]
+  )
+}
+
+/// Makes the request conditional on the `ETag` and `Last-Modified` last
+/// received for `url`, unless it sets its own conditions
+fn add_validators(context: &Context, url: &str, headers: &mut HeaderMap) {
+  let validators = context
+    .get("validators")
+    .and_then(|validators| validators.get(url))
+    .and_then(Value::as_object);
+  let validators = match validators {
+    Some(validators) => validators,
+    None => return,
+  };
+  let conditions = [
+    ("etag", header::IF_NONE_MATCH),
+    ("last_modified", header::IF_MODIFIED_SINCE),
+  ];
+  for (key, name) in conditions {
+    let value = validators
+      .get(key)
+      .and_then(Value::as_str)
+      .and_then(|value| HeaderValue::from_str(value).ok());
+    if let Some(value) = value {
+      headers.entry(name).or_insert(value);
+    }
+  }
+}
+
+/// Keeps the `ETag` and `Last-Modified` of a response in the context, under
+/// `validators` by url, for the next requests to `url` to revalidate
+fn remember_validators(context: &mut Context, url: &str, response: &Response) {
+  let header = |name| {
+    response.headers().get(name).and_then(|value| value.to_str().ok())
+  };
+  let (etag, last_modified) =
+    (header(header::ETAG), header(header::LAST_MODIFIED));
+  let validators = context.entry("validators").or_insert_with(|| json!({}));
+  let validators = match validators.as_object_mut() {
+    Some(validators) => validators,
+    None => return,
+  };
+  // A 304 may leave out the validators it didn't change
+  let mut kept = match validators.get(url) {
+    Some(Value::Object(kept))
+      if response.status() == StatusCode::NOT_MODIFIED =>
+    {
+      kept.clone()
+    }
+    _ => Map::new(),
+  };
+  if let Some(etag) = etag {
+    kept.insert("etag".to_owned(), json!(etag));
+  }
+  if let Some(last_modified) = last_modified {
+    kept.insert("last_modified".to_owned(), json!(last_modified));
+  }
+  if kept.is_empty() {
+    validators.remove(url);
+  } else {
+    validators.insert(url.to_owned(), Value::Object(kept));
+  }
+}
+
+/// Replaces the `:name` segments of `url` with the percent-encoded value of
+/// their parameter, leaving the ones without a value as they are
+pub fn fill_path_params(
+  url: &str,
+  params: &HashMap<&str, String>,
+) -> String {
+  let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+  let mut filled = String::with_capacity(url.len());
+  let mut rest = url;
+  while let Some(start) = rest.find("/:") {
+    let (before, segment) = rest.split_at(start + 2);
+    let end = segment.find(|c| !is_name(c)).unwrap_or(segment.len());
+    let (name, after) = segment.split_at(end);
+    filled.push_str(before);
+    match params.get(name) {
+      Some(value) => {
+        filled.pop();
+        filled.push_str(&percent_encode(value));
+      }
+      None => filled.push_str(name),
     }
+    rest = after;
   }
+  filled.push_str(rest);
+  filled
 }
 
-fn log_request(request: &reqwest::Request) {
+/// Encodes everything but the unreserved characters of RFC 3986
+fn percent_encode(value: &str) -> String {
+  let mut encoded = String::with_capacity(value.len());
+  for byte in value.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        encoded.push(byte as char)
+      }
+      _ => write!(encoded, "%{byte:02X}").unwrap(),
+    }
+  }
+  encoded
+}
+
+/// Where the connections of a request are opened from and to, when it's not
+/// simply from any address to the host of its url
+#[derive(Default)]
+struct Route {
+  local_address: Option<IpAddr>,
+  /// Hostname of the url, and the address its connections go to instead
+  sni: Option<(String, SocketAddr)>,
+}
+
+/// Replaces the IP address of `url` with the `sni` hostname, so it's the
+/// one presented for TLS and in the `Host` header, and returns the address
+/// the connections must go to
+pub fn sni_address(url: &mut Url, sni: &str) -> Result<SocketAddr, String> {
+  let ip = match url.host() {
+    Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+    Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+    _ => return Err(format!("sni needs an IP address in the url, got {url}")),
+  };
+  let port = url.port_or_known_default().unwrap_or_default();
+  url
+    .set_host(Some(sni))
+    .map_err(|err| format!("invalid sni '{sni}': {err}"))?;
+  Ok(SocketAddr::new(ip, port))
+}
+
+/// Client shared by every request to the domain of `url`, so connections are
+/// kept alive between them. Requests capturing their redirects get clients
+/// of their own, which don't follow them, and so do the ones with a `Route`.
+fn pooled_client(
+  pool: &Pool,
+  url: &Url,
+  config: &Config,
+  follow_redirects: bool,
+  route: &Route,
+) -> Result<Client, reqwest::Error> {
+  let mut domain = format!(
+    "{}://{}:{}",
+    url.scheme(),
+    url.host_str().unwrap_or_default(),
+    url.port().unwrap_or(0),
+  );
+  if !follow_redirects {
+    domain += " (no redirects)";
+  }
+  if let Some(address) = route.local_address {
+    write!(domain, " from {address}").unwrap();
+  }
+  if let Some((_, address)) = &route.sni {
+    write!(domain, " via {address}").unwrap();
+  }
+
+  // Concurrent iterations share the read lock, the write lock is only
+  // taken once per domain to build its client. Neither is held across an
+  // await, so a failing iteration can't leave the pool poisoned.
+  let cached =
+    pool.read().unwrap_or_else(PoisonError::into_inner).get(&domain).cloned();
+  if let Some(client) = cached {
+    return Ok(client);
+  }
+
+  let mut pool = pool.write().unwrap_or_else(PoisonError::into_inner);
+  if let Some(client) = pool.get(&domain) {
+    return Ok(client.clone());
+  }
+  let redirect_policy = if follow_redirects {
+    redirect::Policy::default()
+  } else {
+    redirect::Policy::none()
+  };
+  let mut builder = ClientBuilder::default()
+    .danger_accept_invalid_certs(config.no_check_certificate)
+    .redirect(redirect_policy)
+    .dns_resolver(Arc::new(Resolver::new(config.dns.clone())))
+    .local_address(route.local_address);
+  if let Some((sni, address)) = &route.sni {
+    builder = builder.resolve(sni, *address);
+  }
+  let client = builder.build()?;
+  pool.insert(domain, client.clone());
+  Ok(client)
+}
+
+/// Opens `config.prewarm_connections` connections to `base_url`, going
+/// through the TCP and TLS handshakes before the benchmark starts. They are
+/// opened with concurrent HEAD requests and kept alive in the pool.
+pub async fn prewarm_connections(
+  pool: &Pool,
+  config: &Config,
+  base_url: &str,
+  local_address: Option<IpAddr>,
+) -> Result<(), String> {
+  let url = Url::parse(base_url).map_err(|err| err.to_string())?;
+  let route = Route {
+    local_address,
+    sni: None,
+  };
+  let client = pooled_client(pool, &url, config, true, &route)
+    .map_err(|err| err.to_string())?;
+
+  let requests = (0..config.prewarm_connections)
+    .map(|_| client.head(url.clone()).send())
+    .collect::<Vec<_>>();
+  for response in futures::future::join_all(requests).await {
+    response.map_err(|err| err.to_string())?;
+  }
+  Ok(())
+}
+
+/// Values of the headers listed in `redact`
+fn redacted_headers(headers: &HeaderMap, config: &Config) -> Vec<String> {
+  headers
+    .iter()
+    .filter(|(name, _)| config.redacts_header(name.as_str()))
+    .map(|(_, value)| String::from_utf8_lossy(value.as_bytes()).into_owned())
+    .collect()
+}
+
+fn log_request(request: &reqwest::Request, config: &Config, hidden: &[String]) {
   let mut message = String::new();
   write!(message, "{}", ">>>".bold().green()).unwrap();
   write!(message, " {} {},", "URL:".bold(), request.url()).unwrap();
   write!(message, " {} {},", "METHOD:".bold(), request.method()).unwrap();
   write!(message, " {} {:?}", "HEADERS:".bold(), request.headers()).unwrap();
-  println!("{message}");
+  if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+    let body = String::from_utf8_lossy(body);
+    write!(message, " {} {}", "BODY:".bold(), format_body(&body, config))
+      .unwrap();
+  }
+  println!("{}", config.redact_values(&message, hidden));
 }
 
 fn log_message_response(
@@ -407,11 +1335,209 @@ fn log_message_response(
   message
 }
 
-fn log_response(log_message_response: String, body: &Option<String>) {
+/// Details of a failed request printed by `--only-errors`, the body is
+/// added once read
+fn failure_message(
+  name: &str,
+  response: &Response,
+  duration_ms: f64,
+  config: &Config,
+) -> String {
+  let mut message = String::new();
+  write!(
+    message,
+    "{:width$} {} {} {}",
+    name.green(),
+    response.url().as_str().blue().bold(),
+    response.status().to_string().red(),
+    config.time_unit.format(duration_ms).cyan(),
+    width = 25
+  )
+  .unwrap();
+  for (header, value) in response.headers() {
+    let value = String::from_utf8_lossy(value.as_bytes());
+    write!(message, "\n  {}: {}", header.as_str().bold(), value).unwrap();
+  }
+  message
+}
+
+/// First bytes of a body, cut on a char boundary
+fn snippet(body: &str) -> &str {
+  truncate(body, 512)
+}
+
+fn truncate(body: &str, max: usize) -> &str {
+  let mut end = body.len().min(max);
+  while !body.is_char_boundary(end) {
+    end -= 1;
+  }
+  &body[..end]
+}
+
+/// Body as logged by `--verbose`, pretty-printed and truncated as asked
+fn format_body(body: &str, config: &Config) -> String {
+  let pretty = if config.pretty_json {
+    serde_json::from_str::<Value>(body)
+      .ok()
+      .and_then(|json| serde_json::to_string_pretty(&json).ok())
+  } else {
+    None
+  };
+  let body = pretty.as_deref().unwrap_or(body);
+  let shown = truncate(body, config.body_limit.unwrap_or(usize::MAX));
+  let mut message = if config.pretty_json {
+    format!("\n{shown}")
+  } else {
+    format!("{shown:?}")
+  };
+  if shown.len() < body.len() {
+    let more = format!("... ({} more bytes)", body.len() - shown.len());
+    write!(message, " {}", more.yellow()).unwrap();
+  }
+  message
+}
+
+fn log_response(
+  log_message_response: String,
+  body: &Option<String>,
+  config: &Config,
+  hidden: &[String],
+) {
   let mut message = String::new();
   write!(message, "{}{}", "<<<".bold().green(), log_message_response).unwrap();
   if let Some(body) = body.as_ref() {
-    write!(message, " {} {:?}", "BODY:".bold(), body).unwrap()
+    write!(message, " {} {}", "BODY:".bold(), format_body(body, config))
+      .unwrap()
+  }
+  println!("{}", config.redact_values(&message, hidden));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::SeedableRng;
+  use serde_json::json;
+
+  fn request(with_items: Option<WithItems>, max_response_bytes: Option<usize>) -> Request {
+    Request::new(
+      "Fetch".to_owned(),
+      None,
+      "http://localhost/".to_owned(),
+      HashMap::new(),
+      None,
+      0.0,
+      "GET".to_owned(),
+      HashMap::new(),
+      None,
+      None,
+      false,
+      None,
+      max_response_bytes,
+      None,
+      None,
+      None,
+      None,
+      None,
+      None,
+      with_items,
+      None,
+    )
+  }
+
+  #[test]
+  fn reports_streamed_responses_past_the_limit() {
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+
+    let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(async {
+      // Chunked, without a declared length
+      let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|_| async {
+          let (mut sender, body) = hyper::Body::channel();
+          tokio::spawn(async move {
+            for _ in 0..8 {
+              if sender.send_data(Bytes::from(vec![b'x'; 1_024])).await.is_err() {
+                break;
+              }
+            }
+          });
+          Ok::<_, Infallible>(hyper::Response::new(body))
+        }))
+      });
+      let server = hyper::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+      let url = format!("http://{}/", server.local_addr());
+      tokio::spawn(server);
+
+      let mut request = request(None, Some(2_000));
+      request.url = url;
+      request.assign = Some("fetched".to_owned());
+      let mut context = Context::new();
+      let mut reports = Reports::new();
+      let pool = Pool::default();
+      let config = Config {
+        timeout: std::time::Duration::from_secs(10),
+        ..Default::default()
+      };
+      let mut rng = Rng::seed_from_u64(0);
+      let result = request.execute(&mut context, &mut reports, &pool, &config, &mut rng).await;
+
+      assert!(result.is_ok(), "{:?}", result.err());
+      assert_eq!(reports.len(), 1);
+      assert_eq!(reports[0].status, RESPONSE_TOO_LARGE_STATUS);
+      assert!(reports[0].failed());
+      assert_eq!(context["fetched"]["body"], Value::Null);
+    });
+  }
+
+  #[test]
+  fn keeps_a_draw_per_worker() {
+    let with_items = WithItems {
+      shuffle: true,
+      pick: Pick::Count(2),
+      strategy: None,
+      resample: Resample::PerIteration,
+      items: (0..10).map(serde_yaml::Value::from).collect(),
+    };
+    let request = request(Some(with_items), None);
+    let mut rng = Rng::seed_from_u64(0);
+
+    for iteration in 0..1_000u64 {
+      let mut context = Context::new();
+      context.insert("iteration".to_owned(), json!(iteration));
+      context.insert("worker_id".to_owned(), json!(iteration % 4));
+      let first = request.draw_items_per_iteration(&context, 10, &mut rng);
+      let again = request.draw_items_per_iteration(&context, 10, &mut rng);
+      assert_eq!(first, again);
+    }
+    assert_eq!(request.drawn.lock().unwrap().len(), 4);
+  }
+
+  #[test]
+  fn fills_path_params() {
+    let params = HashMap::from([("id", "42".to_owned()), ("slug", "a b/c".to_owned())]);
+
+    assert_eq!(fill_path_params("/users/:id", &params), "/users/42");
+    assert_eq!(fill_path_params("http://h:8080/users/:id/posts/:slug?x=:id", &params), "http://h:8080/users/42/posts/a%20b%2Fc?x=:id");
+    assert_eq!(fill_path_params("/users/:id.json", &params), "/users/42.json");
+    assert_eq!(fill_path_params("/users/:other/:id", &params), "/users/:other/42");
+    assert_eq!(fill_path_params("/users", &params), "/users");
+  }
+
+  #[test]
+  fn percent_encodes_reserved_characters() {
+    assert_eq!(percent_encode("aZ09-._~"), "aZ09-._~");
+    assert_eq!(percent_encode("a b&c=d/é"), "a%20b%26c%3Dd%2F%C3%A9");
+    assert_eq!(percent_encode(""), "");
+  }
+
+  #[test]
+  fn matches_media_types() {
+    assert!(media_type_matches("application/json", "application/json; charset=utf-8"));
+    assert!(media_type_matches("application/json", "Application/JSON"));
+    assert!(media_type_matches(" text/* ", "text/html"));
+    assert!(!media_type_matches("text/*", "application/json"));
+    assert!(!media_type_matches("application/json", "application/problem+json"));
+    assert!(!media_type_matches("application/json", ""));
   }
-  println!("{message}");
 }
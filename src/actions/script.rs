@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use colored::*;
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde_json::{Map, Value};
+
+use crate::actions::Runnable;
+use crate::benchmark::{Context, Pool, Reports, Rng};
+use crate::config::Config;
+use crate::error::Error;
+
+// Name the context is exposed as inside scripts
+const CONTEXT_VARIABLE: &str = "ctx";
+
+#[derive(Clone)]
+pub struct Script {
+  name: String,
+  engine: Arc<Engine>,
+  ast: Arc<AST>,
+  pub assign: Option<String>,
+}
+
+impl Script {
+  pub fn new(name: String, assign: Option<String>, code: String) -> Self {
+    let engine = Engine::new();
+    let ast = engine.compile(&code).unwrap_or_else(|err| {
+      eprintln!(
+        "{} Invalid script '{}': {}",
+        "ERROR:".red().bold(),
+        name,
+        err
+      );
+      std::process::exit(1)
+    });
+
+    Self {
+      name,
+      engine: Arc::new(engine),
+      ast: Arc::new(ast),
+      assign,
+    }
+  }
+
+  /// Runs the script with the context as the `ctx` map, and returns the
+  /// updated context along with the value the script evaluated to
+  fn run(&self, context: &Context) -> Result<(Context, Value), String> {
+    let ctx =
+      rhai::serde::to_dynamic(context).map_err(|err| err.to_string())?;
+    let mut scope = Scope::new();
+    scope.push_dynamic(CONTEXT_VARIABLE, ctx);
+
+    let result = self
+      .engine
+      .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+      .map_err(|err| err.to_string())?;
+
+    let ctx = scope.get_value::<Dynamic>(CONTEXT_VARIABLE).unwrap_or_default();
+    let context: Map<String, Value> =
+      rhai::serde::from_dynamic(&ctx).map_err(|err| err.to_string())?;
+    let result: Value =
+      rhai::serde::from_dynamic(&result).map_err(|err| err.to_string())?;
+
+    Ok((context, result))
+  }
+}
+
+#[async_trait]
+impl Runnable for Script {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    _reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    if config.output.steps() {
+      println!(
+        "{:width$} {}",
+        self.name.green(),
+        "script".cyan().bold(),
+        width = 25
+      );
+    }
+
+    let (updated, result) =
+      self.run(context).map_err(|err| Error::step(&self.name, err))?;
+
+    *context = updated;
+    if let Some(key) = &self.assign {
+      context.insert(key.to_owned(), result);
+    }
+
+    Ok(())
+  }
+}
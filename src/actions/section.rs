@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use colored::*;
+use tokio::sync::Semaphore;
+
+use crate::actions::Runnable;
+use crate::benchmark::{run_steps, Context, Pool, Reports, Rng, Steps};
+use crate::config::Config;
+use crate::error::Error;
+
+/// Runs the steps of an item with a `concurrency` or `iterations` of its own,
+/// in at most `concurrency` iterations at once and `iterations` times over
+/// the whole run. Iterations wait for a free slot, and skip the item once
+/// it ran its share.
+pub struct Section {
+  name: String,
+  steps: Steps,
+  slots: Option<Arc<Semaphore>>,
+  iterations: Option<usize>,
+  started: AtomicUsize,
+}
+
+impl Section {
+  pub fn new(
+    name: String,
+    steps: Steps,
+    concurrency: Option<usize>,
+    iterations: Option<usize>,
+  ) -> Self {
+    Self {
+      name,
+      steps,
+      slots: concurrency.map(|slots| Arc::new(Semaphore::new(slots))),
+      iterations,
+      started: AtomicUsize::new(0),
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for Section {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    if let Some(iterations) = self.iterations {
+      if self.started.fetch_add(1, Ordering::Relaxed) >= iterations {
+        if config.output.steps() {
+          println!(
+            "{:width$} {}",
+            self.name.green(),
+            format!("skipped, ran {iterations} times").yellow(),
+            width = 25
+          );
+        }
+        return Ok(());
+      }
+    }
+
+    // Held until the steps are done, the semaphore is never closed
+    let _slot = match &self.slots {
+      Some(slots) => Some(slots.acquire().await.unwrap()),
+      None => None,
+    };
+    run_steps(&self.steps, context, reports, pool, config, rng).await
+  }
+}
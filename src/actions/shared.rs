@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use colored::*;
+use serde_json::json;
+
+use crate::actions::Runnable;
+use crate::benchmark::{Context, Pool, Reports, Rng};
+use crate::config::Config;
+use crate::error::Error;
+
+const SHARED: &str = "shared";
+
+/// Increments a counter of the store shared by all iterations, optionally
+/// assigning its new value, e.g. to get unique sequence numbers
+#[derive(Clone)]
+pub struct CounterInc {
+  name: String,
+  assign: Option<String>,
+  key: String,
+  by: i64,
+}
+
+impl CounterInc {
+  pub fn new(
+    name: String,
+    assign: Option<String>,
+    key: String,
+    by: i64,
+  ) -> Self {
+    Self {
+      name,
+      assign,
+      key,
+      by,
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for CounterInc {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    _reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let value = config
+      .increment_shared(&self.key, self.by)
+      .map_err(|err| Error::step(&self.name, err))?;
+
+    if config.output.steps() {
+      println!(
+        "{:width$} {}={}",
+        self.name.green(),
+        format!("shared.{}", self.key).cyan().bold(),
+        value.to_string().magenta(),
+        width = 25
+      );
+    }
+
+    if let Some(key) = &self.assign {
+      context.insert(key.to_owned(), json!(value));
+    }
+    context.insert(SHARED.to_owned(), config.shared_values());
+
+    Ok(())
+  }
+}
+
+/// Sets a value of the store shared by all iterations
+#[derive(Clone)]
+pub struct SharedSet {
+  name: String,
+  key: String,
+  value: serde_json::Value,
+}
+
+impl SharedSet {
+  pub fn new(name: String, key: String, value: serde_json::Value) -> Self {
+    Self {
+      name,
+      key,
+      value,
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for SharedSet {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    _reports: &mut Reports,
+    _pool: &Pool,
+    config: &Config,
+    _rng: &mut Rng,
+  ) -> Result<(), Error> {
+    // Strings get interpolated, so values can be taken from the context
+    let value = match &self.value {
+      serde_json::Value::String(value) => {
+        json!(crate::interpolator::Interpolator::new(context).resolve(value)?)
+      }
+      value => value.clone(),
+    };
+
+    if config.output.steps() {
+      println!(
+        "{:width$} {}={}",
+        self.name.green(),
+        format!("shared.{}", self.key).cyan().bold(),
+        serde_json::to_string(&value).unwrap().magenta(),
+        width = 25
+      );
+    }
+
+    config.set_shared(&self.key, value);
+    context.insert(SHARED.to_owned(), config.shared_values());
+
+    Ok(())
+  }
+}
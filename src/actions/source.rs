@@ -0,0 +1,41 @@
+use async_trait::async_trait;
+
+use crate::actions::Runnable;
+use crate::benchmark::{Context, Pool, Reports, Rng, Runner};
+use crate::config::Config;
+use crate::error::Error;
+
+/// Tags the reports of an included step with the file it came from. The
+/// outermost include wins, so a master plan groups by its own includes.
+pub struct Source {
+  source: String,
+  step: Runner,
+}
+
+impl Source {
+  pub fn new(source: String, step: Runner) -> Self {
+    Self {
+      source,
+      step,
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for Source {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let first_report = reports.len();
+    let result = self.step.execute(context, reports, pool, config, rng).await;
+    for report in reports.iter_mut().skip(first_report) {
+      report.source = Some(self.source.clone());
+    }
+    result
+  }
+}
@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use colored::*;
+use serde_json::{Map, Value};
+
+use crate::actions::Runnable;
+use crate::benchmark::{run_steps, Context, Pool, Reports, Rng, Steps};
+use crate::config::Config;
+use crate::error::Error;
+use crate::interpolator;
+
+/// Instance of a template. Its arguments are set in the context while the
+/// template steps run, and the keys they shadowed are restored afterwards.
+pub struct Template {
+  name: String,
+  template: String,
+  args: Map<String, Value>,
+  steps: Steps,
+}
+
+impl Template {
+  pub fn new(
+    name: String,
+    template: String,
+    args: Map<String, Value>,
+    steps: Steps,
+  ) -> Self {
+    Self {
+      name,
+      template,
+      args,
+      steps,
+    }
+  }
+
+  fn resolve_args(&self, context: &Context) -> Result<Context, Error> {
+    let interpolator = interpolator::Interpolator::new(context);
+    let mut args = Context::new();
+    for (key, value) in self.args.iter() {
+      let value = match value {
+        Value::String(value) => Value::String(interpolator.resolve(value)?),
+        value => value.clone(),
+      };
+      args.insert(key.clone(), value);
+    }
+    Ok(args)
+  }
+}
+
+#[async_trait]
+impl Runnable for Template {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    if config.output.steps() {
+      println!(
+        "{:width$} {}",
+        self.name.green(),
+        self.template.cyan().bold(),
+        width = 25
+      );
+    }
+
+    let args = self.resolve_args(context)?;
+    let shadowed: Vec<(String, Option<Value>)> = args
+      .into_iter()
+      .map(|(key, value)| {
+        let previous = context.insert(key.clone(), value);
+        (key, previous)
+      })
+      .collect();
+
+    let result =
+      run_steps(&self.steps, context, reports, pool, config, rng).await;
+
+    for (key, previous) in shadowed {
+      match previous {
+        Some(value) => context.insert(key, value),
+        None => context.remove(&key),
+      };
+    }
+
+    result
+  }
+}
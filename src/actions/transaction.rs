@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use async_trait::async_trait;
+use colored::*;
+
+use crate::actions::{Report, Runnable};
+use crate::benchmark::{run_steps, Context, Pool, Reports, Rng, Steps};
+use crate::config::Config;
+use crate::error::Error;
+
+/// Group of steps reported as a whole, with their combined wall-clock
+/// duration, besides the reports of the steps themselves
+pub struct Transaction {
+  name: String,
+  steps: Steps,
+}
+
+impl Transaction {
+  pub fn new(name: String, steps: Steps) -> Self {
+    Self {
+      name,
+      steps,
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for Transaction {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let first_report = reports.len();
+    let begin = Instant::now();
+    let result =
+      run_steps(&self.steps, context, reports, pool, config, rng).await;
+    let duration_ms = begin.elapsed().as_secs_f64() * 1_000.0;
+
+    // Succeeds when every step did, with the status of its last request
+    let inner = &reports[first_report..];
+    let success = result.is_ok() && !inner.iter().any(Report::failed);
+    let status = inner.last().map_or(0, |report| report.status);
+    let mut report =
+      Report::new(self.name.clone(), duration_ms, status, success);
+    report.transaction = true;
+    reports.push(report);
+
+    if config.output.steps() {
+      let outcome = if success {
+        "transaction".cyan()
+      } else {
+        "transaction failed".red()
+      };
+      println!(
+        "{:width$} {} {}",
+        self.name.green(),
+        outcome.bold(),
+        config.time_unit.format(duration_ms).cyan(),
+        width = 25
+      );
+    }
+
+    result
+  }
+}
@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use colored::*;
+
+use crate::actions::Runnable;
+use crate::benchmark::{Context, Pool, Reports, Rng, Runner};
+use crate::config::Config;
+use crate::error::Error;
+use crate::interpolator;
+
+/// Runs the wrapped step only when its `when` condition holds
+pub struct When {
+  name: String,
+  condition: String,
+  step: Runner,
+}
+
+impl When {
+  pub fn new(name: String, condition: String, step: Runner) -> Self {
+    Self {
+      name,
+      condition,
+      step,
+    }
+  }
+}
+
+#[async_trait]
+impl Runnable for When {
+  async fn execute(
+    &self,
+    context: &mut Context,
+    reports: &mut Reports,
+    pool: &Pool,
+    config: &Config,
+    rng: &mut Rng,
+  ) -> Result<(), Error> {
+    let holds =
+      interpolator::Interpolator::new(context).evaluate(&self.condition)?;
+
+    if holds {
+      return self.step.execute(context, reports, pool, config, rng).await;
+    }
+
+    if config.output.steps() {
+      println!(
+        "{:width$} {} {}",
+        self.name.green(),
+        "skipped, not".yellow(),
+        self.condition.cyan(),
+        width = 25
+      );
+    }
+
+    Ok(())
+  }
+}
@@ -1,16 +1,26 @@
-use clap::{Args, Parser};
+use std::path::Path;
+use std::time::Duration;
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::parse::{parse_duration, DEFAULT_MAX_INCLUDE_DEPTH};
 
 #[derive(Parser)]
 #[command(
   name = "drill",
   version = "0.9.0",
   about = "HTTP load testing application written in Rust inspired by Ansible syntax",
-  rename_all = "kebab-case"
+  rename_all = "kebab-case",
+  args_conflicts_with_subcommands = true,
+  subcommand_negates_reqs = true
 )]
 pub struct Cli {
+  #[command(subcommand)]
+  pub command: Option<Command>,
   /// Sets the benchmark file
   #[arg(required = true)]
-  pub benchmark: String,
+  pub benchmark: Option<String>,
   #[command(flatten)]
   pub metrics: Metrics,
   /// !UNIMPLEMENTED! Do not panic if an interpolation is not present.
@@ -19,36 +29,185 @@ pub struct Cli {
   /// Disables SSL certification check. (Not recommended)
   #[arg(long)]
   pub no_check_certificate: bool,
+  /// Applies the documents of the benchmark setting this `environment`, on
+  /// top of the others
+  #[arg(long, value_name = "NAME")]
+  pub environment: Option<String>,
+  /// Fails reading the benchmark when includes nest deeper than this
+  #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_INCLUDE_DEPTH)]
+  pub max_include_depth: usize,
+  /// Keeps the working directory while running, rather than moving into the
+  /// benchmark file directory, so the output paths are resolved from it
+  #[arg(long)]
+  pub keep_cwd: bool,
+  /// Disables the colors of the output, as does setting NO_COLOR
+  #[arg(long, global = true)]
+  pub no_color: bool,
   #[command(flatten)]
   pub tag_options: TagOptions,
   /// List benchmark tasks (executes --tags/--skip-tags filter)
   #[arg(long)]
   pub list_tasks: bool,
-  /// Disables output
+  /// Sets the format of --list-tasks and --list-tags
+  #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+  pub format: ListFormat,
+  /// Validates the benchmark and prints the expanded plan without running it
   #[arg(long)]
-  pub quiet: bool,
-  /// Set timeout in seconds for all requests
+  pub dry_run: bool,
+  /// Prints every request as an equivalent curl command without running it
   #[arg(long)]
-  pub timeout: Option<String>,
-  /// Shows statistics in nanoseconds
+  pub export_curl: bool,
+  /// Runs the benchmark again whenever its file, its includes or its data
+  /// files change, with a line telling whether it passed
+  #[arg(long, conflicts_with = "out_dir")]
+  pub watch: bool,
+  /// Prints less: -q hides the requests, -qq the other steps too and -qqq
+  /// all but the summary and the errors
+  #[arg(short, long, action = ArgAction::Count)]
+  pub quiet: u8,
+  /// Only prints the failed requests, with their headers and body
   #[arg(long)]
-  pub nanosec: bool,
+  pub only_errors: bool,
+  /// Sets how the hostnames are resolved
+  #[arg(long, value_enum, default_value_t = DnsMode::Cached)]
+  pub dns: DnsMode,
+  /// Connects to the IPv4 addresses of the hosts only
+  #[arg(long, conflicts_with = "ipv6")]
+  pub ipv4: bool,
+  /// Connects to the IPv6 addresses of the hosts only
+  #[arg(long)]
+  pub ipv6: bool,
+  /// Set timeout for all requests, like 500ms or 1m (default: 10s)
+  #[arg(long, value_parser = parse_duration)]
+  pub timeout: Option<Duration>,
+  /// Sets the unit of the durations, auto picks one per value
+  #[arg(long, value_enum, default_value_t = TimeUnit::Ms)]
+  pub time_unit: TimeUnit,
   /// Toggle verbose output
   #[arg(long)]
   pub verbose: bool,
+  /// Truncates the bodies logged by --verbose past this size
+  #[arg(long, value_name = "BYTES", requires = "verbose")]
+  pub body_limit: Option<usize>,
+  /// Pretty-prints the JSON bodies logged by --verbose
+  #[arg(long, requires = "verbose")]
+  pub pretty_json: bool,
+  /// Seeds the random generator used by shuffle and pick
+  #[arg(long)]
+  pub seed: Option<u64>,
+  /// Sets a global variable, overriding the benchmark ones. Can be repeated
+  #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+  pub vars: Vec<(String, String)>,
+  /// Saves the run stats and samples into a SQLite history file
+  #[arg(long, value_name = "FILE")]
+  pub save_run: Option<String>,
+  /// Saves the mean, median, p95 and p99 of every step as a compare file
+  #[arg(long, value_name = "FILE")]
+  pub save_baseline: Option<String>,
+  /// Writes every report as a JSON line while running, instead of keeping
+  /// them in memory, for long runs
+  #[arg(long, value_name = "FILE")]
+  pub stream: Option<String>,
+  /// Writes every request as a JSON line once it completes, with its url,
+  /// status, duration, size and error
+  #[arg(long, value_name = "FILE")]
+  pub request_log: Option<String>,
+  /// Writes the latency distribution of all the requests in the
+  /// HdrHistogram percentile format
+  #[arg(long, value_name = "FILE")]
+  pub hgrm: Option<String>,
+  /// Writes the configuration the run was given as a single YAML file, with
+  /// the includes resolved, the tags applied and the command line overrides
+  #[arg(long, value_name = "FILE")]
+  pub config_snapshot: Option<String>,
+  /// Writes the summary, samples, latency distribution, request log and
+  /// configuration snapshot of the run into a new directory under this one,
+  /// named after its start time, but for the ones given a path of their own
+  #[arg(long, value_name = "DIR")]
+  pub out_dir: Option<String>,
+  /// Flags the requests taking longer than this, like 800ms, while running
+  /// and counts them in the stats
+  #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+  pub slow_threshold: Option<Duration>,
+  /// Searches the highest concurrency keeping the p99 of the requests under
+  /// this latency, like 300ms, instead of running the iterations
+  #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+  pub target_p99: Option<Duration>,
+  /// Sets how long every concurrency is tried by --target-p99 (default: 10s)
+  #[arg(
+    long,
+    value_name = "DURATION",
+    value_parser = parse_duration,
+    requires = "target_p99"
+  )]
+  pub probe_duration: Option<Duration>,
+  /// Stops the run once the p99 of the requests gets this many times over
+  /// the best one seen, like 3, reporting the knee point
+  #[arg(long, value_name = "FACTOR")]
+  pub stop_on_saturation: Option<f64>,
+  /// Sets the number of runtime threads, or of shards with --sharded
+  /// (default: the CPUs, up to the concurrency)
+  #[arg(long, value_name = "N")]
+  pub worker_threads: Option<usize>,
+  /// Sets the maximum number of threads for blocking work, like database
+  /// queries and commands
+  #[arg(long, value_name = "N")]
+  pub max_blocking_threads: Option<usize>,
+  /// Runs one single-threaded runtime per thread, each with its own
+  /// connections and share of the concurrency
+  #[arg(long, conflicts_with_all = ["report", "target_p99"])]
+  pub sharded: bool,
+}
+
+fn parse_var(var: &str) -> Result<(String, String), String> {
+  match var.split_once('=') {
+    Some((name, value)) if !name.trim().is_empty() => {
+      Ok((name.trim().to_owned(), value.to_owned()))
+    }
+    _ => Err(format!("expected NAME=VALUE, got '{var}'")),
+  }
 }
 
 impl Cli {
   pub fn into_flattened(self) -> FlattenedCli {
     FlattenedCli {
-      benchmark_file: self.benchmark,
+      benchmark_file: self.benchmark.unwrap_or_default(),
       relaxed_interpolations: self.relaxed_interpolations,
       no_check_certificate: self.no_check_certificate,
       list_tasks: self.list_tasks,
+      list_format: self.format,
+      dry_run: self.dry_run,
+      keep_cwd: self.keep_cwd,
+      environment: self.environment,
+      max_include_depth: self.max_include_depth,
+      watch: self.watch,
+      export_curl: self.export_curl,
       quiet: self.quiet,
+      only_errors: self.only_errors,
+      dns: self.dns,
+      ipv4: self.ipv4,
+      ipv6: self.ipv6,
       timeout: self.timeout,
-      nanosec: self.nanosec,
+      time_unit: self.time_unit,
       verbose: self.verbose,
+      body_limit: self.body_limit,
+      pretty_json: self.pretty_json,
+      seed: self.seed,
+      vars: self.vars,
+      save_run_option: self.save_run,
+      save_baseline_option: self.save_baseline,
+      stream_option: self.stream,
+      request_log_option: self.request_log,
+      hgrm_option: self.hgrm,
+      config_snapshot_option: self.config_snapshot,
+      out_dir_option: self.out_dir,
+      slow_threshold_option: self.slow_threshold,
+      target_p99_option: self.target_p99,
+      probe_duration_option: self.probe_duration,
+      stop_on_saturation_option: self.stop_on_saturation,
+      worker_threads_option: self.worker_threads,
+      max_blocking_threads_option: self.max_blocking_threads,
+      sharded: self.sharded,
       threshold_option: self.metrics.compare.threshold,
       compare_path_option: self.metrics.compare.compare,
       stats_option: self.metrics.report.stats,
@@ -59,10 +218,160 @@ impl Cli {
         .tag_options
         .tag_lists
         .skip_tags,
+      tag_expression_option: self.tag_options.tag_lists.tag_expression,
     }
   }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+  /// Aligned columns, for humans
+  #[default]
+  Text,
+  Json,
+  Yaml,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsMode {
+  /// Keeps the answers for their TTL
+  #[default]
+  Cached,
+  /// Resolves every host once, the base urls before the run starts
+  Once,
+  /// Queries the DNS servers for every new connection
+  Uncached,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TimeUnit {
+  S,
+  #[default]
+  Ms,
+  Us,
+  Ns,
+  Auto,
+}
+
+impl TimeUnit {
+  /// Formats a duration given in milliseconds
+  pub fn format(&self, ms: f64) -> String {
+    match self {
+      TimeUnit::S => format!("{:.2}s", ms / 1_000.0),
+      TimeUnit::Ms => ms.round().to_string() + "ms",
+      TimeUnit::Us => (1_000.0 * ms).round().to_string() + "us",
+      TimeUnit::Ns => (1_000_000.0 * ms).round().to_string() + "ns",
+      TimeUnit::Auto if ms >= 1_000.0 => TimeUnit::S.format(ms),
+      TimeUnit::Auto if ms >= 1.0 => TimeUnit::Ms.format(ms),
+      TimeUnit::Auto if ms >= 0.001 => TimeUnit::Us.format(ms),
+      TimeUnit::Auto => TimeUnit::Ns.format(ms),
+    }
+  }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+  /// Converts files from other tools into a benchmark plan
+  Import {
+    #[command(subcommand)]
+    source: ImportSource,
+  },
+  /// Records plain HTTP traffic sent through a local proxy into a plan
+  ///
+  /// Only plain HTTP requests are recorded. HTTPS connections are tunnelled
+  /// to their server as is, without TLS interception, so they are forwarded
+  /// but left out of the plan.
+  Record {
+    /// Port the proxy listens on
+    #[arg(long, default_value_t = 8888)]
+    port: u16,
+    /// Leaves out scripts, stylesheets, images and fonts
+    #[arg(long)]
+    skip_static: bool,
+    /// Sets the plan file to write, instead of printing it
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Lists the runs saved with --save-run
+  History {
+    /// SQLite history file
+    database: String,
+  },
+  /// Compares the step stats of two saved runs
+  Diff {
+    /// SQLite history file
+    database: String,
+    /// Baseline run id
+    first: i64,
+    /// Run id to compare with the baseline
+    second: i64,
+  },
+  /// Saves or checks the step stats of a full benchmark run
+  Baseline {
+    #[command(subcommand)]
+    action: BaselineAction,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum BaselineAction {
+  /// Runs the benchmark and saves the stats of every step
+  Save {
+    /// Baseline file to write
+    file: String,
+    /// Benchmark file to run
+    benchmark: String,
+    /// Sets a global variable, overriding the benchmark ones
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
+  },
+  /// Runs the benchmark and fails if a step got slower than the baseline
+  Check {
+    /// Baseline file written by `drill baseline save`
+    file: String,
+    /// Benchmark file to run
+    benchmark: String,
+    /// Sets a global variable, overriding the benchmark ones
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
+    /// Sets the allowed slowdown, as in --threshold
+    #[arg(short, long, default_value = "10%")]
+    threshold: String,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum ImportSource {
+  /// Imports a Postman collection (v2.1)
+  Postman {
+    /// Postman collection file
+    collection: String,
+    /// Sets the plan file to write, instead of printing it
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Scaffolds a plan from an OpenAPI 3 spec (JSON or YAML)
+  Openapi {
+    /// OpenAPI spec file
+    spec: String,
+    /// Sets the plan file to write, instead of printing it
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Converts a browser HAR capture into a plan
+  Har {
+    /// HAR file
+    capture: String,
+    /// Leaves out scripts, stylesheets, images and fonts
+    #[arg(long)]
+    skip_static: bool,
+    /// Sets the plan file to write, instead of printing it
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+}
+
 #[derive(Args)]
 #[group(required = false, multiple = false)]
 pub struct Metrics {
@@ -89,7 +398,8 @@ pub struct CompareFile {
   /// Sets a compare file
   #[arg(short, long)]
   pub compare: Option<String>,
-  /// Sets a threshold value in ms amongst the compared file
+  /// Sets a threshold value in ms, or in % of the compared file, for the
+  /// mean or per metric, e.g. "10%" or "median=5,p95=10%,p99=20"
   #[arg(short, long)]
   pub threshold: Option<String>,
 }
@@ -121,17 +431,51 @@ pub struct TagLists {
     value_delimiter = ','
   )]
   pub skip_tags: Vec<String>,
+  /// Boolean expression of tags to run, e.g. "(smoke or critical) and not
+  /// slow"
+  #[arg(long = "tags", conflicts_with = "include_tags")]
+  pub tag_expression: Option<String>,
 }
 
+#[derive(Default)]
 pub struct FlattenedCli {
   pub benchmark_file: String,
   pub relaxed_interpolations: bool,
   pub no_check_certificate: bool,
   pub list_tasks: bool,
-  pub quiet: bool,
-  pub timeout: Option<String>,
-  pub nanosec: bool,
+  pub list_format: ListFormat,
+  pub dry_run: bool,
+  pub keep_cwd: bool,
+  pub environment: Option<String>,
+  pub max_include_depth: usize,
+  pub watch: bool,
+  pub export_curl: bool,
+  pub quiet: u8,
+  pub only_errors: bool,
+  pub dns: DnsMode,
+  pub ipv4: bool,
+  pub ipv6: bool,
+  pub timeout: Option<Duration>,
+  pub time_unit: TimeUnit,
   pub verbose: bool,
+  pub body_limit: Option<usize>,
+  pub pretty_json: bool,
+  pub seed: Option<u64>,
+  pub vars: Vec<(String, String)>,
+  pub save_run_option: Option<String>,
+  pub save_baseline_option: Option<String>,
+  pub stream_option: Option<String>,
+  pub request_log_option: Option<String>,
+  pub hgrm_option: Option<String>,
+  pub config_snapshot_option: Option<String>,
+  pub out_dir_option: Option<String>,
+  pub slow_threshold_option: Option<Duration>,
+  pub target_p99_option: Option<Duration>,
+  pub probe_duration_option: Option<Duration>,
+  pub stop_on_saturation_option: Option<f64>,
+  pub worker_threads_option: Option<usize>,
+  pub max_blocking_threads_option: Option<usize>,
+  pub sharded: bool,
   pub report_path_option: Option<String>,
   pub compare_path_option: Option<String>,
   pub stats_option: bool,
@@ -139,15 +483,166 @@ pub struct FlattenedCli {
   pub list_tags: bool,
   pub tags: Vec<String>,
   pub skip_tags_option: Vec<String>,
+  pub tag_expression_option: Option<String>,
+}
+
+impl FlattenedCli {
+  /// Options for a plain run of the benchmark, as the subcommands do
+  pub fn for_benchmark(
+    benchmark_file: String,
+    vars: Vec<(String, String)>,
+  ) -> Self {
+    FlattenedCli {
+      benchmark_file,
+      vars,
+      ..Default::default()
+    }
+  }
+
+  /// Writes the artifacts of the run into `dir`, see `--out-dir`
+  pub fn with_out_dir(mut self, dir: &Path) -> Self {
+    let path = |name: &str| Some(dir.join(name).to_string_lossy().into_owned());
+    self.save_baseline_option =
+      self.save_baseline_option.or_else(|| path("summary.json"));
+    self.stream_option = self.stream_option.or_else(|| path("samples.jsonl"));
+    self.hgrm_option = self.hgrm_option.or_else(|| path("latency.hgrm"));
+    self.config_snapshot_option =
+      self.config_snapshot_option.or_else(|| path("config.yml"));
+    self.request_log_option =
+      self.request_log_option.or_else(|| path("requests.jsonl"));
+    self
+  }
 }
 
 #[cfg(test)]
 mod test {
-  use super::Cli;
-  use clap::CommandFactory;
+  use super::{BaselineAction, Cli, Command, TimeUnit};
+  use clap::{CommandFactory, Parser};
+  use std::path::Path;
+  use std::time::Duration;
 
   #[test]
   fn test_assertions() {
     Cli::command().debug_assert();
   }
+
+  #[test]
+  fn parses_vars() {
+    let cli = Cli::parse_from([
+      "drill",
+      "plan.yml",
+      "--var",
+      "tenant=acme",
+      "--var",
+      "query=a=b",
+    ]);
+    assert_eq!(
+      cli.vars,
+      vec![
+        ("tenant".to_owned(), "acme".to_owned()),
+        ("query".to_owned(), "a=b".to_owned())
+      ]
+    );
+    assert!(Cli::try_parse_from(["drill", "plan.yml", "--var", "x"]).is_err());
+  }
+
+  #[test]
+  fn parses_baseline_check() {
+    let cli = Cli::parse_from(["drill", "baseline", "check", "b.yml", "p.yml"]);
+    match cli.command {
+      Some(Command::Baseline {
+        action: BaselineAction::Check {
+          file,
+          benchmark,
+          threshold,
+          ..
+        },
+      }) => {
+        assert_eq!(file, "b.yml");
+        assert_eq!(benchmark, "p.yml");
+        assert_eq!(threshold, "10%");
+      }
+      _ => panic!("expected a baseline check command"),
+    }
+  }
+
+  #[test]
+  fn formats_time_units() {
+    let cli = Cli::parse_from(["drill", "plan.yml", "--time-unit", "auto"]);
+    assert_eq!(cli.time_unit, TimeUnit::Auto);
+    assert_eq!(Cli::parse_from(["drill", "plan.yml"]).time_unit, TimeUnit::Ms);
+
+    assert_eq!(TimeUnit::Ms.format(12.4), "12ms");
+    assert_eq!(TimeUnit::Ns.format(0.5), "500000ns");
+    assert_eq!(TimeUnit::Auto.format(1_520.0), "1.52s");
+    assert_eq!(TimeUnit::Auto.format(15.2), "15ms");
+    assert_eq!(TimeUnit::Auto.format(0.25), "250us");
+    assert_eq!(TimeUnit::Auto.format(0.0004), "400ns");
+  }
+
+  #[test]
+  fn parses_target_p99() {
+    let cli = Cli::parse_from(["drill", "plan.yml", "--target-p99", "300ms"]);
+    assert_eq!(cli.target_p99, Some(Duration::from_millis(300)));
+    assert!(Cli::try_parse_from([
+      "drill",
+      "plan.yml",
+      "--probe-duration",
+      "5s"
+    ])
+    .is_err());
+  }
+
+  #[test]
+  fn parses_ip_family() {
+    let cli = Cli::parse_from(["drill", "plan.yml", "--ipv6"]);
+    assert!(cli.ipv6 && !cli.ipv4);
+    assert!(
+      Cli::try_parse_from(["drill", "plan.yml", "--ipv4", "--ipv6"]).is_err()
+    );
+  }
+
+  #[test]
+  fn parses_quiet_levels() {
+    assert_eq!(Cli::parse_from(["drill", "plan.yml"]).quiet, 0);
+    assert_eq!(Cli::parse_from(["drill", "plan.yml", "-qq"]).quiet, 2);
+    let cli = Cli::parse_from(["drill", "plan.yml", "-q", "--quiet", "-q"]);
+    assert_eq!(cli.quiet, 3);
+  }
+
+  #[test]
+  fn parses_no_color() {
+    assert!(Cli::parse_from(["drill", "plan.yml", "--no-color"]).no_color);
+    let cli = Cli::parse_from(["drill", "history", "runs.db", "--no-color"]);
+    assert!(cli.no_color && cli.command.is_some());
+  }
+
+  #[test]
+  fn fills_paths_from_out_dir() {
+    let cli = Cli::parse_from(["drill", "plan.yml", "--stream", "s.jsonl"]);
+    let args = cli.into_flattened().with_out_dir(Path::new("out"));
+
+    assert_eq!(args.stream_option.as_deref(), Some("s.jsonl"));
+    assert_eq!(args.hgrm_option.as_deref(), Some("out/latency.hgrm"));
+    assert_eq!(args.save_baseline_option.as_deref(), Some("out/summary.json"));
+    assert_eq!(args.config_snapshot_option.as_deref(), Some("out/config.yml"));
+  }
+
+  #[test]
+  fn parses_tag_expression() {
+    let cli = Cli::parse_from(["drill", "plan.yml", "--tags", "a and not b"]);
+    assert_eq!(
+      cli.into_flattened().tag_expression_option.as_deref(),
+      Some("a and not b")
+    );
+    assert!(Cli::try_parse_from([
+      "drill",
+      "plan.yml",
+      "--tags",
+      "a",
+      "--include-tags",
+      "a"
+    ])
+    .is_err());
+  }
 }
@@ -1,139 +1,788 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::env::{current_dir, set_current_dir};
+use std::fs::File;
+use std::io::BufWriter;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread;
 use std::time::{Duration, Instant};
 
+use futures::future::{self, Either};
 use futures::stream::{self, StreamExt};
+use path_absolutize::Absolutize;
+use rand::{rngs::StdRng, SeedableRng};
 
 use serde_json::{json, Map, Value};
 use tokio::{runtime, time::sleep};
 
 use crate::actions::{
-  Assert, Assign, DbQuery, Delay, Exec, Report, Request, Runnable,
+  prewarm_connections, Assert, Assign, CounterInc, Custom, DbQuery, Debug,
+  Delay, Exec, Metric, OnError, Plugin, Report, Request, Runnable, Script,
+  Section, SharedSet, Source, Template, Transaction, When,
 };
-use crate::args::FlattenedCli;
+use crate::args::{DnsMode, FlattenedCli};
 use crate::config::Config;
+use crate::dns::Resolver;
+use crate::error::Error;
 
-use crate::parse::BenchmarkDoc;
-use crate::reader::read_file_as_yml;
+use crate::parse::{
+  read_benchmark_doc, BenchmarkDoc, Metadata, PlanItem, Threshold,
+};
+use crate::tags::Tags;
+use crate::tune;
 use crate::writer;
+use crate::RunStats;
 
 use reqwest::Client;
+use url::{Host, Url};
 
 use colored::*;
 
-pub type Runner = Box<(dyn Runnable + Sync + Send)>;
-pub type Benchmark = Vec<Runner>;
+pub type Runner = Box<dyn Runnable + Sync + Send>;
+pub type Steps = Vec<Runner>;
 pub type Context = Map<String, Value>;
 pub type Reports = Vec<Report>;
 pub type PoolStore = HashMap<String, Client>;
-pub type Pool = Arc<Mutex<PoolStore>>;
+pub type Pool = Arc<RwLock<PoolStore>>;
+pub type Rng = StdRng;
+/// Ids of the idle concurrency slots, so iterations know which one they run
+/// in
+type Workers = Arc<Mutex<BTreeSet<u64>>>;
+
+/// Steps run by every iteration
+pub struct Benchmark {
+  /// Run once by every virtual user, before its first iteration
+  pub before_vu: Steps,
+  pub before_each: Steps,
+  pub plan: Steps,
+  /// Run even when the iteration failed
+  pub after_each: Steps,
+}
 
 impl<'a> From<&'a BenchmarkDoc> for (Config, Benchmark) {
   fn from(doc: &'a BenchmarkDoc) -> Self {
     let mut config = Config::from(doc);
-    let mut benchmark = Benchmark::new();
-
-    for plan in &doc.plan {
-      let name = plan.name.clone().unwrap_or_default();
-      let assign = plan.assign.clone();
-      match plan.action.clone() {
-        crate::parse::Action::Assert {
-          key,
-          value,
-        } => benchmark.push(Box::new(Assert::new(name, key, value)) as Runner),
-        crate::parse::Action::Assign {
-          key,
-          value,
-        } => benchmark.push(Box::new(Assign::new(name, key, value)) as Runner),
-        crate::parse::Action::DbQuery {
-          target,
-          query,
-          with_items,
-        } => benchmark.push(Box::new(DbQuery::new(
-          name, assign, target, query, with_items,
-        )) as Runner),
-        crate::parse::Action::Delay {
-          seconds,
-        } => benchmark.push(Box::new(Delay::new(name, seconds)) as Runner),
-        crate::parse::Action::Exec {
-          command,
-        } => {
-          benchmark.push(Box::new(Exec::new(name, assign, command)) as Runner)
-        }
-        crate::parse::Action::Request {
-          base,
-          url,
-          time,
-          method,
-          headers,
-          body,
-          with_items,
-        } => benchmark.push(Box::new(Request::new(
-          name, base, url, time, method, headers, body, with_items, assign,
-        ))),
-        crate::parse::Action::Include(doc) => {
-          let (include_config, include_benchmark) = Self::from(&doc.doc);
+    let benchmark = Benchmark {
+      before_vu: build_steps(&doc.before_vu, &mut config),
+      before_each: build_steps(&doc.before_each, &mut config),
+      plan: build_steps(&doc.plan, &mut config),
+      after_each: build_steps(&doc.after_each, &mut config),
+    };
+
+    (config, benchmark)
+  }
+}
+
+/// Turns plan items into steps, merging the configuration of included files
+/// into `config`
+fn build_steps(items: &[PlanItem], config: &mut Config) -> Steps {
+  let mut steps = Steps::new();
+
+  for plan in items {
+    let name = plan.name.clone().unwrap_or_default();
+    let assign = plan.assign.clone();
+    let first_step = steps.len();
+    match plan.action.clone() {
+      crate::parse::Action::Assert {
+        key,
+        value,
+      } => steps.push(Box::new(Assert::new(name, key, value)) as Runner),
+      crate::parse::Action::Assign {
+        key,
+        value,
+      } => steps.push(Box::new(Assign::new(name, key, value)) as Runner),
+      crate::parse::Action::CounterInc {
+        key,
+        by,
+      } => {
+        steps.push(Box::new(CounterInc::new(name, assign, key, by)) as Runner)
+      }
+      crate::parse::Action::Custom {
+        command,
+        args,
+      } => {
+        steps.push(Box::new(Custom::new(name, command, args)) as Runner)
+      }
+      crate::parse::Action::DbQuery {
+        target,
+        query,
+        with_items,
+        max_rows,
+      } => steps.push(Box::new(DbQuery::new(
+        name, assign, target, query, with_items, max_rows,
+      )) as Runner),
+      crate::parse::Action::Debug {
+        keys,
+      } => steps.push(Box::new(Debug::new(name, keys)) as Runner),
+      crate::parse::Action::Delay(delay) => {
+        steps.push(Box::new(Delay::new(name, delay)) as Runner)
+      }
+      crate::parse::Action::Exec {
+        command,
+        timeout,
+        detach,
+        fail_on_nonzero,
+        shell,
+      } => steps.push(Box::new(Exec::new(
+        name,
+        assign,
+        command,
+        timeout,
+        detach,
+        fail_on_nonzero,
+        shell,
+      )) as Runner),
+      crate::parse::Action::Metric {
+        name: metric,
+        kind,
+        value,
+      } => {
+        steps.push(Box::new(Metric::new(name, metric, kind, value)) as Runner)
+      }
+      crate::parse::Action::Plugin {
+        name: plugin,
+        args,
+      } => {
+        steps.push(Box::new(Plugin::new(name, plugin, args)) as Runner)
+      }
+      crate::parse::Action::Request {
+        base,
+        url,
+        path_params,
+        sni,
+        time,
+        method,
+        headers,
+        body,
+        body_file,
+        discard_body,
+        max_body_bytes,
+        max_response_bytes,
+        success_status,
+        expected_failures,
+        expect_content_type,
+        capture_redirects,
+        download,
+        assign_only,
+        with_items,
+      } => steps.push(Box::new(Request::new(
+        name,
+        base,
+        url,
+        path_params,
+        sni,
+        time,
+        method,
+        headers,
+        body,
+        body_file,
+        discard_body.unwrap_or(assign.is_none()),
+        max_body_bytes,
+        max_response_bytes,
+        success_status,
+        expected_failures,
+        expect_content_type,
+        capture_redirects,
+        download,
+        assign_only,
+        with_items,
+        assign,
+      ))),
+      crate::parse::Action::Script {
+        code,
+      } => {
+        steps.push(Box::new(Script::new(name, assign, code)) as Runner)
+      }
+      crate::parse::Action::SharedSet {
+        key,
+        value,
+      } => steps.push(Box::new(SharedSet::new(name, key, value)) as Runner),
+      crate::parse::Action::Transaction(items) => {
+        let transaction_steps = build_steps(&items, config);
+        steps.push(Box::new(Transaction::new(name, transaction_steps)))
+      }
+      crate::parse::Action::Include(includes) => {
+        for include in includes {
+          let mut include_config = Config::from(&include.doc);
+          let include_steps =
+            build_steps(&include.doc.plan, &mut include_config);
           config.merge_config(include_config);
-          benchmark.extend(include_benchmark);
+          steps.extend(include_steps.into_iter().map(|step| {
+            Box::new(Source::new(include.path.clone(), step)) as Runner
+          }));
         }
       }
+      crate::parse::Action::Use {
+        template,
+        args,
+      } => {
+        let items = config.templates.get(&template).cloned();
+        let items = items.unwrap_or_else(|| {
+          eprintln!(
+            "{} Unknown template '{}' in '{}'",
+            "ERROR:".red().bold(),
+            template,
+            name
+          );
+          std::process::exit(1)
+        });
+        let template_steps = build_steps(&items, config);
+        steps.push(Box::new(Template::new(
+          name,
+          template,
+          args,
+          template_steps,
+        )))
+      }
     }
 
-    (config, benchmark)
+    // Error handlers and conditions on includes apply to every included step
+    if let Some(on_error) = &plan.on_error {
+      let wrapped = steps.split_off(first_step);
+      for step in wrapped {
+        let name = plan.name.clone().unwrap_or_default();
+        let handlers = build_steps(&on_error.steps, config);
+        steps.push(Box::new(OnError::new(name, step, handlers, on_error.then)));
+      }
+    }
+    // Limits apply to all the steps of an item as a whole
+    if plan.concurrency.is_some() || plan.iterations.is_some() {
+      let wrapped = steps.split_off(first_step);
+      steps.push(Box::new(Section::new(
+        plan.name.clone().unwrap_or_default(),
+        wrapped,
+        plan.concurrency,
+        plan.iterations,
+      )));
+    }
+    if let Some(condition) = &plan.when {
+      let wrapped = steps.split_off(first_step);
+      steps.extend(wrapped.into_iter().map(|step| {
+        let name = plan.name.clone().unwrap_or_default();
+        Box::new(When::new(name, condition.clone(), step)) as Runner
+      }));
+    }
   }
+
+  steps
 }
 
 pub struct BenchmarkResult {
+  /// Reports of every iteration, empty when streamed to disk
   pub reports: Vec<Reports>,
+  pub stats: RunStats,
   pub duration: f64,
+  /// Number of iterations aborted by an error
+  pub errors: usize,
+  pub thresholds: Vec<Threshold>,
+  pub metadata: Metadata,
 }
 
 async fn run_iteration(
   benchmark: Arc<Benchmark>,
   pool: Pool,
   config: Arc<Config>,
+  workers: Workers,
+  start: Instant,
   iteration: u64,
 ) -> Vec<Report> {
-  if config.rampup > 0 {
-    let delay = config.rampup / config.iterations;
-    sleep(Duration::new(delay * iteration, 0)).await;
+  let take_worker = || {
+    let mut idle = workers.lock().unwrap_or_else(PoisonError::into_inner);
+    idle.pop_first().unwrap_or_default()
+  };
+  let worker = take_worker();
+
+  ramp_up(&config, start, worker).await;
+  let begin = Instant::now();
+  // Every iteration is a user of its own
+  let user = User {
+    vu: iteration,
+    worker,
+  };
+  let reports = run_user_iteration(
+    &benchmark,
+    &pool,
+    &config,
+    user,
+    iteration,
+    &mut None,
+  )
+  .await;
+  pace(&config, begin, iteration).await;
+
+  workers.lock().unwrap_or_else(PoisonError::into_inner).insert(worker);
+  reports
+}
+
+/// Identifies who runs an iteration, exposed as `vu_id` and `worker_id`
+#[derive(Clone, Copy)]
+struct User {
+  vu: u64,
+  worker: u64,
+}
+
+/// Runs the iterations given to a virtual user one after the other, keeping
+/// its context, and so its cookies and tokens, between them
+fn run_virtual_user(
+  benchmark: Arc<Benchmark>,
+  pool: Pool,
+  config: Arc<Config>,
+  start: Instant,
+  vu: u64,
+) -> impl stream::Stream<Item = Vec<Report>> {
+  let iterations = (vu..config.iterations).step_by(config.concurrency as usize);
+
+  stream::unfold((iterations, None), move |(mut iterations, mut session)| {
+    let benchmark = benchmark.clone();
+    let pool = pool.clone();
+    let config = config.clone();
+    async move {
+      let iteration = iterations.next().filter(|_| !config.is_halted())?;
+      if iteration == vu {
+        ramp_up(&config, start, vu).await;
+      }
+      let begin = Instant::now();
+      let user = User {
+        vu,
+        worker: vu,
+      };
+      let reports = run_user_iteration(
+        &benchmark,
+        &pool,
+        &config,
+        user,
+        iteration,
+        &mut session,
+      )
+      .await;
+      pace(&config, begin, iteration).await;
+      Some((reports, (iterations, session)))
+    }
+  })
+}
+
+/// Runs iterations, numbered from `first`, at the given concurrency until
+/// `duration` is over
+pub async fn probe(
+  benchmark: &Arc<Benchmark>,
+  pool: &Pool,
+  config: &Arc<Config>,
+  concurrency: u64,
+  duration: Duration,
+  first: u64,
+) -> RunStats {
+  let workers: Workers = Arc::new(Mutex::new((0..concurrency).collect()));
+  let start = Instant::now();
+  let children = (first..)
+    .take_while(|_| start.elapsed() < duration && !config.is_halted())
+    .map(|iteration| {
+      run_iteration(
+        benchmark.clone(),
+        pool.clone(),
+        config.clone(),
+        workers.clone(),
+        start,
+        iteration,
+      )
+    });
+  let mut buffered =
+    stream::iter(children).buffer_unordered(concurrency as usize);
+
+  let mut stats = RunStats::default();
+  while let Some(reports) = buffered.next().await {
+    stats.record(&reports);
   }
+  stats
+}
+
+/// Holds a worker slot back until its share of the `rampup` has passed since
+/// the start, so the number of active workers grows evenly up to the
+/// concurrency, whatever the number of iterations
+async fn ramp_up(config: &Config, start: Instant, slot: u64) {
+  let offset = config.rampup.mul_f64(slot as f64 / config.concurrency as f64);
+  // Wakes up regularly, so halting doesn't wait for the held back slots
+  while let Some(remaining) = offset.checked_sub(start.elapsed()) {
+    if config.is_halted() {
+      break;
+    }
+    sleep(remaining.min(Duration::from_millis(100))).await;
+  }
+}
 
-  let mut context: Context = Context::new();
+/// Waits for the `pacing` interval to be over since the iteration began, so
+/// users start their iterations on a fixed cadence
+async fn pace(config: &Config, begin: Instant, iteration: u64) {
+  let pacing = match config.pacing {
+    Some(pacing) if !config.is_halted() => pacing,
+    _ => return,
+  };
+  match pacing.checked_sub(begin.elapsed()) {
+    Some(remaining) => sleep(remaining).await,
+    None if config.output.errors() => eprintln!(
+      "{} Iteration {} took {}, longer than the {} pacing",
+      "WARNING:".yellow().bold(),
+      iteration,
+      config.time_unit.format(begin.elapsed().as_secs_f64() * 1_000.0),
+      config.time_unit.format(pacing.as_secs_f64() * 1_000.0)
+    ),
+    None => {}
+  }
+}
+
+/// Runs an iteration in the context of its user, from the top again in a
+/// fresh context, up to `iteration_retries` times, when it fails on an error
+async fn run_user_iteration(
+  benchmark: &Benchmark,
+  pool: &Pool,
+  config: &Config,
+  user: User,
+  iteration: u64,
+  session: &mut Option<Context>,
+) -> Vec<Report> {
   let mut reports: Vec<Report> = Vec::new();
+  for attempt in 0..=config.iteration_retries {
+    if config.is_halted() {
+      break;
+    }
 
-  context.insert("iteration".to_string(), json!(iteration.to_string()));
-  context.insert("urls".to_string(), json!(config.urls));
-  context.insert("global".to_string(), json!(config.global));
+    let first_report = reports.len();
+    let errors = run_attempt(
+      benchmark,
+      pool,
+      config,
+      user,
+      iteration,
+      session,
+      &mut reports,
+    )
+    .await;
+    if errors.is_empty() {
+      break;
+    }
 
-  for item in benchmark.iter() {
-    item.execute(&mut context, &mut reports, &pool, &config).await;
+    if attempt < config.iteration_retries && !config.is_halted() {
+      for report in reports.iter_mut().skip(first_report) {
+        report.superseded = true;
+      }
+      if config.output.errors() {
+        for err in errors.iter() {
+          eprintln!(
+            "{} Iteration {}, retrying {}/{}: {}",
+            "WARNING:".yellow().bold(),
+            iteration,
+            attempt + 1,
+            config.iteration_retries,
+            config.redact(&err.to_string())
+          );
+        }
+      }
+      if attempt == 0 {
+        config.record_retry();
+      }
+      *session = None;
+      continue;
+    }
+
+    for err in errors.iter() {
+      eprintln!(
+        "{} Iteration {}: {}",
+        "ERROR:".red().bold(),
+        iteration,
+        config.redact(&err.to_string())
+      );
+    }
+    config.record_error();
   }
 
+  let retried = reports.iter().any(|report| report.superseded);
+  for report in reports.iter_mut() {
+    report.iteration = iteration;
+    report.retried = retried;
+  }
   reports
 }
 
-fn join<S: ToString>(l: Vec<S>, sep: &str) -> String {
-  l.iter().fold(
-    "".to_string(),
-    |a,b| if !a.is_empty() {a+sep} else {a} + &b.to_string()
+/// Runs an iteration once in the context of its user, which starts afresh
+/// and runs `before_vu` when there's none yet, and returns its errors
+async fn run_attempt(
+  benchmark: &Benchmark,
+  pool: &Pool,
+  config: &Config,
+  user: User,
+  iteration: u64,
+  session: &mut Option<Context>,
+  reports: &mut Reports,
+) -> Vec<Error> {
+  // Every iteration gets its own generator derived from the run seed, so
+  // results don't depend on how iterations get scheduled across threads
+  let mut rng = Rng::seed_from_u64(config.seed.wrapping_add(iteration));
+
+  // W3C trace id, never all zeros
+  let trace_id = format!("{:032x}", rand::random::<u128>().max(1));
+  let mut outcome = Ok(());
+  let context = match session {
+    Some(context) => context,
+    None => {
+      let context = session.insert(Context::new());
+      context.insert("run_id".to_string(), json!(config.run_id));
+      context.insert("vu_id".to_string(), json!(user.vu));
+      context.insert("urls".to_string(), json!(config.urls));
+      context.insert("global".to_string(), json!(config.global));
+      for (name, value) in config.secrets.iter() {
+        context.insert(name.to_owned(), json!(value));
+      }
+      context.insert("iteration".to_string(), json!(iteration));
+      context.insert("worker_id".to_string(), json!(user.worker));
+      if config.propagate_trace {
+        context.insert("trace_id".to_string(), json!(trace_id));
+      }
+      outcome = run_steps(
+        &benchmark.before_vu,
+        context,
+        reports,
+        pool,
+        config,
+        &mut rng,
+      )
+      .await;
+      context
+    }
+  };
+  context.insert("iteration".to_string(), json!(iteration));
+  context.insert("worker_id".to_string(), json!(user.worker));
+  if config.propagate_trace {
+    context.insert("trace_id".to_string(), json!(trace_id));
+  }
+  // The user logs in again on its next iteration
+  let setup_failed = outcome.is_err();
+
+  if outcome.is_ok() {
+    outcome = run_steps(
+      &benchmark.before_each,
+      context,
+      reports,
+      pool,
+      config,
+      &mut rng,
+    )
+    .await;
+  }
+  if outcome.is_ok() {
+    outcome =
+      run_steps(&benchmark.plan, context, reports, pool, config, &mut rng)
+        .await;
+  }
+  let cleanup = run_steps(
+    &benchmark.after_each,
+    context,
+    reports,
+    pool,
+    config,
+    &mut rng,
   )
+  .await;
+  if setup_failed {
+    *session = None;
+  }
+
+  vec![outcome, cleanup].into_iter().filter_map(Result::err).collect()
+}
+
+/// Runs the steps in order. The remaining steps likely depend on a failed
+/// one, so they are skipped after an error while other iterations go on.
+pub async fn run_steps(
+  steps: &[Runner],
+  context: &mut Context,
+  reports: &mut Reports,
+  pool: &Pool,
+  config: &Config,
+  rng: &mut Rng,
+) -> Result<(), Error> {
+  for step in steps {
+    if config.is_halted() {
+      break;
+    }
+    // Other iterations may have changed the shared values since last step
+    context.insert("shared".to_string(), config.shared_values());
+    step.execute(context, reports, pool, config, rng).await?;
+  }
+  Ok(())
+}
+
+/// Opens the prewarmed connections to every url, so the first samples don't
+/// include the connection setup
+async fn prewarm(pool: &Pool, config: &Config) {
+  let base_url = config.base_url.iter().map(|url| ("base_url", None, url));
+  let urls = config
+    .urls
+    .iter()
+    .map(|(name, url)| (name.as_str(), Some(name.as_str()), url));
+  for (name, base, url) in base_url.chain(urls) {
+    let local_address = config.local_address(base);
+    match prewarm_connections(pool, config, url, local_address).await {
+      Ok(()) if config.verbose => println!(
+        "{} {} connections to {}",
+        "Prewarmed".yellow(),
+        config.prewarm_connections.to_string().purple(),
+        name.green()
+      ),
+      Ok(()) => {}
+      Err(err) => eprintln!(
+        "{} Couldn't prewarm connections to {}: {}",
+        "WARNING:".yellow().bold(),
+        name,
+        err
+      ),
+    }
+  }
+}
+
+/// Prints the name, version and description of the benchmark, when set
+pub fn show_metadata(metadata: &Metadata) {
+  let version = metadata.version.as_deref().unwrap_or_default();
+  if let Some(name) = &metadata.name {
+    let benchmark = "Benchmark".yellow();
+    println!("{:25} {} {}", benchmark, name.green(), version.purple());
+  } else if !version.is_empty() {
+    println!("{:25} {}", "Version".yellow(), version.purple());
+  }
+  if let Some(description) = &metadata.description {
+    println!("{:25} {}", "Description".yellow(), description.trim_end());
+  }
+}
+
+/// Resolves the hosts of the base urls for `--dns once`, so their lookups
+/// aren't part of the first requests
+async fn preresolve(config: &Config) {
+  let resolver = Resolver::new(config.dns.clone());
+  let base_url = config.base_url.iter().map(|url| ("base_url", url));
+  let urls = config.urls.iter().map(|(name, url)| (name.as_str(), url));
+  for (name, url) in base_url.chain(urls) {
+    // Addresses and interpolated hosts have nothing to resolve yet
+    let host = match Url::parse(url).ok().as_ref().and_then(Url::host) {
+      Some(Host::Domain(host)) => host.to_owned(),
+      _ => continue,
+    };
+    match resolver.lookup(&host).await {
+      Ok(addrs) if config.verbose => println!(
+        "{} {} to {}",
+        "Resolved".yellow(),
+        name.green(),
+        addrs
+          .iter()
+          .map(|addr| addr.ip().to_string())
+          .collect::<Vec<_>>()
+          .join(", ")
+          .purple()
+      ),
+      Ok(_) => {}
+      Err(err) => eprintln!(
+        "{} Couldn't resolve {}: {}",
+        "WARNING:".yellow().bold(),
+        name,
+        err
+      ),
+    }
+  }
+}
+
+/// Halts the benchmark on the first Ctrl-C or SIGTERM, so the stats of the
+/// requests already made can still be shown, and exits on the second one
+async fn handle_interruptions(config: Arc<Config>) {
+  wait_for_interruption().await;
+  eprintln!(
+    "{} Interrupted, waiting for running requests. Press Ctrl-C again to \
+     exit right away",
+    "WARNING:".yellow().bold()
+  );
+  config.halt();
+
+  wait_for_interruption().await;
+  std::process::exit(130);
+}
+
+pub async fn wait_for_interruption() {
+  #[cfg(unix)]
+  {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    let interrupt = Box::pin(tokio::signal::ctrl_c());
+    future::select(interrupt, Box::pin(terminate.recv())).await;
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = tokio::signal::ctrl_c().await;
+  }
+}
+
+/// Resolves once the benchmark has been halted for longer than the request
+/// timeout, as running iterations should have finished by then
+async fn drain_deadline(config: Arc<Config>) {
+  while !config.is_halted() {
+    sleep(Duration::from_millis(100)).await;
+  }
+  sleep(config.timeout).await;
+}
+
+/// Parses the benchmark file, exiting on errors. Relative includes and data
+/// files get resolved from its directory.
+pub fn read_benchmark_file(benchmark_file: &str) -> BenchmarkDoc {
+  let path = PathBuf::from(benchmark_file).absolutize().unwrap().to_path_buf();
+
+  match read_benchmark_doc(&path) {
+    Ok(doc) => doc,
+    Err(err) => {
+      eprintln!("{} {}", "ERROR:".red().bold(), err);
+      std::process::exit(1)
+    }
+  }
+}
+
+/// Reads the benchmark file and drops the items filtered out by `--tags`,
+/// `--include-tags` and `--skip-tags`.
+pub fn load_benchmark_doc(args: &FlattenedCli) -> BenchmarkDoc {
+  let mut doc = read_benchmark_file(&args.benchmark_file);
+
+  let tags = Tags::new(
+    args.tags.clone(),
+    args.skip_tags_option.clone(),
+    args.tag_expression_option.clone(),
+  );
+  tags.filter_doc(&mut doc);
+  doc
 }
 
 pub fn execute(args: &FlattenedCli) -> BenchmarkResult {
   let original_dir = current_dir();
-  set_current_dir(PathBuf::from(&args.benchmark_file).parent().unwrap())
-    .unwrap();
+  let benchmark_doc = load_benchmark_doc(args);
 
-  let benchmark_doc: BenchmarkDoc =
-    serde_yaml::from_value(read_file_as_yml(&args.benchmark_file)).unwrap();
+  let path = PathBuf::from(&args.benchmark_file);
+  let dir = path.absolutize().unwrap().parent().unwrap().to_path_buf();
+  // The output files are resolved from the benchmark directory too, unless
+  // the working directory is kept
+  if !args.keep_cwd {
+    set_current_dir(&dir).unwrap();
+  }
 
-  let (config, benchmark): (Config, Benchmark) = From::from(&benchmark_doc);
-  let config = Arc::new(config.with_args(args));
+  let (mut config, benchmark): (Config, Benchmark) =
+    From::from(&benchmark_doc);
+  config.dir = dir;
+  let config = Arc::new(
+    config.with_args(args).fetch_secrets().validate_databases(),
+  );
 
-  if benchmark.is_empty() {
+  // Relative to where drill was run from, rather than the benchmark file
+  if let Some(path) = &args.config_snapshot_option {
+    let path = match &original_dir {
+      Ok(dir) => dir.join(path),
+      Err(_) => PathBuf::from(path),
+    };
+    let path = path.to_string_lossy();
+    writer::write_config_snapshot(&path, &benchmark_doc, &config, args);
+  }
+
+  if benchmark.plan.is_empty() {
     eprintln!("Empty benchmark. Exiting.");
     std::process::exit(1);
   }
@@ -141,7 +790,7 @@ pub fn execute(args: &FlattenedCli) -> BenchmarkResult {
   let benchmark = Arc::new(benchmark);
 
   let pool_store: PoolStore = PoolStore::new();
-  let pool = Arc::new(Mutex::new(pool_store));
+  let pool = Arc::new(RwLock::new(pool_store));
 
   if args.verbose {
     if args.report_path_option.is_some() {
@@ -163,9 +812,20 @@ pub fn execute(args: &FlattenedCli) -> BenchmarkResult {
         "Iterations".yellow(),
         config.iterations.to_string().purple()
       );
-      println!("{} {}", "Rampup".yellow(), config.rampup.to_string().purple());
+      println!(
+        "{} {}",
+        "Rampup".yellow(),
+        format!("{}s", config.rampup.as_secs_f64()).purple()
+      );
     }
 
+    show_metadata(&config.metadata);
+    println!("{} {}", "Seed".yellow(), config.seed.to_string().purple());
+    println!("{} {}", "Run id".yellow(), config.run_id.purple());
+
+    if let Some(base_url) = &config.base_url {
+      println!("{} {}", "Base URL".yellow(), base_url.green());
+    }
     println!("{}", "URLs".yellow());
     for (key, val) in config.urls.iter() {
       println!("  {}: {}", key.purple(), val.green());
@@ -178,49 +838,355 @@ pub fn execute(args: &FlattenedCli) -> BenchmarkResult {
     println!();
   }
 
-  let threads = std::cmp::min(num_cpus::get(), config.concurrency as usize);
-  let rt = runtime::Builder::new_multi_thread()
-    .enable_all()
-    .worker_threads(threads)
-    .build()
-    .unwrap();
+  if config.dns.mode() == DnsMode::Once && args.report_path_option.is_none()
+  {
+    let rt = runtime::Builder::new_current_thread().enable_all().build();
+    rt.unwrap().block_on(preresolve(&config));
+  }
+
+  let dns = config.dns.clone();
+  let connections = config.connections.clone();
+  let retries = config.retries.clone();
+  let request_log = config.request_log.clone();
+  let mut result = if args.sharded {
+    run_sharded(benchmark, config, args)
+  } else {
+    let threads = std::cmp::min(num_cpus::get(), config.concurrency as usize);
+    let mut builder = runtime::Builder::new_multi_thread();
+    builder
+      .enable_all()
+      .worker_threads(args.worker_threads_option.unwrap_or(threads));
+    if let Some(threads) = args.max_blocking_threads_option {
+      builder.max_blocking_threads(threads);
+    }
+    let rt = builder.build().unwrap();
+
+    rt.block_on(async {
+      if let Some(ref report_path) = args.report_path_option {
+        let metadata = config.metadata.clone();
+        let begin = Instant::now();
+        let workers = Arc::new(Mutex::new(BTreeSet::from([0])));
+        let reports = run_iteration(
+          benchmark.clone(),
+          pool.clone(),
+          config,
+          workers,
+          begin,
+          0,
+        )
+        .await;
+        let duration = begin.elapsed().as_secs_f64();
+
+        writer::write_report(
+          report_path,
+          &args.benchmark_file,
+          &metadata,
+          reports,
+          duration,
+        );
 
-  let result = rt.block_on(async {
-    if let Some(ref report_path) = args.report_path_option {
-      let reports =
-        run_iteration(benchmark.clone(), pool.clone(), config, 0).await;
+        BenchmarkResult {
+          reports: vec![],
+          stats: RunStats::default(),
+          duration: 0.0,
+          errors: 0,
+          thresholds: Vec::new(),
+          metadata,
+        }
+      } else {
+        if config.prewarm_connections > 0 {
+          prewarm(&pool, &config).await;
+        }
 
-      writer::write_file(report_path, join(reports, ""));
+        if let Some(target) = args.target_p99_option {
+          tokio::spawn(handle_interruptions(config.clone()));
+          let (stats, duration) = tune::find_capacity(
+            &benchmark,
+            &pool,
+            &config,
+            target,
+            args.probe_duration_option,
+          )
+          .await;
+          return BenchmarkResult {
+            reports: Vec::new(),
+            stats,
+            duration,
+            errors: config.error_count(),
+            thresholds: config.thresholds.clone(),
+            metadata: config.metadata.clone(),
+          };
+        }
 
-      BenchmarkResult {
-        reports: vec![],
-        duration: 0.0,
+        tokio::spawn(handle_interruptions(config.clone()));
+        let stream = args
+          .stream_option
+          .as_deref()
+          .map(|path| Mutex::new(writer::create_stream(path)));
+
+        let begin = Instant::now();
+        let (reports, stats) = run_shard(
+          benchmark,
+          pool,
+          config.clone(),
+          args,
+          stream.as_ref(),
+          Shard::WHOLE,
+        )
+        .await;
+        let duration = begin.elapsed().as_secs_f64();
+
+        if let Some(stream) = stream {
+          writer::flush_stream(&mut stream.into_inner().unwrap());
+        }
+
+        BenchmarkResult {
+          reports,
+          stats,
+          duration,
+          errors: config.error_count(),
+          thresholds: config.thresholds.clone(),
+          metadata: config.metadata.clone(),
+        }
       }
-    } else {
-      let children = (0..config.iterations).map(|iteration| {
+    })
+  };
+  if !args.keep_cwd {
+    original_dir.and_then(set_current_dir).unwrap_or_else(|err| {
+      eprintln!("Couldn't reset working directory: {}", err)
+    });
+  }
+  result.stats.dns = dns.stats();
+  result.stats.connections = connections.stats();
+  result.stats.retried_iterations = retries.load(Ordering::SeqCst);
+  if let Some(log) = request_log {
+    writer::flush_stream(&mut log.lock().unwrap_or_else(PoisonError::into_inner));
+  }
+  result
+}
+
+/// Share of the concurrency slots, and so of the iterations, run by one
+/// runtime. There's a single one unless `--sharded`.
+#[derive(Clone, Copy)]
+struct Shard {
+  index: u64,
+  count: u64,
+}
+
+impl Shard {
+  const WHOLE: Shard = Shard {
+    index: 0,
+    count: 1,
+  };
+
+  /// Every `count`th slot, so ids stay unique across the shards
+  fn slots(&self, concurrency: u64) -> impl Iterator<Item = u64> {
+    (self.index..concurrency).step_by(self.count as usize)
+  }
+}
+
+/// Runs the iterations of a shard, and returns their stats and reports,
+/// unless they are streamed
+async fn run_shard(
+  benchmark: Arc<Benchmark>,
+  pool: Pool,
+  config: Arc<Config>,
+  args: &FlattenedCli,
+  stream: Option<&Mutex<BufWriter<File>>>,
+  shard: Shard,
+) -> (Vec<Reports>, RunStats) {
+  let slots: BTreeSet<u64> = shard.slots(config.concurrency).collect();
+  let concurrency = slots.len();
+  let workers: Workers = Arc::new(Mutex::new(slots.clone()));
+  let start = Instant::now();
+  let mut buffered = if config.virtual_users {
+    let users = slots.into_iter().map(|vu| {
+      run_virtual_user(
+        benchmark.clone(),
+        pool.clone(),
+        config.clone(),
+        start,
+        vu,
+      )
+      .boxed_local()
+    });
+    stream::select_all(users).boxed_local()
+  } else {
+    let children = (shard.index..config.iterations)
+      .step_by(shard.count as usize)
+      .take_while(|_| !config.is_halted())
+      .map(|iteration| {
         run_iteration(
           benchmark.clone(),
           pool.clone(),
           config.clone(),
+          workers.clone(),
+          start,
           iteration,
         )
       });
+    stream::iter(children).buffer_unordered(concurrency).boxed_local()
+  };
 
-      let buffered =
-        stream::iter(children).buffer_unordered(config.concurrency as usize);
+  let mut deadline = Box::pin(drain_deadline(config.clone()));
+  let mut stats = RunStats::default();
+  let mut saturation =
+    args.stop_on_saturation_option.map(tune::SaturationWatch::new);
 
-      let begin = Instant::now();
-      let reports: Vec<Vec<Report>> = buffered.collect::<Vec<_>>().await;
-      let duration = begin.elapsed().as_secs_f64();
-
-      BenchmarkResult {
-        reports,
-        duration,
+  let mut reports: Vec<Vec<Report>> = Vec::new();
+  loop {
+    match future::select(buffered.next(), deadline.as_mut()).await {
+      Either::Left((Some(iteration_reports), _)) => {
+        stats.record(&iteration_reports);
+        if let Some(saturation) = saturation.as_mut() {
+          // Iterations finishing after the halt would report it again
+          if !config.is_halted()
+            && saturation.record(&iteration_reports, &config)
+          {
+            config.halt();
+          }
+        }
+        match stream {
+          Some(stream) => {
+            let mut stream =
+              stream.lock().unwrap_or_else(PoisonError::into_inner);
+            writer::stream_reports(&mut stream, &iteration_reports)
+          }
+          None => reports.push(iteration_reports),
+        }
+      }
+      Either::Left((None, _)) => break,
+      Either::Right(_) => {
+        eprintln!(
+          "{} Iterations didn't finish in {}s, leaving them behind",
+          "WARNING:".yellow().bold(),
+          config.timeout.as_secs_f64()
+        );
+        break;
       }
     }
+  }
+
+  (reports, stats)
+}
+
+/// Runs the benchmark on one single-threaded runtime per thread, each with
+/// its own connections and share of the concurrency, for `--sharded`
+fn run_sharded(
+  benchmark: Arc<Benchmark>,
+  config: Arc<Config>,
+  args: &FlattenedCli,
+) -> BenchmarkResult {
+  let shards = args
+    .worker_threads_option
+    .unwrap_or_else(num_cpus::get)
+    .clamp(1, config.concurrency.max(1) as usize) as u64;
+  let stream = args
+    .stream_option
+    .as_deref()
+    .map(|path| Mutex::new(writer::create_stream(path)));
+
+  let begin = Instant::now();
+  let results: Vec<(Vec<Reports>, RunStats)> = thread::scope(|scope| {
+    let handles: Vec<_> = (0..shards)
+      .map(|index| {
+        let benchmark = benchmark.clone();
+        let config = config.clone();
+        let stream = stream.as_ref();
+        scope.spawn(move || {
+          let mut builder = runtime::Builder::new_current_thread();
+          builder.enable_all();
+          if let Some(threads) = args.max_blocking_threads_option {
+            builder.max_blocking_threads(threads);
+          }
+          let rt = builder.build().unwrap();
+
+          rt.block_on(async {
+            let pool = Arc::new(RwLock::new(PoolStore::new()));
+            if config.prewarm_connections > 0 {
+              prewarm(&pool, &config).await;
+            }
+            if index == 0 {
+              tokio::spawn(handle_interruptions(config.clone()));
+            }
+            let shard = Shard {
+              index,
+              count: shards,
+            };
+            run_shard(benchmark, pool, config, args, stream, shard).await
+          })
+        })
+      })
+      .collect();
+    handles.into_iter().map(|handle| handle.join().unwrap()).collect()
   });
-  original_dir.and_then(set_current_dir).unwrap_or_else(|err| {
-    eprintln!("Couldn't reset working directory: {}", err)
-  });
-  result
+  let duration = begin.elapsed().as_secs_f64();
+
+  if let Some(stream) = stream {
+    writer::flush_stream(&mut stream.into_inner().unwrap());
+  }
+
+  let mut reports = Vec::new();
+  let mut stats = RunStats::default();
+  for (shard_reports, shard_stats) in results {
+    reports.extend(shard_reports);
+    stats.merge(shard_stats);
+  }
+
+  BenchmarkResult {
+    reports,
+    stats,
+    duration,
+    errors: config.error_count(),
+    thresholds: config.thresholds.clone(),
+    metadata: config.metadata.clone(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Runs one iteration of a plan failing until its `passing_try`
+  fn retried_iteration(passing_try: usize) -> Vec<Report> {
+    let doc = format!("iteration_retries: 2\nplan:\n  - metric:\n      name: attempts\n      type: counter\n  - counter_inc:\n      key: tries\n    assign: tries\n  - assert:\n      key: tries\n      value: {passing_try}\n");
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(&doc).unwrap();
+    let (config, benchmark): (Config, Benchmark) = From::from(&doc);
+    let pool = Pool::default();
+    let user = User {
+      vu: 0,
+      worker: 0,
+    };
+
+    let rt = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    rt.block_on(run_user_iteration(&benchmark, &pool, &config, user, 7, &mut None))
+  }
+
+  #[test]
+  fn flags_every_attempt_of_recovered_iterations() {
+    let reports = retried_iteration(2);
+    let flags: Vec<_> = reports.iter().map(|report| (report.retried, report.superseded, report.iteration)).collect();
+    assert_eq!(flags, [(true, true, 7), (true, false, 7)]);
+
+    let mut stats = RunStats::default();
+    stats.record(&reports);
+    assert_eq!(stats.metrics["attempts"].count, 1);
+  }
+
+  #[test]
+  fn flags_every_attempt_of_failed_iterations() {
+    let reports = retried_iteration(5);
+    let flags: Vec<_> = reports.iter().map(|report| (report.retried, report.superseded)).collect();
+    assert_eq!(flags, [(true, true), (true, true), (true, false)]);
+
+    let mut stats = RunStats::default();
+    stats.record(&reports);
+    assert_eq!(stats.metrics["attempts"].count, 1);
+  }
+
+  #[test]
+  fn leaves_iterations_without_retries_unflagged() {
+    let reports = retried_iteration(1);
+    assert!(reports.iter().all(|report| !report.retried && !report.superseded));
+  }
 }
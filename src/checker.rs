@@ -1,38 +1,191 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use colored::*;
+use linked_hash_map::LinkedHashMap;
+use serde::{Deserialize, Serialize};
 
 use crate::actions::Report;
+use crate::parse::{Metadata, Threshold};
+use crate::{compute_stats, DrillStats, RunStats};
 use crate::reader::get_file;
+use crate::writer::{self, ReportFile, REPORT_VERSION};
+
+/// Aggregated durations of a step, in milliseconds
+#[derive(Serialize, Deserialize)]
+struct StepStats {
+  mean: f64,
+  median: f64,
+  p95: f64,
+  p99: f64,
+}
+
+/// Format of the files written by `--save-baseline` and `drill baseline
+/// save`. Bump `BASELINE_VERSION` on incompatible changes.
+#[derive(Serialize, Deserialize)]
+struct BaselineFile {
+  version: u32,
+  benchmark: String,
+  /// Name, description and version of the benchmark, when it sets them
+  #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+  metadata: Metadata,
+  /// Unix time the run started at
+  started_at: u64,
+  duration: f64,
+  iterations: usize,
+  requests: usize,
+  steps: BTreeMap<String, StepStats>,
+}
+
+const BASELINE_VERSION: u32 = 1;
+
+/// A compare file is either a baseline or the reports of a `--report` run,
+/// possibly written before reports were versioned.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Baseline {
+  Versioned(BaselineFile),
+  Report(ReportFile),
+  Samples(Vec<Report>),
+}
+
+impl From<&DrillStats> for StepStats {
+  fn from(stats: &DrillStats) -> Self {
+    StepStats {
+      mean: stats.mean_duration(),
+      median: stats.median_duration(),
+      p95: stats.value_at_quantile(0.95),
+      p99: stats.value_at_quantile(0.99),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Metric {
+  Mean,
+  Median,
+  P95,
+  P99,
+}
+
+impl Metric {
+  fn of(&self, stats: &StepStats) -> f64 {
+    match self {
+      Metric::Mean => stats.mean,
+      Metric::Median => stats.median,
+      Metric::P95 => stats.p95,
+      Metric::P99 => stats.p99,
+    }
+  }
+
+  fn label(&self) -> &'static str {
+    match self {
+      Metric::Mean => "mean",
+      Metric::Median => "median",
+      Metric::P95 => "p95",
+      Metric::P99 => "p99",
+    }
+  }
+}
+
+/// Allowed slowdown, either absolute or relative to the recorded value
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Limit {
+  Ms(f64),
+  Percent(f64),
+}
+
+impl Limit {
+  fn exceeded(&self, recorded: f64, current: f64) -> bool {
+    let delta_ms = current - recorded;
+    match self {
+      Limit::Ms(ms) => delta_ms > *ms,
+      Limit::Percent(percent) => delta_ms > recorded * percent / 100.0,
+    }
+  }
+}
 
+/// Compares the stats of every step against the baseline stored at
+/// `filepath`. Steps are matched by name, so repeated names (`with_items`,
+/// several iterations) are aggregated together.
 pub fn compare(
-  list_reports: &[Vec<Report>],
+  run_stats: &RunStats,
   filepath: &str,
   threshold: &str,
 ) -> Result<(), i32> {
-  let threshold_value = match threshold.parse::<f64>() {
-    Ok(v) => v,
-    _ => panic!("arrrgh"),
-  };
+  let thresholds = parse_thresholds(threshold).unwrap_or_else(|err| {
+    eprintln!("{} Invalid threshold: {}", "ERROR:".red().bold(), err);
+    std::process::exit(1)
+  });
 
   let file = get_file(filepath);
 
-  let docs: Vec<serde_yaml::Value> = serde_yaml::from_reader(file).unwrap();
-  let doc = &docs[0];
-  let items = doc.as_sequence().unwrap();
+  let recorded = match serde_yaml::from_reader(file) {
+    Ok(Baseline::Versioned(baseline)) => {
+      if baseline.version != BASELINE_VERSION {
+        eprintln!(
+          "{} {}: unsupported baseline version {}",
+          "ERROR:".red().bold(),
+          filepath,
+          baseline.version
+        );
+        std::process::exit(1)
+      }
+      baseline.steps.into_iter().collect()
+    }
+    Ok(Baseline::Report(report)) => {
+      if report.version != REPORT_VERSION {
+        eprintln!(
+          "{} {}: unsupported report version {}",
+          "ERROR:".red().bold(),
+          filepath,
+          report.version
+        );
+        std::process::exit(1)
+      }
+      stats_by_name(report.reports.into_iter())
+    }
+    Ok(Baseline::Samples(reports)) => stats_by_name(reports.into_iter()),
+    Err(err) => {
+      eprintln!("{} {}: {}", "ERROR:".red().bold(), filepath, err);
+      std::process::exit(1)
+    }
+  };
+  let current: LinkedHashMap<_, _> = run_stats
+    .steps
+    .iter()
+    .map(|(name, stats)| (name.clone(), StepStats::from(stats)))
+    .collect();
   let mut slow_counter = 0;
 
   println!();
 
-  for report in list_reports {
-    for (i, report_item) in report.iter().enumerate() {
-      let recorded_duration = items[i]["duration"].as_f64().unwrap();
-      let delta_ms = report_item.duration - recorded_duration;
+  for (name, stats) in current.iter() {
+    let recorded_stats = match recorded.get(name) {
+      Some(recorded_stats) => recorded_stats,
+      None => {
+        println!("{:width$} {}", name.green(), "added".cyan(), width = 25);
+        continue;
+      }
+    };
+
+    for (metric, limit) in thresholds.iter() {
+      let recorded_ms = metric.of(recorded_stats);
+      let current_ms = metric.of(stats);
 
-      if delta_ms > threshold_value {
+      if limit.exceeded(recorded_ms, current_ms) {
+        let slowdown = match limit {
+          Limit::Ms(_) => format!("{}ms", (current_ms - recorded_ms).round()),
+          Limit::Percent(_) => format!(
+            "{}%",
+            ((current_ms - recorded_ms) / recorded_ms * 100.0).round()
+          ),
+        };
         println!(
-          "{:width$} is {}{} slower than before",
-          report_item.name.green(),
-          delta_ms.round().to_string().red(),
-          "ms".red(),
+          "{:width$} {:6} is {} slower than before",
+          name.green(),
+          metric.label(),
+          slowdown.red(),
           width = 25
         );
 
@@ -41,9 +194,182 @@ pub fn compare(
     }
   }
 
+  for name in recorded.keys().filter(|name| !current.contains_key(*name)) {
+    println!("{:width$} {}", name.green(), "removed".cyan(), width = 25);
+  }
+
   if slow_counter == 0 {
     Ok(())
   } else {
     Err(slow_counter)
   }
 }
+
+/// Checks the `thresholds` of the benchmark against its custom metrics,
+/// printing the outcome of each. Metrics without samples fail them.
+pub fn check_thresholds(
+  run_stats: &RunStats,
+  thresholds: &[Threshold],
+  duration: f64,
+) -> bool {
+  if thresholds.is_empty() {
+    return true;
+  }
+
+  println!();
+  let mut passed = true;
+  for threshold in thresholds {
+    let stats = run_stats.metrics.get(&threshold.metric);
+    let value = stats
+      .and_then(|stats| stats.stat(threshold.stat, duration))
+      .map(|value| if threshold.percent { value * 100.0 } else { value });
+    let held =
+      value.map(|value| threshold.operator.holds(value, threshold.value));
+
+    let outcome = match (stats, held) {
+      (None, _) => "no samples".red(),
+      (Some(_), None) => "not available for this metric".red(),
+      (Some(_), Some(true)) => "passed".green(),
+      (Some(_), Some(false)) => "failed".red(),
+    };
+    passed &= held == Some(true);
+    let unit = if threshold.percent { "%" } else { "" };
+    let actual = value.map(|value| format!("({value:.2}{unit})"));
+    println!(
+      "{:width$} {} {}",
+      threshold.expression.green(),
+      outcome.bold(),
+      actual.unwrap_or_default().purple(),
+      width = 25
+    );
+  }
+  passed
+}
+
+/// Writes the run metadata and the stats of every step, to be used later
+/// with `--compare` or `drill baseline check`
+pub fn save_baseline(
+  path: &str,
+  benchmark: &str,
+  metadata: &Metadata,
+  run_stats: &RunStats,
+  duration: f64,
+) {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+  let baseline = BaselineFile {
+    version: BASELINE_VERSION,
+    benchmark: benchmark.to_owned(),
+    metadata: metadata.clone(),
+    started_at: (now.as_secs_f64() - duration) as u64,
+    duration,
+    iterations: run_stats.iterations,
+    requests: run_stats.global.total_requests,
+    steps: run_stats
+      .steps
+      .iter()
+      .map(|(name, stats)| (name.clone(), StepStats::from(stats)))
+      .collect(),
+  };
+
+  // YAML being a superset of JSON, both can be compared against
+  let content = if path.ends_with(".json") {
+    serde_json::to_string_pretty(&baseline).unwrap()
+  } else {
+    serde_yaml::to_string(&baseline).unwrap()
+  };
+  writer::write_file(path, content);
+  println!();
+  println!("{} {}", "Saved baseline into".green(), path.purple());
+}
+
+/// A bare number keeps applying to the mean, `median=5,p99=20` sets a
+/// threshold per metric. Values ending in `%` are relative to the baseline.
+fn parse_thresholds(threshold: &str) -> Result<Vec<(Metric, Limit)>, String> {
+  threshold
+    .split(',')
+    .map(|part| {
+      let (metric, value) = match part.split_once('=') {
+        Some((metric, value)) => (metric.trim(), value),
+        None => ("mean", part),
+      };
+      let metric = match metric {
+        "mean" => Metric::Mean,
+        "median" => Metric::Median,
+        "p95" => Metric::P95,
+        "p99" => Metric::P99,
+        _ => return Err(format!("unknown metric '{metric}'")),
+      };
+      let value = value.trim();
+      let limit = match value.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(Limit::Percent),
+        None => value.parse::<f64>().map(Limit::Ms),
+      }
+      .map_err(|_| format!("'{value}' is not a number of ms or percent"))?;
+      Ok((metric, limit))
+    })
+    .collect()
+}
+
+fn stats_by_name(
+  reports: impl Iterator<Item = Report>,
+) -> LinkedHashMap<String, StepStats> {
+  // Custom metrics and the attempts of retried iterations aren't steps
+  let measured = |report: &Report| report.metric.is_none() && !report.superseded;
+  let mut group_by_name = LinkedHashMap::new();
+  for report in reports.filter(measured) {
+    group_by_name
+      .entry(report.name.clone())
+      .or_insert_with(Vec::new)
+      .push(report);
+  }
+
+  group_by_name
+    .into_iter()
+    .map(|(name, reports)| (name, StepStats::from(&compute_stats(&reports))))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parse::MetricKind;
+
+  #[test]
+  fn groups_reports_by_step_name() {
+    let reports = vec![
+      Report::new("Login".to_owned(), 10.0, 200, true),
+      Report::new("Home".to_owned(), 5.0, 200, true),
+      Report::new("Login".to_owned(), 30.0, 200, true),
+      Report::metric("Login".to_owned(), MetricKind::Counter, 1_000.0),
+    ];
+    let stats = stats_by_name(reports.into_iter());
+
+    let names: Vec<_> = stats.keys().map(String::as_str).collect();
+    assert_eq!(names, ["Login", "Home"]);
+    assert!((stats["Login"].mean - 20.0).abs() < 0.1);
+    assert!((stats["Home"].mean - 5.0).abs() < 0.1);
+  }
+
+  #[test]
+  fn parses_thresholds_per_metric() {
+    assert_eq!(parse_thresholds("12"), Ok(vec![(Metric::Mean, Limit::Ms(12.0))]));
+    assert_eq!(
+      parse_thresholds("median=5, p99 = 20.5"),
+      Ok(vec![(Metric::Median, Limit::Ms(5.0)), (Metric::P99, Limit::Ms(20.5))])
+    );
+    assert_eq!(parse_thresholds("p42=1"), Err("unknown metric 'p42'".to_owned()));
+    assert_eq!(parse_thresholds("p95=fast"), Err("'fast' is not a number of ms or percent".to_owned()));
+  }
+
+  #[test]
+  fn applies_percentage_thresholds() {
+    assert_eq!(parse_thresholds("p99=10%"), Ok(vec![(Metric::P99, Limit::Percent(10.0))]));
+    assert_eq!(parse_thresholds("5 %"), Ok(vec![(Metric::Mean, Limit::Percent(5.0))]));
+
+    assert!(!Limit::Percent(10.0).exceeded(200.0, 220.0));
+    assert!(Limit::Percent(10.0).exceeded(200.0, 221.0));
+    assert!(!Limit::Ms(10.0).exceeded(200.0, 210.0));
+    assert!(Limit::Ms(10.0).exceeded(200.0, 211.0));
+    assert!(!Limit::Percent(10.0).exceeded(200.0, 100.0));
+  }
+}
@@ -1,32 +1,128 @@
-use crate::args::FlattenedCli;
-use crate::db::DbDefinition;
-use crate::parse::BenchmarkDoc;
+use crate::args::{FlattenedCli, TimeUnit};
+use crate::benchmark::Context;
+use crate::db::{DbDefinition, YamlDbDefinition};
+use crate::connections::ConnectionTracker;
+use crate::dns::{DnsCache, IpFamily};
+use crate::error::Error;
+use crate::interpolator::Interpolator;
+use crate::parse::{
+  BenchmarkDoc, Metadata, PlanItem, Redaction, SuccessStatus, Threshold,
+  UrlJoin,
+};
+use crate::output::Output;
+use crate::plugin::WasmPlugin;
+use crate::writer;
+use colored::*;
+use serde_json::{json, Map, Value};
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::IpAddr;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::runtime;
 
 // const NITERATIONS: i64 = 1;
 // const NRAMPUP: i64 = 0;
 const TIMEOUT: u64 = 30;
+const REDACTED: &str = "[REDACTED]";
 
 #[derive(Debug, Default, Clone)]
 pub struct Config {
+  pub metadata: Metadata,
+  pub base_url: Option<String>,
+  pub url_join: UrlJoin,
   pub urls: BTreeMap<String, String>,
+  pub default_headers: BTreeMap<String, String>,
+  /// Default headers of the `urls` entries, by key
+  pub url_headers: BTreeMap<String, BTreeMap<String, String>>,
+  pub local_address: Option<IpAddr>,
+  /// Local addresses of the `urls` entries setting one, by key
+  pub url_local_addresses: BTreeMap<String, IpAddr>,
   pub global: BTreeMap<String, String>,
   pub dbs: BTreeMap<String, DbDefinition>,
+  /// Secret names to the commands printing their values
+  pub secret_commands: BTreeMap<String, String>,
+  /// Secret values, filled by `fetch_secrets`
+  pub secrets: BTreeMap<String, String>,
+  /// Headers, context keys and patterns masked along with the secrets
+  pub redactions: Vec<Redaction>,
+  pub plugins: BTreeMap<String, WasmPlugin>,
+  pub templates: BTreeMap<String, Vec<PlanItem>>,
   pub concurrency: u64,
   pub iterations: u64,
+  pub virtual_users: bool,
+  pub pacing: Option<Duration>,
+  pub iteration_retries: usize,
+  pub prewarm_connections: usize,
+  pub success_status: SuccessStatus,
+  pub expected_failures: Option<SuccessStatus>,
+  pub conditional_requests: bool,
+  pub request_id: Option<String>,
+  pub propagate_trace: bool,
+  pub thresholds: Vec<Threshold>,
   pub relaxed_interpolations: bool,
   pub no_check_certificate: bool,
-  pub rampup: u64,
-  pub quiet: bool,
-  pub nanosec: bool,
-  pub timeout: u64,
+  pub rampup: Duration,
+  /// Lines printed while running, see `-q` and `--only-errors`
+  pub output: Output,
+  pub time_unit: TimeUnit,
+  pub timeout: Duration,
+  /// Duration requests are flagged as slow past, see `--slow-threshold`
+  pub slow_threshold: Option<Duration>,
+  /// File every request is logged to, see `--request-log`
+  pub request_log: Option<Arc<Mutex<BufWriter<File>>>>,
+  /// Answers and stats of the DNS lookups, see `--dns`
+  pub dns: Arc<DnsCache>,
+  /// New and reused connections of the responses, by host
+  pub connections: Arc<ConnectionTracker>,
   pub verbose: bool,
+  /// Size the bodies logged by `--verbose` are truncated at
+  pub body_limit: Option<usize>,
+  pub pretty_json: bool,
+  pub seed: u64,
+  /// Directory of the benchmark file, commands run in and relative downloads
+  /// are written to
+  pub dir: PathBuf,
+  /// Random id of the run, to correlate its requests with server logs
+  pub run_id: String,
+  pub halt: Arc<AtomicBool>,
+  /// Number of iterations aborted by an error
+  pub errors: Arc<AtomicUsize>,
+  /// Number of iterations run again after an error, see `iteration_retries`
+  pub retries: Arc<AtomicUsize>,
+  /// Values shared by all iterations, see `increment_shared`
+  pub shared: Arc<Mutex<Map<String, Value>>>,
 }
 
 impl From<&BenchmarkDoc> for Config {
   fn from(doc: &BenchmarkDoc) -> Self {
     Config {
-      urls: doc.urls.clone(),
+      metadata: Metadata::from(doc),
+      base_url: doc.base_url.clone(),
+      url_join: doc.url_join,
+      urls: doc
+        .urls
+        .iter()
+        .map(|(key, base)| (key.clone(), base.url.clone()))
+        .collect(),
+      default_headers: doc.default_headers.clone(),
+      url_headers: doc
+        .urls
+        .iter()
+        .filter(|(_, base)| !base.default_headers.is_empty())
+        .map(|(key, base)| (key.clone(), base.default_headers.clone()))
+        .collect(),
+      local_address: doc.local_address,
+      url_local_addresses: doc
+        .urls
+        .iter()
+        .filter_map(|(key, base)| Some((key.clone(), base.local_address?)))
+        .collect(),
       global: {
         let mut global = doc.global.clone();
         global.append(&mut doc.env.clone());
@@ -36,36 +132,322 @@ impl From<&BenchmarkDoc> for Config {
         .databases
         .clone()
         .into_iter()
-        .map(|(k, v)| (k, DbDefinition::from(v)))
+        .map(|(name, definition)| (name, load_db(definition)))
+        .collect(),
+      secret_commands: doc.secrets.clone(),
+      secrets: BTreeMap::new(),
+      redactions: doc.redact.clone(),
+      plugins: doc
+        .plugins
+        .iter()
+        .map(|(name, path)| (name.clone(), load_plugin(path)))
         .collect(),
+      templates: doc.templates.clone(),
       concurrency: doc.concurrency.min(doc.iterations as usize) as u64,
       iterations: doc.iterations,
+      virtual_users: doc.virtual_users,
+      pacing: doc.pacing,
+      iteration_retries: doc.iteration_retries,
+      prewarm_connections: doc.prewarm_connections,
+      success_status: doc.success_status.clone(),
+      expected_failures: doc.expected_failures.clone(),
+      conditional_requests: doc.conditional_requests,
+      request_id: doc.request_id.clone(),
+      propagate_trace: doc.propagate_trace,
+      thresholds: doc.thresholds.clone(),
       relaxed_interpolations: false,
       no_check_certificate: false,
       rampup: doc.rampup,
-      quiet: false,
-      nanosec: false,
-      timeout: TIMEOUT,
+      output: Output::default(),
+      time_unit: TimeUnit::default(),
+      timeout: Duration::from_secs(TIMEOUT),
+      slow_threshold: None,
+      request_log: None,
+      dns: Arc::new(DnsCache::default()),
+      connections: Arc::new(ConnectionTracker::default()),
       verbose: false,
+      body_limit: None,
+      pretty_json: false,
+      seed: doc.seed.unwrap_or_else(rand::random),
+      dir: PathBuf::from("."),
+      run_id: format!("{:016x}", rand::random::<u64>()),
+      halt: Arc::new(AtomicBool::new(false)),
+      errors: Arc::new(AtomicUsize::new(0)),
+      retries: Arc::new(AtomicUsize::new(0)),
+      shared: Arc::new(Mutex::new(doc.shared.clone().into_iter().collect())),
     }
   }
 }
 
 impl Config {
   pub fn with_args(mut self, args: &FlattenedCli) -> Config {
-    self.quiet = args.quiet;
-    self.nanosec = args.nanosec;
-    self.timeout =
-      args.timeout.as_ref().map_or(10, |t| t.parse().unwrap_or(10));
+    self.output = Output::new(args.quiet.into(), args.only_errors);
+    self.time_unit = args.time_unit;
+    self.timeout = args.timeout.unwrap_or(Duration::from_secs(10));
+    self.slow_threshold = args.slow_threshold_option;
+    let family = match (args.ipv4, args.ipv6) {
+      (true, _) => Some(IpFamily::V4),
+      (_, true) => Some(IpFamily::V6),
+      _ => None,
+    };
+    self.dns = Arc::new(DnsCache::new(args.dns, family));
+    self.request_log = args
+      .request_log_option
+      .as_deref()
+      .map(|path| Arc::new(Mutex::new(writer::create_stream(path))));
     self.verbose = args.verbose;
+    self.body_limit = args.body_limit;
+    self.pretty_json = args.pretty_json;
     self.relaxed_interpolations = args.relaxed_interpolations;
     self.no_check_certificate = args.no_check_certificate;
+    if let Some(seed) = args.seed {
+      self.seed = seed;
+    }
+    self.global.extend(args.vars.iter().cloned());
+    self
+  }
+
+  /// Connects to the databases with `validate`, exiting when one of them
+  /// can't be reached
+  pub fn validate_databases(self) -> Config {
+    let dbs: Vec<_> = self.dbs.iter().filter(|(_, db)| db.validate).collect();
+    if dbs.is_empty() {
+      return self;
+    }
+
+    // Connection strings may use the global variables and the secrets
+    let mut context = Map::new();
+    context.insert("global".to_owned(), json!(self.global));
+    for (name, value) in self.secrets.iter() {
+      context.insert(name.to_owned(), json!(value));
+    }
+    let interpolator = Interpolator::new(&context);
+
+    let rt = runtime::Builder::new_current_thread().enable_all().build();
+    let checked = rt.unwrap().block_on(async {
+      for (name, db) in dbs {
+        db.check(name, &interpolator).await?;
+        if self.verbose {
+          println!("{} {}", "Connected to".yellow(), name.green());
+        }
+      }
+      Ok::<_, Error>(())
+    });
+    if let Err(err) = checked {
+      let err = self.redact(&err.to_string());
+      eprintln!("{} {}", "ERROR:".red().bold(), err);
+      std::process::exit(1);
+    }
+    self
+  }
+
+  /// Runs the command of every secret, exiting when one of them fails
+  pub fn fetch_secrets(mut self) -> Config {
+    for (name, command) in self.secret_commands.iter() {
+      let output = Command::new("bash")
+        .args(["-c", "--", command])
+        .current_dir(&self.dir)
+        .output();
+      match output {
+        Ok(output) if output.status.success() => {
+          let value = String::from_utf8_lossy(&output.stdout);
+          self.secrets.insert(name.clone(), value.trim_end().to_owned());
+        }
+        Ok(output) => {
+          eprintln!(
+            "{} Couldn't fetch secret '{}': {}",
+            "ERROR:".red().bold(),
+            name,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+          );
+          std::process::exit(1);
+        }
+        Err(err) => {
+          eprintln!(
+            "{} Couldn't fetch secret '{}': {}",
+            "ERROR:".red().bold(),
+            name,
+            err
+          );
+          std::process::exit(1);
+        }
+      }
+    }
     self
   }
 
+  /// Hides secret values, and the matches of the `redact` patterns, from
+  /// text about to be logged
+  pub fn redact(&self, text: &str) -> String {
+    self.redact_values(text, &[])
+  }
+
+  /// Like `redact`, hiding `values` too, like the ones of `redacted_values`
+  pub fn redact_values(&self, text: &str, values: &[String]) -> String {
+    let text = self
+      .secrets
+      .values()
+      .chain(values)
+      .filter(|secret| !secret.is_empty())
+      .fold(text.to_owned(), |text, secret| text.replace(secret, REDACTED));
+    self.redactions.iter().fold(text, |text, redaction| match redaction {
+      Redaction::Pattern(pattern) => {
+        pattern.replace_all(&text, REDACTED).into_owned()
+      }
+      _ => text,
+    })
+  }
+
+  /// Values of the context keys listed in `redact`
+  pub fn redacted_values(&self, context: &Context) -> Vec<String> {
+    self
+      .redactions
+      .iter()
+      .filter_map(|redaction| match redaction {
+        Redaction::Key(key) => context.get(key),
+        _ => None,
+      })
+      .filter_map(|value| match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+      })
+      .collect()
+  }
+
+  /// Whether the values of the header `name` are listed in `redact`
+  pub fn redacts_header(&self, name: &str) -> bool {
+    self.redactions.iter().any(|redaction| match redaction {
+      Redaction::Header(header) => header.eq_ignore_ascii_case(name),
+      _ => false,
+    })
+  }
+
+  /// Headers every request to the `base` url starts with, the ones of the
+  /// base url taking precedence
+  pub fn default_headers(
+    &self,
+    base: Option<&str>,
+  ) -> BTreeMap<String, String> {
+    let mut headers = self.default_headers.clone();
+    if let Some(url_headers) = base.and_then(|base| self.url_headers.get(base))
+    {
+      headers.extend(url_headers.clone());
+    }
+    headers
+  }
+
+  /// Whether a request taking `duration_ms` is flagged as slow
+  pub fn is_slow(&self, duration_ms: f64) -> bool {
+    self
+      .slow_threshold
+      .is_some_and(|threshold| duration_ms > threshold.as_secs_f64() * 1_000.0)
+  }
+
+  /// Address the connections of the requests to a base are opened from
+  pub fn local_address(&self, base: Option<&str>) -> Option<IpAddr> {
+    base
+      .and_then(|base| self.url_local_addresses.get(base).copied())
+      .or(self.local_address)
+  }
+
+  /// Url of a request before interpolation, joined to its base url, or to
+  /// `base_url` when it looks relative
+  pub fn full_url(&self, base: Option<&str>, url: &str) -> String {
+    let is_relative =
+      !url.contains("://") && !url.trim_start().starts_with("{{");
+    let base_url = match base {
+      Some(base) => self.urls.get(base),
+      None if is_relative => self.base_url.as_ref(),
+      None => None,
+    };
+    match base_url {
+      // Placeholders would get escaped by the parser
+      Some(base_url) if base_url.contains("{{") || url.contains("{{") => {
+        let url = url.trim_start_matches('/');
+        format!("{}/{}", base_url.trim_end_matches('/'), url)
+      }
+      Some(base_url) => match self.url_join.join(base_url, url) {
+        Ok(joined) => joined.to_string(),
+        Err(_) => format!("{base_url}{url}"),
+      },
+      None => url.to_owned(),
+    }
+  }
+
+  /// Stops scheduling new iterations and makes running ones skip their
+  /// remaining steps
+  pub fn halt(&self) {
+    self.halt.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_halted(&self) -> bool {
+    self.halt.load(Ordering::SeqCst)
+  }
+
+  pub fn record_error(&self) {
+    self.errors.fetch_add(1, Ordering::SeqCst);
+  }
+
+  pub fn error_count(&self) -> usize {
+    self.errors.load(Ordering::SeqCst)
+  }
+
+  pub fn record_retry(&self) {
+    self.retries.fetch_add(1, Ordering::SeqCst);
+  }
+
+  /// Adds `by` to a shared counter, starting from 0, and returns its new
+  /// value. Fails when the key holds something else than an integer.
+  pub fn increment_shared(&self, key: &str, by: i64) -> Result<i64, String> {
+    let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+    let counter = shared.entry(key).or_insert(Value::from(0));
+    let value = counter
+      .as_i64()
+      .ok_or_else(|| format!("shared '{key}' isn't a counter: {counter}"))?;
+    *counter = Value::from(value + by);
+    Ok(value + by)
+  }
+
+  pub fn set_shared(&self, key: &str, value: Value) {
+    let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+    shared.insert(key.to_owned(), value);
+  }
+
+  /// Current values of the shared store, as exposed to interpolations
+  pub fn shared_values(&self) -> Value {
+    let shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+    Value::Object(shared.clone())
+  }
+
   pub fn merge_config(&mut self, other: Self) {
+    let other_shared = other.shared_values();
+    self.base_url = self.base_url.take().or(other.base_url);
     self.urls.extend(other.urls);
+    self.default_headers.extend(other.default_headers);
+    self.url_headers.extend(other.url_headers);
     self.dbs.extend(other.dbs);
+    self.secret_commands.extend(other.secret_commands);
+    self.plugins.extend(other.plugins);
+    self.templates.extend(other.templates);
     self.global.extend(other.global);
+    let mut shared = self.shared.lock().unwrap_or_else(PoisonError::into_inner);
+    for (key, value) in other_shared.as_object().into_iter().flatten() {
+      shared.entry(key).or_insert_with(|| value.clone());
+    }
   }
 }
+
+fn load_db(definition: YamlDbDefinition) -> DbDefinition {
+  DbDefinition::try_from(definition).unwrap_or_else(|err| {
+    eprintln!("{} {}", "ERROR:".red().bold(), err);
+    std::process::exit(1)
+  })
+}
+
+fn load_plugin(path: &str) -> WasmPlugin {
+  WasmPlugin::load(path).unwrap_or_else(|err| {
+    eprintln!("{} {}", "ERROR:".red().bold(), err);
+    std::process::exit(1)
+  })
+}
@@ -0,0 +1,54 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Mutex, PoisonError};
+
+use hyper::client::connect::HttpInfo;
+use reqwest::Response;
+
+/// Requests sent to a host over a new connection or a kept-alive one
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStats {
+  pub new_connections: usize,
+  pub reused_connections: usize,
+}
+
+/// Connections the responses of a run came through, told apart by their
+/// local and remote addresses
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+  seen: Mutex<HashSet<(SocketAddr, SocketAddr)>>,
+  stats: Mutex<BTreeMap<String, ConnectionStats>>,
+}
+
+impl ConnectionTracker {
+  /// Counts the connection of `response` as new the first time it's seen
+  pub fn record(&self, response: &Response) {
+    let info = match response.extensions().get::<HttpInfo>() {
+      Some(info) => info,
+      None => return,
+    };
+    let url = response.url();
+    let host = format!(
+      "{}:{}",
+      url.host_str().unwrap_or_default(),
+      url.port_or_known_default().unwrap_or_default()
+    );
+
+    let new = {
+      let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+      seen.insert((info.local_addr(), info.remote_addr()))
+    };
+    let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+    let stats = stats.entry(host).or_default();
+    if new {
+      stats.new_connections += 1;
+    } else {
+      stats.reused_connections += 1;
+    }
+  }
+
+  /// Stats by host, like `example.com:443`
+  pub fn stats(&self) -> BTreeMap<String, ConnectionStats> {
+    self.stats.lock().unwrap_or_else(PoisonError::into_inner).clone()
+  }
+}
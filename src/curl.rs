@@ -0,0 +1,199 @@
+use std::net::IpAddr;
+
+use colored::*;
+use serde_json::json;
+use url::Url;
+
+use crate::actions::{fill_path_params, sni_address};
+use crate::args::FlattenedCli;
+use crate::benchmark::{load_benchmark_doc, Benchmark, Context};
+use crate::config::Config;
+use crate::interpolator::Interpolator;
+use crate::parse::{Action, PlanItem};
+
+// Same as the one sent by `Request`
+const USER_AGENT: &str = "drill";
+
+/// Prints every request of the plan as a curl command. Requests are
+/// interpolated with the context of the first iteration and the first of
+/// their items; values only known mid-run, like assigned responses, are
+/// left as `{{ ... }}`.
+pub fn export(args: &FlattenedCli) {
+  let doc = load_benchmark_doc(args);
+  let (config, _): (Config, Benchmark) = From::from(&doc);
+  let config = config.with_args(args);
+
+  let mut context = Context::new();
+  context.insert("iteration".to_string(), json!(0));
+  context.insert("vu_id".to_string(), json!(0));
+  context.insert("worker_id".to_string(), json!(0));
+  context.insert("run_id".to_string(), json!(config.run_id));
+  context.insert("urls".to_string(), json!(config.urls));
+  context.insert("global".to_string(), json!(config.global));
+
+  let mut commands = Vec::new();
+  export_plan(&doc.plan, &config, &context, &mut commands);
+  for (name, command) in commands {
+    println!("{} {}", "#".dimmed(), name.green());
+    println!("{command}");
+    println!();
+  }
+}
+
+/// Collects the name and curl command of every request of the plan
+fn export_plan(
+  plan: &[PlanItem],
+  config: &Config,
+  context: &Context,
+  commands: &mut Vec<(String, String)>,
+) {
+  for item in plan {
+    match &item.action {
+      Action::Include(includes) => {
+        for include in includes {
+          export_plan(&include.doc.plan, config, context, commands)
+        }
+      }
+      Action::Transaction(items) => {
+        export_plan(items, config, context, commands)
+      }
+      Action::Use {
+        template,
+        args,
+      } => {
+        if let Some(items) = config.templates.get(template) {
+          let interpolator = Interpolator::new(context);
+          let mut template_context = context.clone();
+          for (key, value) in args {
+            let value = match value.as_str() {
+              Some(value) => json!(interpolator.resolve_known(value)),
+              None => value.clone(),
+            };
+            template_context.insert(key.clone(), value);
+          }
+          export_plan(items, config, &template_context, commands);
+        }
+      }
+      Action::Request {
+        base,
+        url,
+        path_params,
+        sni,
+        method,
+        headers,
+        body,
+        body_file,
+        with_items,
+        ..
+      } => {
+        let mut context = context.clone();
+        let first_item = with_items.as_ref().and_then(|wi| wi.items.first());
+        if let Some(map) = first_item.and_then(|item| item.as_mapping()) {
+          for (key, value) in map {
+            if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
+              context.insert(key.to_owned(), json!(value));
+            }
+          }
+        }
+        let interpolator = Interpolator::new(&context);
+
+        let url = config.full_url(base.as_deref(), url);
+        let params = path_params
+          .iter()
+          .map(|(name, value)| {
+            (name.as_str(), interpolator.resolve_known(value))
+          })
+          .collect();
+        let mut url =
+          fill_path_params(&interpolator.resolve_known(&url), &params);
+
+        // The hostname goes to the IP address of the url, like with drill
+        let mut resolve = None;
+        if let Some(sni) = sni {
+          let sni = interpolator.resolve_known(sni);
+          if let Ok(mut parsed) = Url::parse(&url) {
+            if let Ok(address) = sni_address(&mut parsed, &sni) {
+              let ip = match address.ip() {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("[{ip}]"),
+              };
+              resolve = Some(format!("{sni}:{}:{ip}", address.port()));
+              url = parsed.into();
+            }
+          }
+        }
+
+        let mut command = format!(
+          "curl -X {} {}",
+          method.to_uppercase(),
+          quote(&url)
+        );
+        if let Some(resolve) = resolve {
+          command += &format!(" --resolve {}", quote(&resolve));
+        }
+        if config.no_check_certificate {
+          command += " --insecure";
+        }
+        let mut all_headers: Vec<_> = config
+          .default_headers(base.as_deref())
+          .into_iter()
+          .filter(|(key, _)| {
+            !headers.keys().any(|k| k.eq_ignore_ascii_case(key))
+          })
+          .collect();
+        all_headers.extend(headers.clone());
+        let is_user_agent = |k: &String| k.eq_ignore_ascii_case("user-agent");
+        if !all_headers.iter().any(|(k, _)| is_user_agent(k)) {
+          let header = format!("User-Agent: {USER_AGENT}");
+          command += &format!(" \\\n  -H {}", quote(&header));
+        }
+        for (key, value) in &all_headers {
+          let value = interpolator.resolve_known(value);
+          let header = format!("{key}: {value}");
+          command += &format!(" \\\n  -H {}", quote(&header));
+        }
+        if let Some(body) = body {
+          let body = interpolator.resolve_known(body);
+          command += &format!(" \\\n  --data-raw {}", quote(&body));
+        }
+        if let Some(body_file) = body_file {
+          let body_file = format!("@{body_file}");
+          command += &format!(" \\\n  --data-binary {}", quote(&body_file));
+        }
+
+        commands.push((item.name.clone().unwrap_or_default(), command));
+      }
+      _ => {}
+    }
+  }
+}
+
+/// Single quotes a shell argument
+fn quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parse::BenchmarkDoc;
+
+  #[test]
+  fn exports_requests_of_transactions() {
+    let doc = "base: http://localhost\nplan:\n  - name: Home\n    request:\n      url: /\n  - name: Checkout\n    transaction:\n      - name: Cart\n        request:\n          url: /cart\n      - name: Pay\n        request:\n          url: /pay\n          method: POST\n          body: 'id={{ id }}'\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+    let config = Config::from(&doc);
+    let mut commands = Vec::new();
+    export_plan(&doc.plan, &config, &Context::new(), &mut commands);
+
+    let names: Vec<_> = commands.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["Home", "Cart", "Pay"]);
+    assert!(commands[2].1.starts_with("curl -X POST 'http://localhost/pay'"));
+    assert!(commands[2].1.ends_with("--data-raw 'id={{ id }}'"));
+  }
+
+  #[test]
+  fn quotes_arguments() {
+    assert_eq!(quote("it's"), r"'it'\''s'");
+  }
+}
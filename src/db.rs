@@ -2,13 +2,42 @@ use std::{convert::TryFrom, time::Duration};
 
 use serde::{Deserialize, Serialize};
 use sqlx::{postgres::PgPoolOptions, PgPool};
+use url::Url;
 
+use crate::error::Error;
 use crate::interpolator::Interpolator;
+use crate::parse::{optional_duration, serialize_optional_duration};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct YamlDbDefinition {
+  #[serde(flatten)]
+  connection: YamlDbConnection,
+  /// Connects at startup, rather than on the first query
+  #[serde(default)]
+  validate: bool,
+  max_connections: Option<u32>,
+  min_connections: Option<u32>,
+  #[serde(
+    default,
+    deserialize_with = "optional_duration",
+    serialize_with = "serialize_optional_duration",
+    skip_serializing_if = "Option::is_none"
+  )]
+  acquire_timeout: Option<Duration>,
+  #[serde(
+    default,
+    deserialize_with = "optional_duration",
+    serialize_with = "serialize_optional_duration",
+    skip_serializing_if = "Option::is_none"
+  )]
+  idle_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged, rename_all = "snake_case")]
-pub enum YamlDbDefinition {
+enum YamlDbConnection {
   ConnectionString {
+    #[serde(serialize_with = "without_password")]
     connection_string: String,
   },
   Parameterized {
@@ -17,6 +46,7 @@ pub enum YamlDbDefinition {
     host: String,
     port: String,
     user: String,
+    #[serde(serialize_with = "redacted")]
     password: String,
     dbname: String,
   },
@@ -45,41 +75,83 @@ pub enum DB {
 pub struct DbDefinition {
   typ: DbType,
   connection_string: String,
+  pub validate: bool,
+  pool: PoolSettings,
+}
+
+/// Sizing and timeouts of the connection pool of a database
+#[derive(Serialize, Debug, Clone, Copy)]
+struct PoolSettings {
+  max_connections: u32,
+  min_connections: u32,
+  acquire_timeout: Duration,
+  idle_timeout: Duration,
 }
 
-impl From<YamlDbDefinition> for DbDefinition {
-  fn from(value: YamlDbDefinition) -> Self {
-    match value {
-      YamlDbDefinition::ConnectionString {
+impl PoolSettings {
+  fn options(&self) -> PgPoolOptions {
+    PgPoolOptions::new()
+      .max_connections(self.max_connections)
+      .min_connections(self.min_connections)
+      .acquire_timeout(self.acquire_timeout)
+      .idle_timeout(self.idle_timeout)
+  }
+}
+
+fn parse_type(typ: &str) -> Result<DbType, Error> {
+  DbType::try_from(typ)
+    .map_err(|_| Error::InvalidDatabase(format!("unknown type '{typ}'")))
+}
+
+impl TryFrom<YamlDbDefinition> for DbDefinition {
+  type Error = Error;
+  fn try_from(value: YamlDbDefinition) -> Result<Self, Self::Error> {
+    let validate = value.validate;
+    let max_connections = value.max_connections.unwrap_or(MAX_CONNECTIONS);
+    let min_connections = value.min_connections.unwrap_or(0);
+    if max_connections == 0 || min_connections > max_connections {
+      return Err(Error::InvalidDatabase(format!(
+        "max_connections must be at least 1 and min_connections \
+         ({min_connections}) at most max_connections ({max_connections})"
+      )));
+    }
+    let pool = PoolSettings {
+      max_connections,
+      min_connections,
+      acquire_timeout: value.acquire_timeout.unwrap_or(TIMEOUT),
+      idle_timeout: value.idle_timeout.unwrap_or(TIMEOUT),
+    };
+    match value.connection {
+      YamlDbConnection::ConnectionString {
         connection_string,
       } => {
-        let typ = connection_string.split_once("://").unwrap().0;
-        let typ = DbType::try_from(typ)
-          .unwrap_or_else(|_| panic!("Invalid DB type '{}'.", typ));
-        Self {
-          typ,
+        let (typ, _) = connection_string.split_once("://").ok_or_else(|| {
+          Error::InvalidDatabase(format!(
+            "'{connection_string}' is not a connection string"
+          ))
+        })?;
+        Ok(Self {
+          typ: parse_type(typ)?,
           connection_string: connection_string.to_string(),
-        }
+          validate,
+          pool,
+        })
       }
-      YamlDbDefinition::Parameterized {
+      YamlDbConnection::Parameterized {
         typ,
         host,
         port,
         user,
         password,
         dbname,
-      } => Self {
-        typ: DbType::try_from(typ.as_str())
-          .unwrap_or_else(|_| panic!("Invalid DB type '{}'.", typ)),
+      } => Ok(Self {
+        typ: parse_type(&typ)?,
         connection_string: build_connection_string(
-          &serde_yaml::to_string(&typ).unwrap(),
-          &host,
-          &port,
-          &user,
-          &password,
-          &dbname,
+          &typ, &host, &port, &user, &password, &dbname,
         ),
-      },
+        validate,
+        pool,
+      }),
     }
   }
 }
@@ -96,25 +168,97 @@ fn build_connection_string(
 }
 
 impl DbDefinition {
-  pub fn to_db(&self, interpolator: &Interpolator) -> DB {
+  pub fn to_db(&self, interpolator: &Interpolator) -> Result<DB, Error> {
     match &self.typ {
       DbType::Postgres => {
-        DB::Postgres(connect_postgres(&self.connection_string, interpolator))
+        let pool = connect_postgres(
+          &self.connection_string,
+          self.pool.options(),
+          interpolator,
+        )?;
+        Ok(DB::Postgres(pool))
       }
     }
   }
+
+  /// Opens a connection to the database and closes it, to fail before the
+  /// benchmark starts rather than on its first query. Errors don't include
+  /// the password.
+  pub async fn check(
+    &self,
+    name: &str,
+    interpolator: &Interpolator<'_>,
+  ) -> Result<(), Error> {
+    let connection_string = interpolator.resolve(&self.connection_string)?;
+    let unreachable = |err: String| {
+      let (redacted, password) = redact_password(&connection_string);
+      let err = match password {
+        Some(password) if !password.is_empty() => {
+          err.replace(&password, REDACTED)
+        }
+        _ => err,
+      };
+      Error::InvalidDatabase(format!(
+        "'{name}' ({redacted}) is unreachable: {err}"
+      ))
+    };
+
+    match &self.typ {
+      DbType::Postgres => {
+        let pool = PgPoolOptions::new()
+          .max_connections(1)
+          .acquire_timeout(self.pool.acquire_timeout)
+          .connect(&connection_string)
+          .await
+          .map_err(|err| unreachable(err.to_string()))?;
+        pool.close().await;
+      }
+    }
+    Ok(())
+  }
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Drops the password of a connection string, returned apart so it can be
+/// hidden from error messages too
+fn redact_password(connection_string: &str) -> (String, Option<String>) {
+  match Url::parse(connection_string) {
+    Ok(mut url) => {
+      let password = url.password().map(str::to_owned);
+      let _ = url.set_password(None);
+      (url.to_string(), password)
+    }
+    Err(_) => (REDACTED.to_owned(), None),
+  }
+}
+
+fn without_password<S>(
+  connection_string: &str,
+  ser: S,
+) -> Result<S::Ok, S::Error>
+where
+  S: serde::Serializer,
+{
+  ser.serialize_str(&redact_password(connection_string).0)
+}
+
+fn redacted<S>(_: &str, ser: S) -> Result<S::Ok, S::Error>
+where
+  S: serde::Serializer,
+{
+  ser.serialize_str(REDACTED)
 }
 
 const MAX_CONNECTIONS: u32 = 4;
-const TIMEOUT: u64 = 30;
+const TIMEOUT: Duration = Duration::from_secs(30);
 fn connect_postgres(
   connection_string: &str,
+  options: PgPoolOptions,
   interpolator: &Interpolator,
-) -> PgPool {
-  let resolved_con_str = interpolator.resolve(connection_string);
-  PgPoolOptions::new()
-    .max_connections(MAX_CONNECTIONS)
-    .idle_timeout(Duration::from_secs(TIMEOUT))
+) -> Result<PgPool, Error> {
+  let resolved_con_str = interpolator.resolve(connection_string)?;
+  options
     .connect_lazy(&resolved_con_str)
-    .expect("Failed to connect to database")
+    .map_err(|err| Error::InvalidDatabase(err.to_string()))
 }
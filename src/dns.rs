@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use trust_dns_resolver::config::LookupIpStrategy;
+use trust_dns_resolver::{system_conf, TokioAsyncResolver};
+
+use crate::args::DnsMode;
+
+/// Lookups made by the resolvers of a run
+#[derive(Clone, Debug, Default)]
+pub struct DnsStats {
+  pub lookups: usize,
+  pub failed_lookups: usize,
+  /// Milliseconds spent in lookups, summed
+  pub total_duration: f64,
+  pub max_duration: f64,
+}
+
+impl DnsStats {
+  pub fn mean_duration(&self) -> f64 {
+    self.total_duration / self.lookups as f64
+  }
+
+  fn record(&mut self, duration: f64, success: bool) {
+    self.lookups += 1;
+    if !success {
+      self.failed_lookups += 1;
+    }
+    self.total_duration += duration;
+    self.max_duration = self.max_duration.max(duration);
+  }
+}
+
+/// Address family the hosts are resolved to, see `--ipv4` and `--ipv6`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+  V4,
+  V6,
+}
+
+/// State shared by the resolvers of every client of a run: the answers kept
+/// by `--dns once` and the stats of the lookups
+#[derive(Debug, Default)]
+pub struct DnsCache {
+  mode: DnsMode,
+  family: Option<IpFamily>,
+  answers: Mutex<HashMap<String, Vec<SocketAddr>>>,
+  stats: Mutex<DnsStats>,
+}
+
+impl DnsCache {
+  pub fn new(mode: DnsMode, family: Option<IpFamily>) -> Self {
+    DnsCache {
+      mode,
+      family,
+      ..Default::default()
+    }
+  }
+
+  pub fn mode(&self) -> DnsMode {
+    self.mode
+  }
+
+  pub fn stats(&self) -> DnsStats {
+    self.stats.lock().unwrap_or_else(PoisonError::into_inner).clone()
+  }
+
+  fn answer(&self, host: &str) -> Option<Vec<SocketAddr>> {
+    let answers = self.answers.lock().unwrap_or_else(PoisonError::into_inner);
+    answers.get(host).cloned()
+  }
+
+  fn keep(&self, host: &str, addrs: &[SocketAddr]) {
+    let mut answers =
+      self.answers.lock().unwrap_or_else(PoisonError::into_inner);
+    answers.insert(host.to_owned(), addrs.to_vec());
+  }
+
+  fn record(&self, duration: f64, success: bool) {
+    let mut stats = self.stats.lock().unwrap_or_else(PoisonError::into_inner);
+    stats.record(duration, success);
+  }
+}
+
+/// Resolver of a client, timing its lookups. The system resolver is only
+/// read from `/etc/resolv.conf`, the queries bypass any cache of the OS.
+#[derive(Clone)]
+pub struct Resolver {
+  cache: Arc<DnsCache>,
+  /// Built on the first lookup, from within the runtime
+  resolver: Arc<Mutex<Option<TokioAsyncResolver>>>,
+}
+
+impl Resolver {
+  pub fn new(cache: Arc<DnsCache>) -> Self {
+    Resolver {
+      cache,
+      resolver: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  /// Resolves `host`, from the answers kept for the run with `--dns once`
+  pub async fn lookup(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+    if self.cache.mode == DnsMode::Once {
+      if let Some(addrs) = self.cache.answer(host) {
+        return Ok(addrs);
+      }
+    }
+
+    let resolver = self.resolver()?;
+    let begin = Instant::now();
+    let result = resolver.lookup_ip(host).await;
+    let duration = begin.elapsed().as_secs_f64() * 1_000.0;
+    self.cache.record(duration, result.is_ok());
+
+    let addrs: Vec<_> = result
+      .map_err(io::Error::other)?
+      .iter()
+      .map(|ip| SocketAddr::new(ip, 0))
+      .collect();
+    if self.cache.mode == DnsMode::Once {
+      self.cache.keep(host, &addrs);
+    }
+    Ok(addrs)
+  }
+
+  fn resolver(&self) -> io::Result<TokioAsyncResolver> {
+    let mut resolver =
+      self.resolver.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(resolver) = resolver.as_ref() {
+      return Ok(resolver.clone());
+    }
+
+    let (config, mut options) =
+      system_conf::read_system_conf().map_err(|err| {
+        io::Error::other(format!("error reading DNS system conf: {err}"))
+      })?;
+    if self.cache.mode == DnsMode::Uncached {
+      options.cache_size = 0;
+    }
+    options.ip_strategy = match self.cache.family {
+      Some(IpFamily::V4) => LookupIpStrategy::Ipv4Only,
+      Some(IpFamily::V6) => LookupIpStrategy::Ipv6Only,
+      None => options.ip_strategy,
+    };
+    let built = TokioAsyncResolver::tokio(config, options);
+    *resolver = Some(built.clone());
+    Ok(built)
+  }
+}
+
+impl Resolve for Resolver {
+  fn resolve(&self, name: Name) -> Resolving {
+    let resolver = self.clone();
+    Box::pin(async move {
+      let addrs = resolver.lookup(name.as_str()).await?;
+      Ok(Box::new(addrs.into_iter()) as Addrs)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn records_lookups() {
+    let cache = DnsCache::new(DnsMode::Cached, None);
+    cache.record(4.0, true);
+    cache.record(10.0, false);
+    cache.record(1.0, true);
+
+    let stats = cache.stats();
+    assert_eq!((stats.lookups, stats.failed_lookups), (3, 1));
+    assert_eq!(stats.max_duration, 10.0);
+    assert_eq!(stats.mean_duration(), 5.0);
+  }
+
+  #[test]
+  fn reuses_answers_once_kept() {
+    let addrs = vec![SocketAddr::from(([10, 0, 0, 1], 0))];
+    let cache = Arc::new(DnsCache::new(DnsMode::Once, None));
+    cache.keep("api.test", &addrs);
+
+    let resolver = Resolver::new(cache.clone());
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    assert_eq!(rt.block_on(resolver.lookup("api.test")).unwrap(), addrs);
+    assert_eq!(cache.stats().lookups, 0);
+  }
+}
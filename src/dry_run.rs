@@ -0,0 +1,523 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use colored::*;
+use url::Url;
+
+use crate::args::FlattenedCli;
+use crate::benchmark::{load_benchmark_doc, Benchmark};
+use crate::config::Config;
+use crate::interpolator::INTERPOLATION_REGEX;
+use crate::parse::{Action, BenchmarkDoc, PlanItem, WithItems};
+
+const METHODS: [&str; 6] = ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD"];
+// Keys every iteration context starts with
+const BUILTINS: [&str; 10] = [
+  "iteration",
+  "vu_id",
+  "worker_id",
+  "run_id",
+  "trace_id",
+  "urls",
+  "global",
+  "cookies",
+  "validators",
+  "shared",
+];
+
+struct Validator {
+  config: Config,
+  known: HashSet<String>,
+  /// Lines of the expanded plan
+  listing: Vec<String>,
+  problems: Vec<String>,
+  step: usize,
+  // Set once a step can write arbitrary keys, like plugins and scripts
+  open_context: bool,
+}
+
+/// Parses the benchmark with all its includes, checks it for mistakes
+/// that would only show up mid-run and prints the expanded plan, without
+/// sending anything.
+pub fn validate(args: &FlattenedCli) -> Result<(), usize> {
+  let doc = load_benchmark_doc(args);
+  let (config, _): (Config, Benchmark) = From::from(&doc);
+  let validator = Validator::run(config.with_args(args), &doc);
+
+  println!();
+  for line in &validator.listing {
+    println!("{line}");
+  }
+  println!();
+
+  if validator.problems.is_empty() {
+    println!("{}", "Plan is valid".green());
+    return Ok(());
+  }
+
+  for problem in &validator.problems {
+    println!("{} {}", "ERROR:".red().bold(), problem);
+  }
+  Err(validator.problems.len())
+}
+
+impl Validator {
+  fn run(config: Config, doc: &BenchmarkDoc) -> Self {
+    let mut validator = Validator {
+      config,
+      known: HashSet::new(),
+      listing: Vec::new(),
+      problems: Vec::new(),
+      step: 0,
+      open_context: false,
+    };
+
+    validator.check_urls();
+    validator.walk_hook("before_vu", &doc.before_vu);
+    validator.walk_hook("before_each", &doc.before_each);
+    validator.walk(&doc.plan, 0);
+    validator.walk_hook("after_each", &doc.after_each);
+    validator
+  }
+
+  fn walk(&mut self, plan: &[PlanItem], depth: usize) {
+    for item in plan {
+      let name = item.name.clone().unwrap_or_default();
+
+      if let Some(condition) = &item.when {
+        self.check_interpolations(&name, condition);
+      }
+
+      if let Action::Include(includes) = &item.action {
+        for include in includes {
+          self.listing.push(format!(
+            "{:indent$}{} {}",
+            "",
+            "include".yellow(),
+            include.path.cyan(),
+            indent = depth * 2
+          ));
+          self.walk(&include.doc.plan, depth + 1);
+        }
+        self.walk_on_error(item, depth);
+        continue;
+      }
+
+      self.step += 1;
+      let line = format!(
+        "{:indent$}{:>3} {:width$} {}",
+        "",
+        self.step,
+        name.green(),
+        self.describe(&item.action),
+        indent = depth * 2,
+        width = 25
+      );
+      self.listing.push(line);
+      self.check(&name, &item.action);
+
+      if let Action::Use {
+        template,
+        ..
+      } = &item.action
+      {
+        if let Some(items) = self.config.templates.get(template).cloned() {
+          self.walk(&items, depth + 1);
+        }
+      }
+      if let Action::Transaction(items) = &item.action {
+        self.walk(items, depth + 1);
+      }
+
+      if let Some(key) = &item.assign {
+        self.known.insert(key.clone());
+      }
+      self.walk_on_error(item, depth);
+    }
+  }
+
+  fn walk_on_error(&mut self, item: &PlanItem, depth: usize) {
+    if let Some(on_error) = &item.on_error {
+      self.listing.push(format!(
+        "{:indent$}{} {}",
+        "",
+        "on_error, then".yellow(),
+        format!("{:?}", on_error.then).to_lowercase(),
+        indent = depth * 2 + 4
+      ));
+      self.walk(&on_error.steps, depth + 2);
+    }
+  }
+
+  fn walk_hook(&mut self, hook: &str, plan: &[PlanItem]) {
+    if !plan.is_empty() {
+      self.listing.push(hook.yellow().to_string());
+      self.walk(plan, 1);
+    }
+  }
+
+  fn describe(&self, action: &Action) -> String {
+    match action {
+      Action::Assert {
+        key,
+        value,
+      } => format!("{} {}={}", "assert".yellow(), key, value),
+      Action::Assign {
+        key,
+        value,
+      } => format!("{} {}={}", "assign".yellow(), key, value),
+      Action::CounterInc {
+        key,
+        by,
+      } => format!("{} shared.{} by {}", "counter_inc".yellow(), key, by),
+      Action::Custom {
+        command,
+        ..
+      } => format!("{} {}", "custom".yellow(), command),
+      Action::DbQuery {
+        target,
+        query,
+        ..
+      } => format!("{} {} <= {}", "db-query".yellow(), target, query),
+      Action::Debug {
+        keys,
+      } if keys.is_empty() => format!("{} context", "debug".yellow()),
+      Action::Debug {
+        keys,
+      } => format!("{} {}", "debug".yellow(), keys.join(", ")),
+      Action::Delay(delay) => format!("{} {}", "delay".yellow(), delay),
+      Action::Exec {
+        command,
+        detach,
+        ..
+      } => {
+        let mode = if *detach { " (async)" } else { "" };
+        format!("{} {}{}", "exec".yellow(), command, mode)
+      }
+      Action::Metric {
+        name,
+        kind,
+        value,
+      } => {
+        let kind = format!("{kind:?}").to_lowercase();
+        format!("{} {} {} {}", "metric".yellow(), kind, name, value)
+      }
+      Action::Plugin {
+        name,
+        ..
+      } => format!("{} {}", "plugin".yellow(), name),
+      Action::Request {
+        base,
+        url,
+        method,
+        with_items,
+        ..
+      } => {
+        let mut description = format!(
+          "{} {} {}",
+          "request".yellow(),
+          method.to_uppercase(),
+          self.config.full_url(base.as_deref(), url).blue()
+        );
+        if let Some(with_items) = with_items {
+          description += &format!(" ({} items)", with_items.items.len());
+        }
+        description
+      }
+      Action::Script {
+        ..
+      } => "script".yellow().to_string(),
+      Action::SharedSet {
+        key,
+        value,
+      } => format!("{} shared.{}={}", "shared_set".yellow(), key, value),
+      Action::Transaction(items) => {
+        format!("{} of {} items", "transaction".yellow(), items.len())
+      }
+      Action::Include(includes) => {
+        let paths: Vec<&str> =
+          includes.iter().map(|include| include.path.as_str()).collect();
+        format!("{} {}", "include".yellow(), paths.join(", "))
+      }
+      Action::Use {
+        template,
+        ..
+      } => format!("{} {}", "use".yellow(), template),
+    }
+  }
+
+  fn check(&mut self, name: &str, action: &Action) {
+    match action {
+      Action::Assert {
+        key,
+        value,
+      } => {
+        self.check_reference(name, key);
+        if let Some(value) = value.as_str() {
+          self.check_interpolations(name, value);
+        }
+      }
+      Action::Assign {
+        key,
+        ..
+      } => {
+        self.known.insert(key.clone());
+      }
+      Action::CounterInc {
+        ..
+      } => {}
+      // Custom steps can write any key
+      Action::Custom {
+        ..
+      } => self.open_context = true,
+      Action::DbQuery {
+        target,
+        query,
+        with_items,
+        ..
+      } => {
+        if !self.config.dbs.contains_key(target) {
+          self.problems.push(format!("{name}: unknown database '{target}'"));
+        }
+        self.learn_items(with_items);
+        self.check_interpolations(name, query);
+      }
+      Action::Debug {
+        keys,
+      } => {
+        for key in keys {
+          self.check_reference(name, key);
+        }
+      }
+      Action::Delay(_) => {}
+      Action::Exec {
+        command,
+        ..
+      } => self.check_interpolations(name, command),
+      Action::Metric {
+        value,
+        ..
+      } => {
+        if let Some(value) = value.as_str() {
+          self.check_interpolations(name, value);
+        }
+      }
+      Action::Plugin {
+        name: plugin,
+        ..
+      } => {
+        if !self.config.plugins.contains_key(plugin) {
+          self.problems.push(format!("{name}: unknown plugin '{plugin}'"));
+        }
+        self.open_context = true;
+      }
+      Action::Request {
+        base,
+        url,
+        path_params,
+        sni,
+        method,
+        headers,
+        body,
+        body_file,
+        with_items,
+        ..
+      } => {
+        if !METHODS.contains(&method.to_uppercase().as_str()) {
+          self.problems.push(format!("{name}: unknown method '{method}'"));
+        }
+        if let Some(body_file) = body_file {
+          if body.is_some() {
+            self
+              .problems
+              .push(format!("{name}: both body and body_file are set"));
+          }
+          if !Path::new(body_file).is_file() {
+            self.problems.push(format!("{name}: no body file '{body_file}'"));
+          }
+        }
+        self.learn_items(with_items);
+
+        if let Some(base) = base {
+          if !self.config.urls.contains_key(base) {
+            self.problems.push(format!("{name}: unknown base url '{base}'"));
+          }
+        }
+        let full_url = self.config.full_url(base.as_deref(), url);
+        self.check_url(name, &full_url);
+        self.check_interpolations(name, url);
+        for (param, value) in path_params {
+          if !url.contains(&format!("/:{param}")) {
+            self.problems.push(format!(
+              "{name}: no ':{param}' segment in the url for its path_params"
+            ));
+          }
+          self.check_interpolations(name, value);
+        }
+        if let Some(sni) = sni {
+          self.check_interpolations(name, sni);
+        }
+
+        for value in headers.values() {
+          self.check_interpolations(name, value);
+        }
+        if let Some(body) = body {
+          self.check_interpolations(name, body);
+        }
+      }
+      // Scripts can write any key
+      Action::Script {
+        ..
+      } => self.open_context = true,
+      Action::SharedSet {
+        value,
+        ..
+      } => {
+        if let Some(value) = value.as_str() {
+          self.check_interpolations(name, value);
+        }
+      }
+      Action::Transaction(_) | Action::Include(_) => {}
+      Action::Use {
+        template,
+        args,
+      } => {
+        if !self.config.templates.contains_key(template) {
+          self.problems.push(format!("{name}: unknown template '{template}'"));
+        }
+        for value in args.values().filter_map(|value| value.as_str()) {
+          self.check_interpolations(name, value);
+        }
+        self.known.extend(args.keys().cloned());
+      }
+    }
+  }
+
+  fn check_urls(&mut self) {
+    if let Some(base_url) = self.config.base_url.clone() {
+      self.check_url("base_url", &base_url);
+      self.check_interpolations("base_url", &base_url);
+    }
+    let urls = self.config.urls.clone();
+    for (key, url) in urls.iter() {
+      self.check_url(&format!("urls.{key}"), url);
+      self.check_interpolations(&format!("urls.{key}"), url);
+    }
+    let url_headers = self.config.url_headers.clone();
+    for (key, headers) in url_headers.iter() {
+      for value in headers.values() {
+        self.check_interpolations(&format!("urls.{key}"), value);
+      }
+    }
+    let default_headers = self.config.default_headers.clone();
+    for value in default_headers.values() {
+      self.check_interpolations("default_headers", value);
+    }
+  }
+
+  fn check_url(&mut self, name: &str, url: &str) {
+    // Can't tell anything when the whole address comes from the context
+    if url.trim_start().starts_with("{{") {
+      return;
+    }
+    let placeholder = INTERPOLATION_REGEX.replace_all(url, "x");
+    if let Err(err) = Url::parse(&placeholder) {
+      self.problems.push(format!("{name}: invalid url '{url}' ({err})"));
+    }
+  }
+
+  fn check_interpolations(&mut self, name: &str, text: &str) {
+    for caps in INTERPOLATION_REGEX.captures_iter(text) {
+      self.check_reference(name, &caps[1]);
+    }
+  }
+
+  fn check_reference(&mut self, name: &str, reference: &str) {
+    let root = reference.split(['.', '[']).next().unwrap_or_default();
+    let resolves = self.open_context
+      || self.config.global.contains_key(reference)
+      || BUILTINS.contains(&root)
+      || self.config.secret_commands.contains_key(root)
+      || self.known.contains(root)
+      || std::env::var(reference).is_ok();
+
+    if !resolves {
+      self.problems.push(format!("{name}: unknown variable '{reference}'"));
+    }
+  }
+
+  // Items get merged into the context, so their keys can be referenced
+  fn learn_items(&mut self, with_items: &Option<WithItems>) {
+    if let Some(with_items) = with_items {
+      for item in &with_items.items {
+        if let Some(map) = item.as_mapping() {
+          self
+            .known
+            .extend(map.keys().filter_map(|k| k.as_str()).map(str::to_owned));
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parse::read_benchmark_doc;
+
+  #[test]
+  fn lists_the_expanded_plan() {
+    let dir = std::env::temp_dir().join(format!("drill-dry-run-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+      dir.join("main.yml"),
+      "base: http://localhost
+templates:
+  login:
+    - name: Login
+      request:
+        url: /login
+        method: POST
+        body: 'user={{ user }}'
+      assign: login
+plan:
+  - name: Sign in
+    use: login
+    args:
+      user: bob
+  - include: ./users.yml
+  - name: Checkout
+    transaction:
+      - name: Cart
+        request:
+          url: /cart/{{ login.body.id }}
+      - name: Pay
+        request:
+          url: /pay/{{ missing }}
+",
+    )
+    .unwrap();
+    std::fs::write(dir.join("users.yml"), "plan:\n  - name: Users\n    request:\n      url: /users\n").unwrap();
+
+    colored::control::set_override(false);
+    let doc = read_benchmark_doc(&dir.join("main.yml")).unwrap();
+    let (config, _): (Config, Benchmark) = From::from(&doc);
+    let validator = Validator::run(config, &doc);
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let listing: Vec<_> = validator.listing.iter().map(|line| line.trim_end()).collect();
+    assert_eq!(
+      listing,
+      [
+        "  1 Sign in                   use login",
+        "    2 Login                     request POST http://localhost/login",
+        "include users.yml",
+        "    3 Users                     request GET http://localhost/users",
+        "  4 Checkout                  transaction of 2 items",
+        "    5 Cart                      request GET http://localhost/cart/{{ login.body.id }}",
+        "    6 Pay                       request GET http://localhost/pay/{{ missing }}",
+      ]
+    );
+    assert_eq!(validator.problems, ["Pay: unknown variable 'missing'"]);
+  }
+}
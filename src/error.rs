@@ -0,0 +1,90 @@
+use std::fmt;
+
+/// Errors aborting a single iteration. The run goes on with the remaining
+/// iterations, and the failures are counted in the stats.
+#[derive(Debug)]
+pub enum Error {
+  /// An interpolation references a variable missing from the context
+  UnknownVariable(String),
+  /// A request references a key missing from `urls`
+  UnknownBaseUrl {
+    step: String,
+    base: String,
+  },
+  InvalidUrl {
+    url: String,
+    reason: String,
+  },
+  UnknownMethod(String),
+  InvalidHeader {
+    name: String,
+    reason: String,
+  },
+  UnknownDatabase(String),
+  InvalidDatabase(String),
+  AssertionMismatch {
+    expected: String,
+    actual: String,
+  },
+  /// A response got past the `max_response_bytes` of its request
+  ResponseTooLarge {
+    step: String,
+    limit: usize,
+  },
+  /// A step failed running a query, a command, a script or a plugin
+  Step {
+    step: String,
+    reason: String,
+  },
+}
+
+impl Error {
+  pub fn step<S: ToString>(step: &str, reason: S) -> Error {
+    Error::Step {
+      step: step.to_owned(),
+      reason: reason.to_string(),
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::UnknownVariable(name) => {
+        write!(f, "Couldn't resolve variable '{name}'")
+      }
+      Error::UnknownBaseUrl {
+        step,
+        base,
+      } => write!(
+        f,
+        "Request '{step}' references a non-existent base url named '{base}'"
+      ),
+      Error::InvalidUrl {
+        url,
+        reason,
+      } => write!(f, "Invalid url '{url}': {reason}"),
+      Error::UnknownMethod(method) => write!(f, "Unknown method '{method}'"),
+      Error::InvalidHeader {
+        name,
+        reason,
+      } => write!(f, "Invalid header '{name}': {reason}"),
+      Error::UnknownDatabase(name) => write!(f, "No such DB: {name}"),
+      Error::InvalidDatabase(reason) => write!(f, "Invalid DB: {reason}"),
+      Error::AssertionMismatch {
+        expected,
+        actual,
+      } => write!(f, "Assertion mismatched: {expected} != {actual}"),
+      Error::ResponseTooLarge {
+        step,
+        limit,
+      } => write!(f, "Response of '{step}' is larger than {limit} bytes"),
+      Error::Step {
+        step,
+        reason,
+      } => write!(f, "Step '{step}' failed: {reason}"),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
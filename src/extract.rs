@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes};
+use serde::de::{
+  DeserializeSeed, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess,
+  Visitor,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Chunks received ahead of the parser at most
+const CHUNKS_IN_FLIGHT: usize = 16;
+
+/// Paths kept out of a JSON document, like `data.id` or `items[0].name`,
+/// as a tree of their segments
+#[derive(Debug, Default)]
+pub struct Paths {
+  /// Whether a path ends here, keeping the whole value
+  whole: bool,
+  children: BTreeMap<String, Paths>,
+}
+
+impl Paths {
+  pub fn new(paths: &[String]) -> Self {
+    let mut root = Paths::default();
+    for path in paths {
+      // Same paths as interpolations
+      let path = path.replace(['.', '['], "/").replace(']', "");
+      let node = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .fold(&mut root, |node, segment| {
+          node.children.entry(segment.to_owned()).or_default()
+        });
+      node.whole = true;
+    }
+    root
+  }
+
+  pub fn is_empty(&self) -> bool {
+    !self.whole && self.children.is_empty()
+  }
+
+  /// Keeps the paths of a JSON text, None when it isn't JSON
+  pub fn extract(&self, text: &str) -> Option<Value> {
+    let mut de = serde_json::Deserializer::from_str(text);
+    let value = self.deserialize(&mut de).ok()?;
+    de.end().ok()?;
+    Some(value)
+  }
+}
+
+// Skips the values off the paths without building them
+impl<'de> DeserializeSeed<'de> for &Paths {
+  type Value = Value;
+
+  fn deserialize<D>(self, de: D) -> Result<Value, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    if self.whole {
+      return Value::deserialize(de);
+    }
+    de.deserialize_any(self)
+  }
+}
+
+impl<'de> Visitor<'de> for &Paths {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a JSON value")
+  }
+
+  // Scalars are null where the paths go deeper
+  fn visit_bool<E: Error>(self, _: bool) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_i64<E: Error>(self, _: i64) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_u64<E: Error>(self, _: u64) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_f64<E: Error>(self, _: f64) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_str<E: Error>(self, _: &str) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_unit<E: Error>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_map<A>(self, mut map: A) -> Result<Value, A::Error>
+  where
+    A: MapAccess<'de>,
+  {
+    let mut kept = Map::new();
+    while let Some(key) = map.next_key::<String>()? {
+      match self.children.get(&key) {
+        Some(paths) => {
+          let value = map.next_value_seed(paths)?;
+          kept.insert(key, value);
+        }
+        None => {
+          map.next_value::<IgnoredAny>()?;
+        }
+      }
+    }
+    Ok(Value::Object(kept))
+  }
+
+  // Items off the paths are null up to the last one kept, so indices hold
+  fn visit_seq<A>(self, mut seq: A) -> Result<Value, A::Error>
+  where
+    A: SeqAccess<'de>,
+  {
+    let mut kept = Vec::new();
+    for index in 0.. {
+      match self.children.get(&index.to_string()) {
+        Some(paths) => match seq.next_element_seed(paths)? {
+          Some(value) => {
+            kept.resize(index, Value::Null);
+            kept.push(value);
+          }
+          None => break,
+        },
+        None => {
+          if seq.next_element::<IgnoredAny>()?.is_none() {
+            break;
+          }
+        }
+      }
+    }
+    Ok(Value::Array(kept))
+  }
+}
+
+/// Body read by a parser on a blocking thread, as its chunks get sent
+struct ChunkReader {
+  chunks: mpsc::Receiver<Bytes>,
+  chunk: Bytes,
+}
+
+impl Read for ChunkReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while !self.chunk.has_remaining() {
+      match self.chunks.blocking_recv() {
+        Some(chunk) => self.chunk = chunk,
+        None => return Ok(0),
+      }
+    }
+    let len = buf.len().min(self.chunk.remaining());
+    self.chunk.copy_to_slice(&mut buf[..len]);
+    Ok(len)
+  }
+}
+
+/// Starts parsing a JSON body keeping only `paths`, to be sent its chunks.
+/// The parser stops receiving them when the body isn't JSON, and yields None.
+pub fn spawn_parser(
+  paths: Arc<Paths>,
+) -> (mpsc::Sender<Bytes>, JoinHandle<Option<Value>>) {
+  let (sender, chunks) = mpsc::channel(CHUNKS_IN_FLIGHT);
+  let parser = tokio::task::spawn_blocking(move || {
+    let reader = ChunkReader {
+      chunks,
+      chunk: Bytes::new(),
+    };
+    let mut de = serde_json::Deserializer::from_reader(reader);
+    let value = paths.as_ref().deserialize(&mut de).ok()?;
+    de.end().ok()?;
+    Some(value)
+  });
+  (sender, parser)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn extracts_paths() {
+    let paths = Paths::new(&["data.id".to_owned(), "items[1].name".to_owned()]);
+    let text = r#"{"data": {"id": 42, "big": "x"}, "items": [{"name": "a"}, {"name": "b"}, 3]}"#;
+
+    assert_eq!(
+      paths.extract(text),
+      Some(json!({"data": {"id": 42}, "items": [null, {"name": "b"}]}))
+    );
+    assert_eq!(paths.extract("not json"), None);
+  }
+}
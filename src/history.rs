@@ -0,0 +1,260 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use colored::*;
+use linked_hash_map::LinkedHashMap;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{ConnectOptions, Connection, Row};
+use tokio::runtime;
+
+use crate::actions::Report;
+use crate::RunStats;
+
+const SCHEMA: [&str; 3] = [
+  "CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at INTEGER NOT NULL,
+    benchmark TEXT NOT NULL,
+    duration REAL NOT NULL,
+    requests INTEGER NOT NULL
+  )",
+  "CREATE TABLE IF NOT EXISTS steps (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    name TEXT NOT NULL,
+    total INTEGER NOT NULL,
+    successful INTEGER NOT NULL,
+    failed INTEGER NOT NULL,
+    median REAL NOT NULL,
+    mean REAL NOT NULL,
+    stdev REAL NOT NULL,
+    p99 REAL NOT NULL,
+    p995 REAL NOT NULL,
+    p999 REAL NOT NULL
+  )",
+  "CREATE TABLE IF NOT EXISTS samples (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    iteration INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    duration REAL NOT NULL,
+    status INTEGER NOT NULL
+  )",
+];
+
+/// Saves the run metadata, the stats of every step and the raw samples into
+/// the SQLite history at `path`, creating it when missing.
+pub fn save_run(
+  path: &str,
+  benchmark: &str,
+  list_reports: &[Vec<Report>],
+  run_stats: &RunStats,
+  duration: f64,
+) {
+  let run_id = block_on(async {
+    let mut conn = open(path).await?;
+    let mut tx = conn.begin().await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let started_at = now.as_secs_f64() - duration;
+    let requests = run_stats.global.total_requests;
+    let run_id = sqlx::query(
+      "INSERT INTO runs (started_at, benchmark, duration, requests)
+       VALUES (?, ?, ?, ?)",
+    )
+    .bind(started_at as i64)
+    .bind(benchmark)
+    .bind(duration)
+    .bind(requests as i64)
+    .execute(&mut *tx)
+    .await?
+    .last_insert_rowid();
+
+    // Reports streamed to disk are not kept, only their stats are saved
+    for reports in list_reports {
+      for report in reports.iter().filter(|report| report.metric.is_none()) {
+        sqlx::query(
+          "INSERT INTO samples (run_id, iteration, name, duration, status)
+           VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(run_id)
+        .bind(report.iteration as i64)
+        .bind(&report.name)
+        .bind(report.duration)
+        .bind(report.status as i64)
+        .execute(&mut *tx)
+        .await?;
+      }
+    }
+
+    for (name, stats) in run_stats.steps.iter() {
+      sqlx::query(
+        "INSERT INTO steps (run_id, name, total, successful, failed, median,
+         mean, stdev, p99, p995, p999)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+      )
+      .bind(run_id)
+      .bind(name)
+      .bind(stats.total_requests as i64)
+      .bind(stats.successful_requests as i64)
+      .bind(stats.failed_requests as i64)
+      .bind(stats.median_duration())
+      .bind(stats.mean_duration())
+      .bind(stats.stdev_duration())
+      .bind(stats.value_at_quantile(0.99))
+      .bind(stats.value_at_quantile(0.995))
+      .bind(stats.value_at_quantile(0.999))
+      .execute(&mut *tx)
+      .await?;
+    }
+
+    tx.commit().await?;
+    Ok(run_id)
+  });
+
+  println!();
+  println!(
+    "{} {} {}",
+    "Saved run".green(),
+    run_id.to_string().purple(),
+    format!("into {path}").green()
+  );
+}
+
+/// Lists the runs stored in the history, oldest first
+pub fn list_runs(path: &str) {
+  let rows = block_on(async {
+    let mut conn = open(path).await?;
+    sqlx::query(
+      "SELECT id, datetime(started_at, 'unixepoch') AS started, benchmark,
+       duration, requests FROM runs ORDER BY id",
+    )
+    .fetch_all(&mut conn)
+    .await
+  });
+
+  println!(
+    "{:>5} {:20} {:>10} {:>9} {}",
+    "Run".yellow(),
+    "Started (UTC)".yellow(),
+    "Requests".yellow(),
+    "Duration".yellow(),
+    "Benchmark".yellow()
+  );
+  for row in rows {
+    println!(
+      "{:>5} {:20} {:>10} {:>9} {}",
+      row.get::<i64, _>("id").to_string().purple(),
+      row.get::<String, _>("started"),
+      row.get::<i64, _>("requests").to_string().purple(),
+      format!("{:.1}s", row.get::<f64, _>("duration")),
+      row.get::<String, _>("benchmark").green()
+    );
+  }
+}
+
+/// Compares the median and 99th percentile of every step between two runs
+pub fn diff_runs(path: &str, first: i64, second: i64) {
+  let (before, after) = block_on(async {
+    let mut conn = open(path).await?;
+    let before = step_stats(&mut conn, first).await?;
+    let after = step_stats(&mut conn, second).await?;
+    Ok((before, after))
+  });
+
+  let (before, after) = match (before, after) {
+    (Some(before), Some(after)) => (before, after),
+    (before, _) => {
+      let missing = if before.is_none() { first } else { second };
+      eprintln!("{} No run {} in {}", "ERROR:".red().bold(), missing, path);
+      std::process::exit(1);
+    }
+  };
+
+  println!(
+    "{:width$} {:>22} {:>22}",
+    "Step".yellow(),
+    "Median".yellow(),
+    "99.0'th percentile".yellow(),
+    width = 25
+  );
+  for (name, (median, p99)) in after.iter() {
+    match before.get(name) {
+      Some((old_median, old_p99)) => println!(
+        "{:width$} {:>22} {:>22}",
+        name.green(),
+        change(*old_median, *median),
+        change(*old_p99, *p99),
+        width = 25
+      ),
+      None => {
+        println!("{:width$} {}", name.green(), "added".cyan(), width = 25)
+      }
+    }
+  }
+  for name in before.keys().filter(|name| !after.contains_key(*name)) {
+    println!("{:width$} {}", name.green(), "removed".cyan(), width = 25);
+  }
+}
+
+fn change(before: f64, after: f64) -> ColoredString {
+  let delta = if before > 0.0 {
+    (after - before) / before * 100.0
+  } else {
+    0.0
+  };
+  let text = format!("{:.1}ms -> {:.1}ms {:+.0}%", before, after, delta);
+  if delta > 0.0 {
+    text.red()
+  } else {
+    text.green()
+  }
+}
+
+/// Median and 99th percentile of every step, `None` when the run is missing
+async fn step_stats(
+  conn: &mut SqliteConnection,
+  run_id: i64,
+) -> Result<Option<LinkedHashMap<String, (f64, f64)>>, sqlx::Error> {
+  let run = sqlx::query("SELECT id FROM runs WHERE id = ?")
+    .bind(run_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+  if run.is_none() {
+    return Ok(None);
+  }
+
+  let rows = sqlx::query(
+    "SELECT name, median, p99 FROM steps WHERE run_id = ? ORDER BY rowid",
+  )
+  .bind(run_id)
+  .fetch_all(conn)
+  .await?;
+
+  Ok(Some(
+    rows
+      .iter()
+      .map(|row| (row.get("name"), (row.get("median"), row.get("p99"))))
+      .collect(),
+  ))
+}
+
+async fn open(path: &str) -> Result<SqliteConnection, sqlx::Error> {
+  let mut conn = SqliteConnectOptions::new()
+    .filename(path)
+    .create_if_missing(true)
+    .connect()
+    .await?;
+  for statement in SCHEMA {
+    sqlx::query(statement).execute(&mut conn).await?;
+  }
+  Ok(conn)
+}
+
+fn block_on<T, F>(future: F) -> T
+where
+  F: std::future::Future<Output = Result<T, sqlx::Error>>,
+{
+  let rt = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+  rt.block_on(future).unwrap_or_else(|err| {
+    eprintln!("{} Run history: {}", "ERROR:".red().bold(), err);
+    std::process::exit(1)
+  })
+}
@@ -0,0 +1,221 @@
+use serde::Deserialize;
+
+use super::{
+  is_recorded_header, is_static_asset, is_supported_method, read_source, warn,
+  PlanDoc, PlanStep, RequestStep,
+};
+
+const STATIC_RESOURCE_TYPES: [&str; 5] =
+  ["script", "stylesheet", "image", "font", "media"];
+
+#[derive(Debug, Deserialize)]
+struct Har {
+  log: Log,
+}
+
+#[derive(Debug, Deserialize)]
+struct Log {
+  #[serde(default)]
+  entries: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+  request: HarRequest,
+  response: Option<HarResponse>,
+  // Browser specific, set by Chromium based browsers
+  #[serde(rename = "_resourceType")]
+  resource_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+  method: String,
+  url: String,
+  #[serde(default)]
+  headers: Vec<NameValue>,
+  #[serde(default)]
+  cookies: Vec<NameValue>,
+  post_data: Option<PostData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+  #[serde(default)]
+  mime_type: String,
+  text: Option<String>,
+  #[serde(default)]
+  params: Vec<NameValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HarResponse {
+  content: Option<Content>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+  #[serde(default)]
+  mime_type: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct NameValue {
+  name: String,
+  #[serde(default)]
+  value: String,
+}
+
+/// Converts a browser HAR capture into a plan replaying its requests in
+/// order, with their headers and cookies. When `skip_static` is set,
+/// scripts, stylesheets, images and fonts are left out.
+pub fn import(path: &str, skip_static: bool) -> Result<PlanDoc, String> {
+  convert(&read_source(path)?, path, skip_static)
+}
+
+fn convert(
+  text: &str,
+  path: &str,
+  skip_static: bool,
+) -> Result<PlanDoc, String> {
+  let har: Har = serde_json::from_str(text)
+    .map_err(|err| format!("Invalid HAR file {path}: {err}"))?;
+
+  let mut doc = PlanDoc::new(format!("Imported from HAR capture '{path}'"));
+  let mut skipped = 0;
+
+  for entry in &har.log.entries {
+    if skip_static && is_static(entry) {
+      skipped += 1;
+      continue;
+    }
+
+    let request = &entry.request;
+    let method = request.method.to_uppercase();
+    if !is_supported_method(&method) {
+      warn(format!("Skipping {} {}: unsupported method", method, request.url));
+      continue;
+    }
+
+    let (base, url) = match doc.split_url(&request.url) {
+      Some((base, url)) => (Some(base), url),
+      None => (None, request.url.clone()),
+    };
+    let mut step = RequestStep {
+      base,
+      url,
+      method,
+      ..Default::default()
+    };
+
+    for header in &request.headers {
+      if is_recorded_header(&header.name) && !header.name.starts_with(':') {
+        step.headers.insert(header.name.clone(), header.value.clone());
+      }
+    }
+
+    if !request.cookies.is_empty() {
+      let cookie = request
+        .cookies
+        .iter()
+        .map(|c| format!("{}={}", c.name, c.value))
+        .collect::<Vec<_>>()
+        .join("; ");
+      step.headers.insert("Cookie".to_owned(), cookie);
+    }
+
+    if let Some(post_data) = &request.post_data {
+      step.body = match &post_data.text {
+        Some(text) => Some(text.clone()),
+        None => Some(
+          post_data
+            .params
+            .iter()
+            .map(|p| format!("{}={}", p.name, p.value))
+            .collect::<Vec<_>>()
+            .join("&"),
+        ),
+      };
+      if !post_data.mime_type.is_empty() {
+        step
+          .headers
+          .entry("Content-Type".to_owned())
+          .or_insert_with(|| post_data.mime_type.clone());
+      }
+    }
+
+    doc.plan.push(PlanStep {
+      name: step.default_name(),
+      request: step,
+    });
+  }
+
+  if skipped > 0 {
+    warn(format!("Skipped {skipped} static asset requests"));
+  }
+  Ok(doc)
+}
+
+fn is_static(entry: &Entry) -> bool {
+  if let Some(resource_type) = &entry.resource_type {
+    return STATIC_RESOURCE_TYPES.contains(&resource_type.as_str());
+  }
+
+  let mime_type = entry
+    .response
+    .as_ref()
+    .and_then(|r| r.content.as_ref())
+    .map(|c| c.mime_type.as_str())
+    .unwrap_or_default();
+  is_static_asset(&entry.request.url, mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CAPTURE: &str = r#"{"log": {"entries": [
+    {"request": {"method": "POST", "url": "https://shop.test/api/login?next=/",
+      "headers": [{"name": ":authority", "value": "shop.test"}, {"name": "Host", "value": "shop.test"}, {"name": "Accept", "value": "application/json"}],
+      "cookies": [{"name": "a", "value": "1"}, {"name": "b", "value": "2"}],
+      "postData": {"mimeType": "application/x-www-form-urlencoded", "params": [{"name": "user", "value": "bob"}]}}},
+    {"request": {"method": "GET", "url": "https://cdn.test/app.js"}},
+    {"request": {"method": "OPTIONS", "url": "https://shop.test/api"}}
+  ]}}"#;
+
+  #[test]
+  fn converts_entries() {
+    let doc = convert(CAPTURE, "session.har", false).unwrap();
+
+    assert_eq!(doc.urls["shop_test"], "https://shop.test");
+    assert_eq!(doc.urls["cdn_test"], "https://cdn.test");
+    assert_eq!(doc.plan.len(), 2);
+
+    let login = &doc.plan[0];
+    assert_eq!(login.name, "POST /api/login");
+    assert_eq!(login.request.base.as_deref(), Some("shop_test"));
+    assert_eq!(login.request.url, "api/login?next=/");
+    assert_eq!(login.request.body.as_deref(), Some("user=bob"));
+    let headers: Vec<_> = login.request.headers.keys().map(String::as_str).collect();
+    assert_eq!(headers, ["Accept", "Content-Type", "Cookie"]);
+    assert_eq!(login.request.headers["Cookie"], "a=1; b=2");
+  }
+
+  #[test]
+  fn skips_static_assets() {
+    let doc = convert(CAPTURE, "session.har", true).unwrap();
+
+    assert_eq!(doc.plan.len(), 1);
+    assert_eq!(doc.plan[0].name, "POST /api/login");
+  }
+
+  #[test]
+  fn reports_invalid_captures() {
+    let err = convert(r#"{"entries": []}"#, "session.har", false).unwrap_err();
+
+    assert!(err.starts_with("Invalid HAR file session.har: missing field `log`"), "{}", err);
+  }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use colored::*;
+use serde::Serialize;
+use url::Url;
+
+use crate::args::ImportSource;
+use crate::writer;
+
+mod har;
+mod openapi;
+mod postman;
+
+/// Benchmark document as written by the importers. It only covers the
+/// subset of the benchmark syntax other tools can be mapped to.
+#[derive(Debug, Default, Serialize)]
+pub struct PlanDoc {
+  /// Describes where the plan comes from, written as a header comment
+  #[serde(skip)]
+  pub origin: String,
+  pub iterations: u64,
+  pub concurrency: u64,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub urls: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub global: BTreeMap<String, String>,
+  pub plan: Vec<PlanStep>,
+  // Origin (scheme, host and port) to its key in the `urls` map
+  #[serde(skip)]
+  origins: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanStep {
+  pub name: String,
+  pub request: RequestStep,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RequestStep {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub base: Option<String>,
+  pub url: String,
+  pub method: String,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub headers: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub body: Option<String>,
+}
+
+impl RequestStep {
+  /// Names steps like `GET /api/users`
+  pub fn default_name(&self) -> String {
+    let path = self.url.split('?').next().unwrap_or_default();
+    format!("{} /{}", self.method, path)
+  }
+}
+
+impl PlanDoc {
+  pub fn new(origin: String) -> Self {
+    PlanDoc {
+      origin,
+      iterations: 1,
+      concurrency: 1,
+      ..Default::default()
+    }
+  }
+
+  /// Splits an absolute url into a base, registered in `urls`, and the
+  /// relative part, as written
+  pub fn split_url(&mut self, url: &str) -> Option<(String, String)> {
+    let parsed = Url::parse(url).ok().filter(|parsed| parsed.has_host())?;
+
+    // Parsing would escape interpolations, so the relative part is sliced
+    // from the original url
+    let authority = url.find("://").map_or(0, |i| i + 3);
+    let rest = url[authority..]
+      .find(['/', '?'])
+      .map_or("", |i| &url[authority + i..]);
+
+    let origin = parsed.origin().ascii_serialization();
+    let key = self.origin_key(&origin, parsed.host_str().unwrap());
+    Some((key, rest.trim_start_matches('/').to_owned()))
+  }
+
+  fn origin_key(&mut self, origin: &str, host: &str) -> String {
+    if let Some(key) = self.origins.get(origin) {
+      return key.clone();
+    }
+
+    let base_key: String = host
+      .chars()
+      .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+      .collect();
+    let mut key = base_key.clone();
+    let mut suffix = 2;
+    while self.urls.contains_key(&key) {
+      key = format!("{base_key}_{suffix}");
+      suffix += 1;
+    }
+
+    self.urls.insert(key.clone(), origin.to_owned());
+    self.origins.insert(origin.to_owned(), key.clone());
+    key
+  }
+}
+
+pub fn run(source: ImportSource) {
+  let (imported, output) = match source {
+    ImportSource::Postman {
+      collection,
+      output,
+    } => (postman::import(&collection), output),
+    ImportSource::Openapi {
+      spec,
+      output,
+    } => (openapi::import(&spec), output),
+    ImportSource::Har {
+      capture,
+      skip_static,
+      output,
+    } => (har::import(&capture, skip_static), output),
+  };
+
+  match imported {
+    Ok(doc) => write_plan(&doc, output),
+    Err(err) => {
+      eprintln!("{} {}", "ERROR:".red().bold(), err);
+      std::process::exit(1)
+    }
+  }
+}
+
+/// Reads the file to import
+fn read_source(path: &str) -> Result<String, String> {
+  std::fs::read_to_string(path)
+    .map_err(|err| format!("Can't read {path}: {err}"))
+}
+
+/// Writes the plan to `output`, or prints it when missing
+pub fn write_plan(doc: &PlanDoc, output: Option<String>) {
+  let yaml = format!(
+    "---\n# {}\n{}",
+    doc.origin,
+    serde_yaml::to_string(&doc).unwrap()
+  );
+  match output {
+    Some(path) => {
+      writer::write_file(&path, yaml);
+      println!(
+        "{} {} {}",
+        "Wrote".green(),
+        doc.plan.len().to_string().purple(),
+        format!("requests into {path}").green()
+      );
+    }
+    None => print!("{yaml}"),
+  }
+}
+
+// Headers the client computes by itself, or that only make sense for the
+// captured connection. Cookies are written separately.
+const SKIPPED_HEADERS: [&str; 5] =
+  ["host", "content-length", "connection", "cookie", "accept-encoding"];
+const STATIC_EXTENSIONS: [&str; 14] = [
+  "js", "mjs", "css", "png", "jpg", "jpeg", "gif", "svg", "ico", "webp",
+  "woff", "woff2", "ttf", "map",
+];
+
+pub fn is_recorded_header(name: &str) -> bool {
+  let name = name.to_lowercase();
+  !SKIPPED_HEADERS.contains(&name.as_str()) && !name.starts_with("proxy-")
+}
+
+/// Tells scripts, stylesheets, images and fonts apart, by the response
+/// content type or the url extension
+pub fn is_static_asset(url: &str, mime_type: &str) -> bool {
+  if mime_type.starts_with("image/")
+    || mime_type.starts_with("font/")
+    || mime_type.contains("css")
+    || mime_type.contains("javascript")
+  {
+    return true;
+  }
+
+  let path = url.split(['?', '#']).next().unwrap_or_default();
+  path
+    .rsplit_once('.')
+    .map(|(_, ext)| STATIC_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+    .unwrap_or(false)
+}
+
+/// Methods a request step supports, see `Request::send_request`
+pub fn is_supported_method(method: &str) -> bool {
+  matches!(method, "GET" | "POST" | "PUT" | "PATCH" | "DELETE" | "HEAD")
+}
+
+pub fn warn(message: String) {
+  eprintln!("{} {}", "WARNING:".yellow().bold(), message);
+}
@@ -0,0 +1,371 @@
+use serde_json::{json, Map, Value};
+
+use super::{read_source, warn, PlanDoc, PlanStep, RequestStep};
+
+const BASE_KEY: &str = "api";
+const DEFAULT_SERVER: &str = "http://localhost";
+// Operations are emitted in this order for every path
+const METHODS: [&str; 6] = ["get", "post", "put", "patch", "delete", "head"];
+// Bounds `$ref` chains, in case references point at each other
+const MAX_REFERENCE_DEPTH: usize = 5;
+
+/// Scaffolds a plan from an OpenAPI 3 spec (JSON or YAML): one request per
+/// operation against the first server, with path, query and header
+/// parameters turned into globals and example request bodies.
+pub fn import(path: &str) -> Result<PlanDoc, String> {
+  convert(&read_source(path)?, path)
+}
+
+fn convert(text: &str, path: &str) -> Result<PlanDoc, String> {
+  let spec: Value = serde_yaml::from_str(text)
+    .map_err(|err| format!("Invalid OpenAPI spec {path}: {err}"))?;
+
+  if !spec["openapi"].as_str().unwrap_or_default().starts_with('3') {
+    warn(format!("{path} doesn't look like an OpenAPI 3 spec"));
+  }
+
+  let title = spec["info"]["title"].as_str().unwrap_or(path);
+  let mut doc = PlanDoc::new(format!("Imported from OpenAPI spec '{title}'"));
+  doc.urls.insert(BASE_KEY.to_owned(), server_url(&spec));
+
+  let paths = spec["paths"].as_object().cloned().unwrap_or_default();
+  for (path, item) in paths.iter() {
+    let item = resolve(&spec, item);
+    for method in METHODS.iter() {
+      if let Some(operation) = item.get(*method) {
+        let step =
+          import_operation(&spec, &mut doc, path, method, item, operation);
+        doc.plan.push(step);
+      }
+    }
+    for method in ["options", "trace"] {
+      if item.get(method).is_some() {
+        warn(format!(
+          "Skipping {} {}: unsupported method",
+          method.to_uppercase(),
+          path
+        ));
+      }
+    }
+  }
+
+  Ok(doc)
+}
+
+fn import_operation(
+  spec: &Value,
+  doc: &mut PlanDoc,
+  path: &str,
+  method: &str,
+  item: &Value,
+  operation: &Value,
+) -> PlanStep {
+  let name = operation["operationId"]
+    .as_str()
+    .or_else(|| operation["summary"].as_str())
+    .map(str::to_owned)
+    .unwrap_or_else(|| format!("{} {}", method.to_uppercase(), path));
+
+  // `{id}` path templates become `{{ id }}` interpolations
+  let mut url = path.replace('{', "{{ ").replace('}', " }}");
+  let mut query = Vec::new();
+  let mut step = RequestStep {
+    base: Some(BASE_KEY.to_owned()),
+    method: method.to_uppercase(),
+    ..Default::default()
+  };
+
+  // Operation parameters override the ones shared by the whole path
+  let mut parameters: Vec<&Value> = Vec::new();
+  for parameter in operation["parameters"]
+    .as_array()
+    .into_iter()
+    .flatten()
+    .chain(item["parameters"].as_array().into_iter().flatten())
+  {
+    let parameter = resolve(spec, parameter);
+    let duplicated = parameters.iter().any(|p| {
+      p["name"] == parameter["name"] && p["in"] == parameter["in"]
+    });
+    if !duplicated {
+      parameters.push(parameter);
+    }
+  }
+
+  for parameter in parameters {
+    let name = parameter["name"].as_str().unwrap_or_default();
+    let required = parameter["required"].as_bool().unwrap_or(false);
+    let location = parameter["in"].as_str().unwrap_or_default();
+    if name.is_empty() || (location != "path" && !required) {
+      continue;
+    }
+
+    if !doc.global.contains_key(name) {
+      let example = parameter_example(spec, parameter);
+      doc.global.insert(name.to_owned(), value_to_string(&example));
+    }
+
+    match location {
+      "query" => query.push(format!("{name}={{{{ {name} }}}}")),
+      "header" => {
+        step.headers.insert(name.to_owned(), format!("{{{{ {name} }}}}"));
+      }
+      _ => {}
+    }
+  }
+
+  if !query.is_empty() {
+    url = format!("{}?{}", url, query.join("&"));
+  }
+  step.url = url.trim_start_matches('/').to_owned();
+
+  let body = resolve(spec, &operation["requestBody"]);
+  if let Some(content) = body["content"].as_object() {
+    let json_type = content.keys().find(|k| k.contains("json"));
+    match json_type {
+      Some(content_type) => {
+        let media = &content[content_type];
+        let example = media_example(spec, media);
+        step.headers.insert("Content-Type".to_owned(), content_type.clone());
+        step.body = Some(serde_json::to_string_pretty(&example).unwrap());
+      }
+      None => warn(format!(
+        "'{name}': only JSON request bodies are supported, found {}",
+        content.keys().cloned().collect::<Vec<_>>().join(", ")
+      )),
+    }
+  }
+
+  PlanStep {
+    name,
+    request: step,
+  }
+}
+
+fn server_url(spec: &Value) -> String {
+  let server = &spec["servers"][0];
+  let mut url = match server["url"].as_str() {
+    Some(url) if url.contains("://") => url.to_owned(),
+    Some(url) => {
+      format!("{}/{}", DEFAULT_SERVER, url.trim_start_matches('/'))
+    }
+    None => DEFAULT_SERVER.to_owned(),
+  };
+
+  if let Some(variables) = server["variables"].as_object() {
+    for (name, variable) in variables {
+      let default = variable["default"].as_str().unwrap_or_default();
+      url = url.replace(&format!("{{{name}}}"), default);
+    }
+  }
+  url.trim_end_matches('/').to_owned()
+}
+
+/// Follows local `$ref`s, like `#/components/schemas/User`
+fn resolve<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+  let mut value = value;
+  for _ in 0..MAX_REFERENCE_DEPTH {
+    match value["$ref"].as_str().and_then(|r| r.strip_prefix('#')) {
+      Some(pointer) => value = spec.pointer(pointer).unwrap_or(&Value::Null),
+      None => break,
+    }
+  }
+  value
+}
+
+fn parameter_example(spec: &Value, parameter: &Value) -> Value {
+  if let Some(example) = parameter.get("example") {
+    return example.clone();
+  }
+  if let Some(example) = first_example(spec, parameter) {
+    return example;
+  }
+  schema_example(spec, &parameter["schema"], &mut Vec::new())
+}
+
+fn media_example(spec: &Value, media: &Value) -> Value {
+  if let Some(example) = media.get("example") {
+    return example.clone();
+  }
+  if let Some(example) = first_example(spec, media) {
+    return example;
+  }
+  schema_example(spec, &media["schema"], &mut Vec::new())
+}
+
+fn first_example(spec: &Value, value: &Value) -> Option<Value> {
+  let examples = value["examples"].as_object()?;
+  let example = resolve(spec, examples.values().next()?);
+  example.get("value").cloned()
+}
+
+/// Builds a placeholder value matching the schema. `stack` holds the
+/// references being expanded, so recursive schemas stop at the first cycle.
+fn schema_example<'a>(
+  spec: &'a Value,
+  schema: &'a Value,
+  stack: &mut Vec<&'a str>,
+) -> Value {
+  if let Some(reference) = schema["$ref"].as_str() {
+    if stack.contains(&reference) {
+      return Value::Null;
+    }
+    stack.push(reference);
+    let example = schema_example(spec, resolve(spec, schema), stack);
+    stack.pop();
+    return example;
+  }
+
+  for key in ["example", "default"] {
+    if let Some(example) = schema.get(key) {
+      return example.clone();
+    }
+  }
+  if let Some(value) = schema["enum"].get(0) {
+    return value.clone();
+  }
+  if let Some(schemas) = schema["allOf"].as_array() {
+    let mut merged = Map::new();
+    for schema in schemas {
+      if let Value::Object(map) = schema_example(spec, schema, stack) {
+        merged.extend(map);
+      }
+    }
+    return Value::Object(merged);
+  }
+  for key in ["oneOf", "anyOf"] {
+    if let Some(first) = schema[key].get(0) {
+      return schema_example(spec, first, stack);
+    }
+  }
+
+  match schema["type"].as_str() {
+    Some("object") => object_example(spec, schema, stack),
+    None if schema.get("properties").is_some() => {
+      object_example(spec, schema, stack)
+    }
+    Some("array") => match schema_example(spec, &schema["items"], stack) {
+      Value::Null => json!([]),
+      item => json!([item]),
+    },
+    Some("integer") => json!(0),
+    Some("number") => json!(0.0),
+    Some("boolean") => json!(true),
+    Some("string") => match schema["format"].as_str() {
+      Some("date") => json!("2024-01-01"),
+      Some("date-time") => json!("2024-01-01T00:00:00Z"),
+      Some("uuid") => json!("00000000-0000-0000-0000-000000000000"),
+      Some("email") => json!("user@example.com"),
+      _ => json!("string"),
+    },
+    _ => Value::Null,
+  }
+}
+
+fn object_example<'a>(
+  spec: &'a Value,
+  schema: &'a Value,
+  stack: &mut Vec<&'a str>,
+) -> Value {
+  let mut object = Map::new();
+  if let Some(properties) = schema["properties"].as_object() {
+    for (name, property) in properties {
+      // Skips properties only reachable through a recursive reference
+      let example = schema_example(spec, property, stack);
+      if !example.is_null() || property.get("$ref").is_none() {
+        object.insert(name.clone(), example);
+      }
+    }
+  }
+  Value::Object(object)
+}
+
+fn value_to_string(value: &Value) -> String {
+  match value {
+    Value::String(s) => s.clone(),
+    Value::Null => "1".to_owned(),
+    other => other.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SPEC: &str = "
+openapi: 3.0.0
+info:
+  title: Shop
+servers:
+  - url: https://shop.test/v1
+paths:
+  /users/{id}:
+    parameters:
+      - name: id
+        in: path
+        required: true
+        schema:
+          type: integer
+          example: 42
+    get:
+      operationId: getUser
+      parameters:
+        - name: fields
+          in: query
+          required: true
+          schema:
+            type: string
+        - name: debug
+          in: query
+          schema:
+            type: boolean
+    put:
+      summary: Update a user
+      requestBody:
+        content:
+          application/json:
+            schema:
+              $ref: '#/components/schemas/User'
+components:
+  schemas:
+    User:
+      type: object
+      properties:
+        name:
+          type: string
+          example: bob
+";
+
+  #[test]
+  fn converts_operations() {
+    let doc = convert(SPEC, "shop.yml").unwrap();
+
+    assert_eq!(doc.origin, "Imported from OpenAPI spec 'Shop'");
+    assert_eq!(doc.urls["api"], "https://shop.test/v1");
+    assert_eq!(doc.global["id"], "42");
+    assert!(doc.global.contains_key("fields"));
+    assert!(!doc.global.contains_key("debug"));
+
+    let names: Vec<_> = doc.plan.iter().map(|step| step.name.as_str()).collect();
+    assert_eq!(names, ["getUser", "Update a user"]);
+
+    let get = &doc.plan[0].request;
+    assert_eq!(get.method, "GET");
+    assert_eq!(get.base.as_deref(), Some("api"));
+    assert_eq!(get.url, "users/{{ id }}?fields={{ fields }}");
+    assert!(get.body.is_none());
+
+    let put = &doc.plan[1].request;
+    assert_eq!(put.url, "users/{{ id }}");
+    assert_eq!(put.headers["Content-Type"], "application/json");
+    let body: Value = serde_json::from_str(put.body.as_deref().unwrap()).unwrap();
+    assert_eq!(body, json!({"name": "bob"}));
+  }
+
+  #[test]
+  fn reports_invalid_specs() {
+    let err = convert("paths: [", "shop.yml").unwrap_err();
+
+    assert!(err.starts_with("Invalid OpenAPI spec shop.yml:"), "{}", err);
+  }
+}
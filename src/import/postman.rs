@@ -0,0 +1,365 @@
+use serde::Deserialize;
+
+use super::{
+  is_supported_method, read_source, warn, PlanDoc, PlanStep, RequestStep,
+};
+
+#[derive(Debug, Deserialize)]
+struct Collection {
+  info: Info,
+  #[serde(default)]
+  item: Vec<Item>,
+  #[serde(default)]
+  variable: Vec<Variable>,
+  auth: Option<Auth>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Info {
+  name: String,
+}
+
+/// Either a folder, holding more items, or a request
+#[derive(Debug, Deserialize)]
+struct Item {
+  #[serde(default)]
+  name: String,
+  item: Option<Vec<Item>>,
+  request: Option<RequestDef>,
+  auth: Option<Auth>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RequestDef {
+  Url(String),
+  Full(Box<PostmanRequest>),
+}
+
+#[derive(Debug, Deserialize)]
+struct PostmanRequest {
+  #[serde(default = "default_method")]
+  method: String,
+  #[serde(default)]
+  header: Vec<KeyValue>,
+  url: Option<UrlDef>,
+  body: Option<Body>,
+  auth: Option<Auth>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum UrlDef {
+  Raw(String),
+  Structured {
+    raw: Option<String>,
+    protocol: Option<String>,
+    #[serde(default)]
+    host: Vec<String>,
+    #[serde(default)]
+    path: Vec<String>,
+    #[serde(default)]
+    query: Vec<KeyValue>,
+  },
+}
+
+#[derive(Debug, Deserialize)]
+struct Body {
+  #[serde(default)]
+  mode: String,
+  raw: Option<String>,
+  #[serde(default)]
+  urlencoded: Vec<KeyValue>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Auth {
+  #[serde(rename = "type")]
+  typ: String,
+  #[serde(default)]
+  bearer: Vec<KeyValue>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct KeyValue {
+  key: String,
+  #[serde(default)]
+  value: serde_json::Value,
+  #[serde(default)]
+  disabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Variable {
+  key: String,
+  #[serde(default)]
+  value: serde_json::Value,
+}
+
+fn default_method() -> String {
+  "GET".into()
+}
+
+fn value_to_string(value: &serde_json::Value) -> String {
+  match value {
+    serde_json::Value::String(s) => s.clone(),
+    serde_json::Value::Null => String::new(),
+    other => other.to_string(),
+  }
+}
+
+struct Importer {
+  doc: PlanDoc,
+}
+
+/// Converts a Postman collection (v2.1) into a plan. Folders are flattened
+/// in order, collection variables become globals and every distinct base
+/// address becomes an entry in `urls`.
+pub fn import(path: &str) -> Result<PlanDoc, String> {
+  convert(&read_source(path)?, path)
+}
+
+fn convert(text: &str, path: &str) -> Result<PlanDoc, String> {
+  let collection: Collection = serde_json::from_str(text)
+    .map_err(|err| format!("Invalid Postman collection {path}: {err}"))?;
+
+  let mut importer = Importer {
+    doc: PlanDoc::new(format!(
+      "Imported from Postman collection '{}'",
+      collection.info.name
+    )),
+  };
+
+  for variable in &collection.variable {
+    importer
+      .doc
+      .global
+      .insert(variable.key.clone(), value_to_string(&variable.value));
+  }
+
+  importer.import_items(&collection.item, "", collection.auth.as_ref());
+  Ok(importer.doc)
+}
+
+impl Importer {
+  fn import_items(
+    &mut self,
+    items: &[Item],
+    prefix: &str,
+    auth: Option<&Auth>,
+  ) {
+    for item in items {
+      let name = if prefix.is_empty() {
+        item.name.clone()
+      } else {
+        format!("{} / {}", prefix, item.name)
+      };
+      let auth = item.auth.as_ref().or(auth);
+
+      if let Some(children) = &item.item {
+        self.import_items(children, &name, auth);
+      } else if let Some(request) = &item.request {
+        if let Some(step) = self.import_request(&name, request, auth) {
+          self.doc.plan.push(step);
+        }
+      }
+    }
+  }
+
+  fn import_request(
+    &mut self,
+    name: &str,
+    request: &RequestDef,
+    auth: Option<&Auth>,
+  ) -> Option<PlanStep> {
+    let request = match request {
+      RequestDef::Url(url) => {
+        return Some(PlanStep {
+          name: name.to_owned(),
+          request: self.request_step(url, "GET"),
+        })
+      }
+      RequestDef::Full(request) => request,
+    };
+
+    let method = request.method.to_uppercase();
+    if !is_supported_method(&method) {
+      warn(format!("Skipping '{name}': unsupported method {method}"));
+      return None;
+    }
+
+    let url = match &request.url {
+      Some(url) => raw_url(url),
+      None => {
+        warn(format!("Skipping '{name}': missing url"));
+        return None;
+      }
+    };
+
+    let mut step = self.request_step(&url, &method);
+
+    for header in request.header.iter().filter(|h| !h.disabled) {
+      step.headers.insert(header.key.clone(), value_to_string(&header.value));
+    }
+
+    match request.auth.as_ref().or(auth) {
+      Some(auth) if auth.typ == "bearer" => {
+        if let Some(token) = auth.bearer.iter().find(|kv| kv.key == "token") {
+          step.headers.insert(
+            "Authorization".to_owned(),
+            format!("Bearer {}", value_to_string(&token.value)),
+          );
+        }
+      }
+      Some(auth) if auth.typ != "noauth" => {
+        warn(format!("'{name}': unsupported auth type {}", auth.typ));
+      }
+      _ => {}
+    }
+
+    if let Some(body) = &request.body {
+      match body.mode.as_str() {
+        "raw" => step.body = body.raw.clone(),
+        "urlencoded" => {
+          let encoded = body
+            .urlencoded
+            .iter()
+            .filter(|kv| !kv.disabled)
+            .map(|kv| format!("{}={}", kv.key, value_to_string(&kv.value)))
+            .collect::<Vec<_>>()
+            .join("&");
+          step.body = Some(encoded);
+          step
+            .headers
+            .entry("Content-Type".to_owned())
+            .or_insert_with(|| "application/x-www-form-urlencoded".to_owned());
+        }
+        mode => warn(format!("'{name}': unsupported body mode {mode}")),
+      }
+    }
+
+    Some(PlanStep {
+      name: name.to_owned(),
+      request: step,
+    })
+  }
+
+  /// Splits the url into a base, registered in `urls`, and a relative part
+  fn request_step(&mut self, url: &str, method: &str) -> RequestStep {
+    let mut step = RequestStep {
+      url: url.to_owned(),
+      method: method.to_owned(),
+      ..Default::default()
+    };
+
+    // `{{baseUrl}}/users` style urls, with the base defined in the collection
+    if let Some(rest) = url.strip_prefix("{{") {
+      if let Some((variable, path)) = rest.split_once("}}") {
+        let variable = variable.trim();
+        if let Some(base) = self.doc.global.get(variable).cloned() {
+          self.doc.urls.insert(variable.to_owned(), base);
+        }
+        if self.doc.urls.contains_key(variable) {
+          step.base = Some(variable.to_owned());
+          step.url = path.trim_start_matches('/').to_owned();
+        }
+      }
+      return step;
+    }
+
+    if let Some((base, rest)) = self.doc.split_url(url) {
+      step.base = Some(base);
+      step.url = rest;
+    }
+    step
+  }
+}
+
+fn raw_url(url: &UrlDef) -> String {
+  match url {
+    UrlDef::Raw(raw) => raw.clone(),
+    UrlDef::Structured {
+      raw: Some(raw),
+      ..
+    } => raw.clone(),
+    UrlDef::Structured {
+      raw: None,
+      protocol,
+      host,
+      path,
+      query,
+    } => {
+      let mut url = match protocol {
+        Some(protocol) => format!("{}://{}", protocol, host.join(".")),
+        None => host.join("."),
+      };
+      if !path.is_empty() {
+        url = format!("{}/{}", url, path.join("/"));
+      }
+      let query: Vec<_> = query
+        .iter()
+        .filter(|kv| !kv.disabled)
+        .map(|kv| format!("{}={}", kv.key, value_to_string(&kv.value)))
+        .collect();
+      if !query.is_empty() {
+        url = format!("{}?{}", url, query.join("&"));
+      }
+      url
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn converts_collections() {
+    let collection = r#"{
+      "info": {"name": "Shop"},
+      "variable": [{"key": "baseUrl", "value": "https://shop.test/api"}],
+      "auth": {"type": "bearer", "bearer": [{"key": "token", "value": "{{token}}"}]},
+      "item": [
+        {"name": "Users", "item": [
+          {"name": "List", "request": {"method": "GET", "url": {"raw": "{{baseUrl}}/users?page=1"}}},
+          {"name": "Create", "request": {"method": "POST", "url": "{{baseUrl}}/users",
+            "header": [{"key": "X-Trace", "value": "1"}, {"key": "X-Off", "value": "1", "disabled": true}],
+            "body": {"mode": "urlencoded", "urlencoded": [{"key": "name", "value": "bob"}]}}}
+        ]},
+        {"name": "Health", "request": "http://status.test/health"},
+        {"name": "Copy", "request": {"method": "COPY", "url": "{{baseUrl}}/users"}}
+      ]
+    }"#;
+    let doc = convert(collection, "shop.json").unwrap();
+
+    assert_eq!(doc.origin, "Imported from Postman collection 'Shop'");
+    assert_eq!(doc.global["baseUrl"], "https://shop.test/api");
+    assert_eq!(doc.urls["baseUrl"], "https://shop.test/api");
+    assert_eq!(doc.urls["status_test"], "http://status.test");
+
+    let names: Vec<_> = doc.plan.iter().map(|step| step.name.as_str()).collect();
+    assert_eq!(names, ["Users / List", "Users / Create", "Health"]);
+
+    let list = &doc.plan[0].request;
+    assert_eq!(list.base.as_deref(), Some("baseUrl"));
+    assert_eq!(list.url, "users?page=1");
+    assert_eq!(list.headers["Authorization"], "Bearer {{token}}");
+
+    let create = &doc.plan[1].request;
+    assert_eq!(create.method, "POST");
+    assert_eq!(create.body.as_deref(), Some("name=bob"));
+    assert_eq!(create.headers["Content-Type"], "application/x-www-form-urlencoded");
+    assert_eq!(create.headers["X-Trace"], "1");
+    assert!(!create.headers.contains_key("X-Off"));
+
+    let health = &doc.plan[2].request;
+    assert_eq!((health.base.as_deref(), health.url.as_str()), (Some("status_test"), "health"));
+  }
+
+  #[test]
+  fn reports_invalid_collections() {
+    let err = convert("{\"item\": []}", "shop.json").unwrap_err();
+
+    assert!(err.starts_with("Invalid Postman collection shop.json: missing field `info`"), "{}", err);
+  }
+}
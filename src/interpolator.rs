@@ -1,15 +1,20 @@
-use colored::*;
+use std::cmp::Ordering;
+
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
 use serde_json::json;
 
 use crate::benchmark::Context;
+use crate::error::Error;
 
 const INTERPOLATION_PREFIX: &str = "{{";
 const INTERPOLATION_SUFFIX: &str = "}}";
 const GLOBAL: &str = "global";
 
 lazy_static! {
+  // Splits a condition into its operands around the comparison operator
+  static ref COMPARISON_REGEX: Regex =
+    Regex::new(r"^(.*?)\s*(==|!=|<=|>=|<|>)\s*(.*)$").unwrap();
   pub static ref INTERPOLATION_REGEX: Regex = {
     let regexp = format!(
       "{}{}{}",
@@ -33,44 +38,75 @@ impl<'a> Interpolator<'a> {
     }
   }
 
-  pub fn resolve(&self, resolvable: &str) -> String {
-    INTERPOLATION_REGEX
-      .replace_all(resolvable, |caps: &Captures| {
-        let capture = &caps[1];
-
-        if let Some(item) =
-          self.resolve_global_reference(capture)
-        {
-          return item;
-        }
-
-        if let Some(item) =
-          self.resolve_env_file_reference(capture)
-        {
-          return item;
-        }
-
-        if let Some(item) =
-          self.resolve_environment_interpolation(capture)
-        {
-          return item;
-        }
+  /// Replaces every interpolation, failing on the first variable that
+  /// can't be resolved
+  pub fn resolve(&self, resolvable: &str) -> Result<String, Error> {
+    let mut missing = None;
+    let resolved =
+      INTERPOLATION_REGEX.replace_all(resolvable, |caps: &Captures| {
+        self.resolve_reference(&caps[1]).unwrap_or_else(|| {
+          missing.get_or_insert_with(|| caps[1].to_owned());
+          String::new()
+        })
+      });
 
-        if let Some(item) =
-          self.resolve_context_interpolation(capture)
-        {
-          return item;
-        }
+    match missing {
+      Some(name) => Err(Error::UnknownVariable(name)),
+      None => Ok(resolved.to_string()),
+    }
+  }
 
-        panic!(
-          "{} Couldn't resolve variable '{}'",
-          "ERROR:".yellow().bold(),
-          capture
-        );
+  /// Like `resolve`, but leaves the interpolations it can't resolve as
+  /// they are
+  pub fn resolve_known(&self, resolvable: &str) -> String {
+    INTERPOLATION_REGEX
+      .replace_all(resolvable, |caps: &Captures| {
+        self.resolve_reference(&caps[1]).unwrap_or_else(|| caps[0].to_owned())
       })
       .to_string()
   }
 
+  /// Evaluates a condition like `{{ login.status }} == 200`. Operands are
+  /// compared as numbers when both are, and as strings otherwise, and may
+  /// be quoted. Without an operator, the condition holds unless it resolves
+  /// to an empty string, `false`, `0` or `null`.
+  pub fn evaluate(&self, condition: &str) -> Result<bool, Error> {
+    let caps = match COMPARISON_REGEX.captures(condition) {
+      Some(caps) => caps,
+      None => {
+        let value = self.resolve(condition)?;
+        let value = unquote(&value);
+        return Ok(!["", "false", "0", "null"].contains(&value));
+      }
+    };
+
+    let lhs = self.resolve(&caps[1])?;
+    let rhs = self.resolve(&caps[3])?;
+    let (lhs, rhs) = (unquote(&lhs), unquote(&rhs));
+    let ordering = match (lhs.parse::<f64>(), rhs.parse::<f64>()) {
+      (Ok(lhs), Ok(rhs)) => lhs.partial_cmp(&rhs),
+      _ => Some(lhs.cmp(rhs)),
+    };
+
+    Ok(match (&caps[2], ordering) {
+      ("==", ordering) => ordering == Some(Ordering::Equal),
+      ("!=", ordering) => ordering != Some(Ordering::Equal),
+      (_, None) => false,
+      ("<", Some(ordering)) => ordering == Ordering::Less,
+      ("<=", Some(ordering)) => ordering != Ordering::Greater,
+      (">", Some(ordering)) => ordering == Ordering::Greater,
+      (_, Some(ordering)) => ordering != Ordering::Less,
+    })
+  }
+
+  fn resolve_reference(&self, capture: &str) -> Option<String> {
+    self
+      .resolve_global_reference(capture)
+      .or_else(|| self.resolve_env_file_reference(capture))
+      .or_else(|| self.resolve_environment_interpolation(capture))
+      .or_else(|| self.resolve_context_interpolation(capture))
+  }
+
   fn resolve_global_reference(
     &self,
     value: &str,
@@ -79,8 +115,9 @@ impl<'a> Interpolator<'a> {
       .context
       .get(GLOBAL)?
       .as_object()?
-      .get(value)
-      .map(|v| v.as_str().unwrap().to_owned())
+      .get(value)?
+      .as_str()
+      .map(|v| v.to_owned())
   }
 
   fn resolve_env_file_reference(
@@ -130,6 +167,14 @@ impl<'a> Interpolator<'a> {
   }
 }
 
+fn unquote(value: &str) -> &str {
+  let value = value.trim();
+  ['"', '\'']
+    .iter()
+    .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+    .unwrap_or(value)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -150,7 +195,7 @@ mod tests {
 
     let interpolator = Interpolator::new(&context);
     let url = String::from("http://example.com/users/{{ user_Id }}/view/{{ user_Id }}/{{ Transfer-Encoding }}");
-    let interpolated = interpolator.resolve(&url);
+    let interpolated = interpolator.resolve(&url).unwrap();
 
     assert_eq!(
       interpolated,
@@ -183,66 +228,83 @@ mod tests {
     let interpolator = Interpolator::new(&context);
 
     assert_eq!(
-      interpolator.resolve("{{ Null }}"),
+      interpolator.resolve("{{ Null }}").unwrap(),
       "".to_string()
     );
     assert_eq!(
-      interpolator.resolve("{{ Bool }}"),
+      interpolator.resolve("{{ Bool }}").unwrap(),
       "true".to_string()
     );
     assert_eq!(
-      interpolator.resolve("{{ Number }}"),
+      interpolator.resolve("{{ Number }}").unwrap(),
       "12".to_string()
     );
     assert_eq!(
-      interpolator.resolve("{{ String }}"),
+      interpolator.resolve("{{ String }}").unwrap(),
       "string".to_string()
     );
     assert_eq!(
-      interpolator.resolve("{{ Array }}"),
+      interpolator.resolve("{{ Array }}").unwrap(),
       "[\"a\",\"b\",\"c\"]".to_string()
     );
     assert_eq!(
-      interpolator.resolve("{{ Object }}"),
+      interpolator.resolve("{{ Object }}").unwrap(),
       "{\"this\":\"that\"}".to_string()
     );
     assert_eq!(
       interpolator.resolve(
         "{{ Nested.this.that.those[2].deee.eeee }}"
-      ),
+      ).unwrap(),
       "eeep".to_string()
     );
     assert_eq!(
       interpolator
-        .resolve("{{ ArrayNested[0].a[1].aaa[0].aaaa }}"),
+        .resolve("{{ ArrayNested[0].a[1].aaa[0].aaaa }}")
+        .unwrap(),
       "123".to_string()
     );
     assert_eq!(
       interpolator
-        .resolve("{{ ArrayNested[0].a[1].aaa[0].$aaaa }}"),
+        .resolve("{{ ArrayNested[0].a[1].aaa[0].$aaaa }}")
+        .unwrap(),
       "$123".to_string()
     );
   }
 
   #[test]
-  #[should_panic]
   fn interpolates_missing_variable() {
     let context: Context = Context::new();
 
     let interpolator = Interpolator::new(&context);
     let url = String::from("/users/{{ userId }}");
-    interpolator.resolve(&url);
+    let err = interpolator.resolve(&url).unwrap_err();
+
+    assert_eq!(err.to_string(), "Couldn't resolve variable 'userId'");
   }
 
   #[test]
   fn interpolates_relaxed() {
     let context: Context = Context::new();
 
+    // `resolve` fails on unknown variables, `resolve_known` keeps them
     let interpolator = Interpolator::new(&context);
     let url = String::from("/users/{{ userId }}");
-    let interpolated = interpolator.resolve(&url);
+    let interpolated = interpolator.resolve_known(&url);
 
-    assert_eq!(interpolated, "/users/");
+    assert_eq!(interpolated, "/users/{{ userId }}");
+  }
+
+  #[test]
+  fn keeps_unknown_variables() {
+    let mut context: Context = Context::new();
+
+    context.insert(String::from("userId"), json!(String::from("12")));
+
+    let interpolator = Interpolator::new(&context);
+    let url = String::from("/users/{{ userId }}/{{ account.body.id }}");
+    let interpolated = interpolator.resolve_known(&url);
+
+    assert_eq!(interpolated, "/users/12/{{ account.body.id }}");
   }
 
   #[test]
@@ -256,7 +318,7 @@ mod tests {
 
     let interpolator = Interpolator::new(&context);
     let url = String::from("http://example.com/postalcode/{{ zip5 }}/view/{{ zip5 }}");
-    let interpolated = interpolator.resolve(&url);
+    let interpolated = interpolator.resolve(&url).unwrap();
 
     assert_eq!(
       interpolated,
@@ -275,7 +337,7 @@ mod tests {
 
     let interpolator = Interpolator::new(&context);
     let url = String::from("http://example.com/postalcode/{{ 5digitzip }}/view/{{ 5digitzip }}");
-    let interpolated = interpolator.resolve(&url);
+    let interpolated = interpolator.resolve(&url).unwrap();
 
     assert_eq!(interpolated, "http://example.com/postalcode/{{ 5digitzip }}/view/{{ 5digitzip }}");
   }
@@ -289,11 +351,31 @@ mod tests {
     let url = String::from(
       "http://example.com/postalcode/{{ FOO }}",
     );
-    let interpolated = interpolator.resolve(&url);
+    let interpolated = interpolator.resolve(&url).unwrap();
 
     assert_eq!(
       interpolated,
       "http://example.com/postalcode/BAR"
     );
   }
+
+  #[test]
+  fn evaluates_conditions() {
+    let mut context: Context = Context::new();
+
+    context.insert(String::from("login"), json!({"status": 200}));
+    context.insert(String::from("role"), json!("admin"));
+    context.insert(String::from("empty"), json!(""));
+
+    let interpolator = Interpolator::new(&context);
+
+    assert!(interpolator.evaluate("{{ login.status }} == 200").unwrap());
+    assert!(interpolator.evaluate("{{ login.status }} < 300").unwrap());
+    assert!(!interpolator.evaluate("{{ login.status }} >= 400").unwrap());
+    assert!(interpolator.evaluate("{{ role }} == 'admin'").unwrap());
+    assert!(interpolator.evaluate("{{ role }} != guest").unwrap());
+    assert!(interpolator.evaluate("{{ role }}").unwrap());
+    assert!(!interpolator.evaluate("{{ empty }}").unwrap());
+    assert!(interpolator.evaluate("{{ missing }} == 1").is_err());
+  }
 }
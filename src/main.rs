@@ -3,58 +3,223 @@ mod args;
 mod benchmark;
 mod checker;
 mod config;
+mod connections;
+mod curl;
 mod db;
+mod dns;
+mod dry_run;
+mod error;
+mod extract;
+mod history;
+mod import;
 mod interpolator;
+mod output;
 mod parse;
+mod plugin;
 mod reader;
+mod record;
 mod tags;
+mod tune;
+mod watch;
 mod writer;
 
 use crate::actions::Report;
-use args::Cli;
+use crate::connections::ConnectionStats;
+use crate::dns::DnsStats;
+use crate::parse::{Metadata, MetricKind, MetricStat};
+use args::{BaselineAction, Cli, Command, FlattenedCli, TimeUnit};
 use clap::Parser;
 use colored::*;
 use hdrhistogram::Histogram;
 use linked_hash_map::LinkedHashMap;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::env;
 use std::process;
 
 fn main() {
-  let args = Cli::parse().into_flattened();
+  let mut cli = Cli::parse();
+
+  // NO_COLOR is honored unless empty, see https://no-color.org
+  let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+  if cli.no_color || no_color {
+    control::set_override(false);
+  }
+
+  if let Some(command) = cli.command.take() {
+    run_command(command);
+    process::exit(0);
+  }
+
+  let args = cli.into_flattened();
+  parse::select_environment(args.environment.clone());
+  parse::set_max_include_depth(args.max_include_depth);
 
   #[cfg(windows)]
   let _ = control::set_virtual_terminal(true);
 
   if args.list_tags {
-    tags::list_benchmark_file_tags(&args.benchmark_file);
+    tags::list_benchmark_file_tags(&args.benchmark_file, args.list_format);
     process::exit(0);
   };
 
-  let tags = tags::Tags::new(args.tags.clone(), args.skip_tags_option.clone());
+  let tags = tags::Tags::new(
+    args.tags.clone(),
+    args.skip_tags_option.clone(),
+    args.tag_expression_option.clone(),
+  );
 
   if args.list_tasks {
-    tags::list_benchmark_file_tasks(&args.benchmark_file, &tags);
+    tags::list_benchmark_file_tasks(
+      &args.benchmark_file,
+      &tags,
+      args.list_format,
+    );
     process::exit(0);
   };
 
+  if args.dry_run {
+    match dry_run::validate(&args) {
+      Ok(_) => process::exit(0),
+      Err(_) => process::exit(1),
+    }
+  }
+
+  if args.export_curl {
+    curl::export(&args);
+    process::exit(0);
+  }
+
+  if args.watch {
+    watch::run(&args);
+  }
+
+  let out_dir = args.out_dir_option.as_deref().map(writer::create_run_dir);
+  let args = match &out_dir {
+    Some(dir) => args.with_out_dir(dir),
+    None => args,
+  };
+
   let benchmark_result = benchmark::execute(&args);
   let list_reports = benchmark_result.reports;
+  let run_stats = benchmark_result.stats;
   let duration = benchmark_result.duration;
 
-  show_stats(&list_reports, args.stats_option, args.nanosec, duration);
-  compare_benchmark(
-    &list_reports,
+  show_stats(
+    &run_stats,
+    &benchmark_result.metadata,
+    args.stats_option,
+    args.time_unit,
+    duration,
+    benchmark_result.errors,
+  );
+  if let Some(path) = &args.save_run_option {
+    history::save_run(
+      path,
+      &args.benchmark_file,
+      &list_reports,
+      &run_stats,
+      duration,
+    );
+  }
+  if let Some(path) = &args.save_baseline_option {
+    checker::save_baseline(
+      path,
+      &args.benchmark_file,
+      &benchmark_result.metadata,
+      &run_stats,
+      duration,
+    );
+  }
+  if let Some(path) = &args.hgrm_option {
+    writer::write_hgrm(path, &run_stats.global.hist);
+  }
+  if let Some(dir) = &out_dir {
+    println!(
+      "{} {}",
+      "Saved the run into".green(),
+      dir.display().to_string().purple()
+    );
+  }
+  let thresholds_passed = checker::check_thresholds(
+    &run_stats,
+    &benchmark_result.thresholds,
+    duration,
+  );
+  let compare_passed = compare_benchmark(
+    &run_stats,
     args.compare_path_option.as_deref(),
     args.threshold_option.as_deref(),
   );
 
-  process::exit(0)
+  process::exit(if thresholds_passed && compare_passed { 0 } else { 1 })
+}
+
+fn run_command(command: Command) {
+  match command {
+    Command::Import {
+      source,
+    } => import::run(source),
+    Command::Record {
+      port,
+      skip_static,
+      output,
+    } => record::run(port, skip_static, output),
+    Command::History {
+      database,
+    } => history::list_runs(&database),
+    Command::Diff {
+      database,
+      first,
+      second,
+    } => history::diff_runs(&database, first, second),
+    Command::Baseline {
+      action,
+    } => run_baseline(action),
+  }
+}
+
+fn run_baseline(action: BaselineAction) {
+  match action {
+    BaselineAction::Save {
+      file,
+      benchmark,
+      vars,
+    } => {
+      let args = FlattenedCli::for_benchmark(benchmark, vars);
+      let result = benchmark::execute(&args);
+      checker::save_baseline(
+        &file,
+        &args.benchmark_file,
+        &result.metadata,
+        &result.stats,
+        result.duration,
+      );
+    }
+    BaselineAction::Check {
+      file,
+      benchmark,
+      vars,
+      threshold,
+    } => {
+      let args = FlattenedCli::for_benchmark(benchmark, vars);
+      let result = benchmark::execute(&args);
+      if checker::compare(&result.stats, &file, &threshold).is_err() {
+        process::exit(1);
+      }
+    }
+  }
 }
 
 struct DrillStats {
   total_requests: usize,
   successful_requests: usize,
   failed_requests: usize,
+  /// Requests failing with one of the `expected_failures`
+  expected_failures: usize,
+  /// Requests answered with a 304, their cached copy still being valid
+  not_modified_requests: usize,
+  /// Requests taking longer than `--slow-threshold`
+  slow_requests: usize,
   hist: Histogram<u64>,
 }
 
@@ -73,59 +238,246 @@ impl DrillStats {
   }
 }
 
+impl Default for DrillStats {
+  fn default() -> Self {
+    DrillStats {
+      total_requests: 0,
+      successful_requests: 0,
+      failed_requests: 0,
+      expected_failures: 0,
+      not_modified_requests: 0,
+      slow_requests: 0,
+      hist: Histogram::<u64>::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap(),
+    }
+  }
+}
+
+impl DrillStats {
+  fn merge(&mut self, other: &DrillStats) {
+    self.hist.add(&other.hist).unwrap();
+    self.total_requests += other.total_requests;
+    self.successful_requests += other.successful_requests;
+    self.failed_requests += other.failed_requests;
+    self.expected_failures += other.expected_failures;
+    self.not_modified_requests += other.not_modified_requests;
+    self.slow_requests += other.slow_requests;
+  }
+
+  fn record(&mut self, report: &Report) {
+    self.hist += (report.duration * 1_000.0) as u64;
+    self.total_requests += 1;
+    if report.success {
+      self.successful_requests += 1;
+    } else if report.expected_failure {
+      self.expected_failures += 1;
+    } else {
+      self.failed_requests += 1;
+    }
+    if report.status == 304 {
+      self.not_modified_requests += 1;
+    }
+    if report.slow {
+      self.slow_requests += 1;
+    }
+  }
+}
+
 fn compute_stats(sub_reports: &[Report]) -> DrillStats {
-  let mut hist =
-    Histogram::<u64>::new_with_bounds(1, 60 * 60 * 1000, 2).unwrap();
-  let mut group_by_status = HashMap::new();
+  let mut stats = DrillStats::default();
+  for report in sub_reports {
+    stats.record(report);
+  }
+  stats
+}
+
+/// Samples of a custom metric, aggregated according to its kind
+struct MetricStats {
+  kind: MetricKind,
+  count: usize,
+  sum: f64,
+  min: f64,
+  max: f64,
+  last: f64,
+  /// Every sample of trends, for their percentiles
+  values: Vec<f64>,
+}
+
+impl MetricStats {
+  fn new(kind: MetricKind) -> Self {
+    MetricStats {
+      kind,
+      count: 0,
+      sum: 0.0,
+      min: f64::INFINITY,
+      max: f64::NEG_INFINITY,
+      last: 0.0,
+      values: Vec::new(),
+    }
+  }
 
-  for req in sub_reports {
-    group_by_status.entry(req.status / 100).or_insert_with(Vec::new).push(req);
+  fn record(&mut self, value: f64) {
+    self.count += 1;
+    self.sum += value;
+    self.min = self.min.min(value);
+    self.max = self.max.max(value);
+    self.last = value;
+    if self.kind == MetricKind::Trend {
+      self.values.push(value);
+    }
   }
 
-  for r in sub_reports.iter() {
-    hist += (r.duration * 1_000.0) as u64;
+  fn merge(&mut self, other: MetricStats) {
+    self.count += other.count;
+    self.sum += other.sum;
+    self.min = self.min.min(other.min);
+    self.max = self.max.max(other.max);
+    self.last = other.last;
+    self.values.extend(other.values);
   }
 
-  let total_requests = sub_reports.len();
-  let successful_requests =
-    group_by_status.entry(2).or_insert_with(Vec::new).len();
-  let failed_requests = total_requests - successful_requests;
+  fn mean(&self) -> f64 {
+    self.sum / self.count as f64
+  }
 
-  DrillStats {
-    total_requests,
-    successful_requests,
-    failed_requests,
-    hist,
+  /// Value of a statistic, `None` when it doesn't apply to the metric kind
+  fn stat(&self, stat: Option<MetricStat>, duration: f64) -> Option<f64> {
+    let stat = stat.unwrap_or(match self.kind {
+      MetricKind::Counter => MetricStat::Total,
+      MetricKind::Gauge => MetricStat::Value,
+      MetricKind::Trend => MetricStat::Avg,
+    });
+    let trend = self.kind == MetricKind::Trend;
+    Some(match stat {
+      MetricStat::Total => self.sum,
+      MetricStat::Rate => self.sum / duration,
+      MetricStat::Value => self.last,
+      MetricStat::Count => self.count as f64,
+      MetricStat::Avg => self.mean(),
+      MetricStat::Min => self.min,
+      MetricStat::Max => self.max,
+      MetricStat::Median if trend => self.value_at_quantile(0.5),
+      MetricStat::P90 if trend => self.value_at_quantile(0.9),
+      MetricStat::P95 if trend => self.value_at_quantile(0.95),
+      MetricStat::P99 if trend => self.value_at_quantile(0.99),
+      _ => return None,
+    })
+  }
+
+  fn value_at_quantile(&self, quantile: f64) -> f64 {
+    let mut values = self.values.clone();
+    values.sort_by(f64::total_cmp);
+    let index = (quantile * (values.len() - 1) as f64).round() as usize;
+    values[index]
   }
 }
 
-fn format_time(tdiff: f64, nanosec: bool) -> String {
-  if nanosec {
-    (1_000_000.0 * tdiff).round().to_string() + "ns"
-  } else {
-    tdiff.round().to_string() + "ms"
+/// Stats of a whole run, per step and overall, aggregated as the iterations
+/// finish so the reports themselves don't need to be kept around.
+#[derive(Default)]
+pub struct RunStats {
+  steps: LinkedHashMap<String, DrillStats>,
+  global: DrillStats,
+  /// Steps of the included files, by the top-level include
+  sources: LinkedHashMap<String, DrillStats>,
+  /// Custom metrics, by name
+  metrics: LinkedHashMap<String, MetricStats>,
+  iterations: usize,
+  /// Lookups of the whole run, shared by the shards
+  dns: DnsStats,
+  /// Connections of the whole run, by host
+  connections: BTreeMap<String, ConnectionStats>,
+  /// Iterations run again after an error, see `iteration_retries`
+  retried_iterations: usize,
+}
+
+impl RunStats {
+  /// Adds the stats of another part of the run, like a `--sharded` shard
+  pub fn merge(&mut self, other: RunStats) {
+    self.iterations += other.iterations;
+    self.global.merge(&other.global);
+    for (name, stats) in other.steps {
+      match self.steps.get_mut(&name) {
+        Some(merged) => merged.merge(&stats),
+        None => {
+          self.steps.insert(name, stats);
+        }
+      }
+    }
+    for (source, stats) in other.sources {
+      match self.sources.get_mut(&source) {
+        Some(merged) => merged.merge(&stats),
+        None => {
+          self.sources.insert(source, stats);
+        }
+      }
+    }
+    for (name, stats) in other.metrics {
+      match self.metrics.get_mut(&name) {
+        Some(merged) => merged.merge(stats),
+        None => {
+          self.metrics.insert(name, stats);
+        }
+      }
+    }
+  }
+
+  pub fn record(&mut self, reports: &[Report]) {
+    self.iterations += 1;
+    // Only the last attempt of a retried iteration counts
+    for report in reports.iter().filter(|report| !report.superseded) {
+      if let Some(sample) = &report.metric {
+        match self.metrics.get_mut(&report.name) {
+          Some(stats) => stats.record(sample.value),
+          None => {
+            let mut stats = MetricStats::new(sample.kind);
+            stats.record(sample.value);
+            self.metrics.insert(report.name.clone(), stats);
+          }
+        }
+        continue;
+      }
+      // Transactions would count their requests twice in the totals
+      if !report.transaction {
+        self.global.record(report);
+      }
+      match self.steps.get_mut(&report.name) {
+        Some(stats) => stats.record(report),
+        None => {
+          let mut stats = DrillStats::default();
+          stats.record(report);
+          self.steps.insert(report.name.clone(), stats);
+        }
+      }
+      // Transactions would count their requests twice here too
+      let source = report.source.as_ref().filter(|_| !report.transaction);
+      if let Some(source) = source {
+        match self.sources.get_mut(source) {
+          Some(stats) => stats.record(report),
+          None => {
+            let mut stats = DrillStats::default();
+            stats.record(report);
+            self.sources.insert(source.clone(), stats);
+          }
+        }
+      }
+    }
   }
 }
 
 fn show_stats(
-  list_reports: &[Vec<Report>],
+  run_stats: &RunStats,
+  metadata: &Metadata,
   stats_option: bool,
-  nanosec: bool,
+  time_unit: TimeUnit,
   duration: f64,
+  errors: usize,
 ) {
   if !stats_option {
     return;
   }
 
-  let mut group_by_name = LinkedHashMap::new();
-
-  for req in list_reports.concat() {
-    group_by_name.entry(req.name.clone()).or_insert_with(Vec::new).push(req);
-  }
-
-  // compute stats per name
-  for (name, reports) in group_by_name {
-    let substats = compute_stats(&reports);
+  // stats per name
+  for (name, substats) in run_stats.steps.iter() {
     println!();
     println!(
       "{:width$} {:width2$} {}",
@@ -151,11 +503,41 @@ fn show_stats(
       width = 25,
       width2 = 25
     );
+    if substats.not_modified_requests > 0 {
+      println!(
+        "{:width$} {:width2$} {}",
+        name.green(),
+        "Not modified requests".yellow(),
+        substats.not_modified_requests.to_string().purple(),
+        width = 25,
+        width2 = 25
+      );
+    }
+    if substats.expected_failures > 0 {
+      println!(
+        "{:width$} {:width2$} {}",
+        name.green(),
+        "Expected failures".yellow(),
+        substats.expected_failures.to_string().purple(),
+        width = 25,
+        width2 = 25
+      );
+    }
+    if substats.slow_requests > 0 {
+      println!(
+        "{:width$} {:width2$} {}",
+        name.green(),
+        "Slow requests".yellow(),
+        substats.slow_requests.to_string().purple(),
+        width = 25,
+        width2 = 25
+      );
+    }
     println!(
       "{:width$} {:width2$} {}",
       name.green(),
       "Median time per request".yellow(),
-      format_time(substats.median_duration(), nanosec).purple(),
+      time_unit.format(substats.median_duration()).purple(),
       width = 25,
       width2 = 25
     );
@@ -163,7 +545,7 @@ fn show_stats(
       "{:width$} {:width2$} {}",
       name.green(),
       "Average time per request".yellow(),
-      format_time(substats.mean_duration(), nanosec).purple(),
+      time_unit.format(substats.mean_duration()).purple(),
       width = 25,
       width2 = 25
     );
@@ -171,7 +553,7 @@ fn show_stats(
       "{:width$} {:width2$} {}",
       name.green(),
       "Sample standard deviation".yellow(),
-      format_time(substats.stdev_duration(), nanosec).purple(),
+      time_unit.format(substats.stdev_duration()).purple(),
       width = 25,
       width2 = 25
     );
@@ -179,7 +561,7 @@ fn show_stats(
       "{:width$} {:width2$} {}",
       name.green(),
       "99.0'th percentile".yellow(),
-      format_time(substats.value_at_quantile(0.99), nanosec).purple(),
+      time_unit.format(substats.value_at_quantile(0.99)).purple(),
       width = 25,
       width2 = 25
     );
@@ -187,7 +569,7 @@ fn show_stats(
       "{:width$} {:width2$} {}",
       name.green(),
       "99.5'th percentile".yellow(),
-      format_time(substats.value_at_quantile(0.995), nanosec).purple(),
+      time_unit.format(substats.value_at_quantile(0.995)).purple(),
       width = 25,
       width2 = 25
     );
@@ -195,18 +577,23 @@ fn show_stats(
       "{:width$} {:width2$} {}",
       name.green(),
       "99.9'th percentile".yellow(),
-      format_time(substats.value_at_quantile(0.999), nanosec).purple(),
+      time_unit.format(substats.value_at_quantile(0.999)).purple(),
       width = 25,
       width2 = 25
     );
+    let prefix = format!("{:25} ", name.green());
+    show_histogram(&prefix, &substats.hist, time_unit);
   }
 
-  // compute global stats
-  let allreports = list_reports.concat();
-  let global_stats = compute_stats(&allreports);
+  show_sources(run_stats, time_unit);
+  show_metrics(run_stats, duration);
+
+  // global stats
+  let global_stats = &run_stats.global;
   let requests_per_second = global_stats.total_requests as f64 / duration;
 
   println!();
+  benchmark::show_metadata(metadata);
   println!(
     "{:width2$} {} {}",
     "Time taken for tests".yellow(),
@@ -232,6 +619,46 @@ fn show_stats(
     global_stats.failed_requests.to_string().purple(),
     width2 = 25
   );
+  if global_stats.not_modified_requests > 0 {
+    println!(
+      "{:width2$} {}",
+      "Not modified requests".yellow(),
+      global_stats.not_modified_requests.to_string().purple(),
+      width2 = 25
+    );
+  }
+  if global_stats.expected_failures > 0 {
+    println!(
+      "{:width2$} {}",
+      "Expected failures".yellow(),
+      global_stats.expected_failures.to_string().purple(),
+      width2 = 25
+    );
+  }
+  if global_stats.slow_requests > 0 {
+    println!(
+      "{:width2$} {}",
+      "Slow requests".yellow(),
+      global_stats.slow_requests.to_string().purple(),
+      width2 = 25
+    );
+  }
+  if run_stats.retried_iterations > 0 {
+    println!(
+      "{:width2$} {}",
+      "Retried iterations".yellow(),
+      run_stats.retried_iterations.to_string().purple(),
+      width2 = 25
+    );
+  }
+  if errors > 0 {
+    println!(
+      "{:width2$} {}",
+      "Errored iterations".yellow(),
+      errors.to_string().purple(),
+      width2 = 25
+    );
+  }
   println!(
     "{:width2$} {} {}",
     "Requests per second".yellow(),
@@ -242,57 +669,241 @@ fn show_stats(
   println!(
     "{:width2$} {}",
     "Median time per request".yellow(),
-    format_time(global_stats.median_duration(), nanosec).purple(),
+    time_unit.format(global_stats.median_duration()).purple(),
     width2 = 25
   );
   println!(
     "{:width2$} {}",
     "Average time per request".yellow(),
-    format_time(global_stats.mean_duration(), nanosec).purple(),
+    time_unit.format(global_stats.mean_duration()).purple(),
     width2 = 25
   );
   println!(
     "{:width2$} {}",
     "Sample standard deviation".yellow(),
-    format_time(global_stats.stdev_duration(), nanosec).purple(),
+    time_unit.format(global_stats.stdev_duration()).purple(),
     width2 = 25
   );
   println!(
     "{:width2$} {}",
     "99.0'th percentile".yellow(),
-    format_time(global_stats.value_at_quantile(0.99), nanosec).purple(),
+    time_unit.format(global_stats.value_at_quantile(0.99)).purple(),
     width2 = 25
   );
   println!(
     "{:width2$} {}",
     "99.5'th percentile".yellow(),
-    format_time(global_stats.value_at_quantile(0.995), nanosec).purple(),
+    time_unit.format(global_stats.value_at_quantile(0.995)).purple(),
     width2 = 25
   );
   println!(
     "{:width2$} {}",
     "99.9'th percentile".yellow(),
-    format_time(global_stats.value_at_quantile(0.999), nanosec).purple(),
+    time_unit.format(global_stats.value_at_quantile(0.999)).purple(),
     width2 = 25
   );
+  show_histogram("", &global_stats.hist, time_unit);
+
+  let dns = &run_stats.dns;
+  if dns.lookups > 0 {
+    println!(
+      "{:width2$} {}",
+      "DNS lookups".yellow(),
+      dns.lookups.to_string().purple(),
+      width2 = 25
+    );
+    if dns.failed_lookups > 0 {
+      println!(
+        "{:width2$} {}",
+        "Failed DNS lookups".yellow(),
+        dns.failed_lookups.to_string().purple(),
+        width2 = 25
+      );
+    }
+    println!(
+      "{:width2$} {}",
+      "Average DNS lookup time".yellow(),
+      time_unit.format(dns.mean_duration()).purple(),
+      width2 = 25
+    );
+    println!(
+      "{:width2$} {}",
+      "Slowest DNS lookup".yellow(),
+      time_unit.format(dns.max_duration).purple(),
+      width2 = 25
+    );
+  }
+
+  for (host, connections) in run_stats.connections.iter() {
+    println!(
+      "{:width2$} {} {} {}",
+      "Connections".yellow(),
+      host.green(),
+      format!("{} new,", connections.new_connections).purple(),
+      format!("{} reused", connections.reused_connections).purple(),
+      width2 = 25
+    );
+  }
+}
+
+/// Prints the distribution of the durations, in microseconds, as bars over
+/// even buckets, the last one gathering the slowest 1% of the requests
+fn show_histogram(prefix: &str, hist: &Histogram<u64>, time_unit: TimeUnit) {
+  const BUCKETS: u64 = 10;
+  const BAR_WIDTH: u64 = 40;
+  if hist.is_empty() {
+    return;
+  }
+
+  let low = hist.min();
+  let width = (hist.value_at_quantile(0.99) - low) / BUCKETS + 1;
+  let mut counts = [0; BUCKETS as usize];
+  for value in hist.iter_recorded() {
+    let bucket = ((value.value_iterated_to() - low) / width).min(BUCKETS - 1);
+    counts[bucket as usize] += value.count_at_value();
+  }
+  let highest = counts.iter().copied().max().unwrap_or_default().max(1);
+
+  let format = |micros: u64| time_unit.format(micros as f64 / 1_000.0);
+  println!("{prefix}{}", "Latency distribution".yellow());
+  for (bucket, count) in counts.iter().enumerate() {
+    let from = low + bucket as u64 * width;
+    let range = if bucket as u64 == BUCKETS - 1 {
+      format!("{}+", format(from))
+    } else {
+      format!("{} - {}", format(from), format(from + width))
+    };
+    let bar = "#".repeat((count * BAR_WIDTH).div_ceil(highest) as usize);
+    println!(
+      "{prefix}{:width2$} {:bar_width$} {}",
+      range.yellow(),
+      bar.purple(),
+      count.to_string().purple(),
+      width2 = 25,
+      bar_width = BAR_WIDTH as usize
+    );
+  }
+}
+
+/// Prints the totals of the steps of every included file
+fn show_sources(run_stats: &RunStats, time_unit: TimeUnit) {
+  for (source, stats) in run_stats.sources.iter() {
+    let lines = [
+      ("Total requests", stats.total_requests.to_string()),
+      ("Successful requests", stats.successful_requests.to_string()),
+      ("Failed requests", stats.failed_requests.to_string()),
+      ("Median time per request", time_unit.format(stats.median_duration())),
+      ("Average time per request", time_unit.format(stats.mean_duration())),
+      (
+        "99.0'th percentile",
+        time_unit.format(stats.value_at_quantile(0.99)),
+      ),
+    ];
+    println!();
+    println!("{} {}", "Included file".yellow(), source.green());
+    for (label, value) in lines {
+      println!(
+        "{:width$} {:width2$} {}",
+        source.green(),
+        label.yellow(),
+        value.purple(),
+        width = 25,
+        width2 = 25
+      );
+    }
+  }
+}
+
+fn show_metrics(run_stats: &RunStats, duration: f64) {
+  for (name, stats) in run_stats.metrics.iter() {
+    let lines = match stats.kind {
+      MetricKind::Counter => vec![
+        ("Total", format_metric(stats.sum)),
+        ("Rate", format!("{:.2} [#/sec]", stats.sum / duration)),
+      ],
+      MetricKind::Gauge => vec![
+        ("Value", format_metric(stats.last)),
+        ("Min", format_metric(stats.min)),
+        ("Max", format_metric(stats.max)),
+      ],
+      MetricKind::Trend => vec![
+        ("Samples", stats.count.to_string()),
+        ("Average", format_metric(stats.mean())),
+        ("Median", format_metric(stats.value_at_quantile(0.5))),
+        ("Min", format_metric(stats.min)),
+        ("Max", format_metric(stats.max)),
+        ("99.0'th percentile", format_metric(stats.value_at_quantile(0.99))),
+      ],
+    };
+
+    println!();
+    for (label, value) in lines {
+      println!(
+        "{:width$} {:width2$} {}",
+        name.green(),
+        label.yellow(),
+        value.purple(),
+        width = 25,
+        width2 = 25
+      );
+    }
+  }
+}
+
+/// Whole values are printed as such, others with two decimals
+fn format_metric(value: f64) -> String {
+  if value.fract() == 0.0 {
+    value.to_string()
+  } else {
+    format!("{value:.2}")
+  }
 }
 
 fn compare_benchmark(
-  list_reports: &[Vec<Report>],
+  run_stats: &RunStats,
   compare_path_option: Option<&str>,
   threshold_option: Option<&str>,
-) {
+) -> bool {
   if let Some(compare_path) = compare_path_option {
     if let Some(threshold) = threshold_option {
-      let compare_result =
-        checker::compare(list_reports, compare_path, threshold);
-
-      match compare_result {
-        Ok(_) => process::exit(0),
-        Err(_) => process::exit(1),
-      }
+      checker::compare(run_stats, compare_path, threshold).is_ok()
     } else {
       panic!("Threshold needed!");
     }
+  } else {
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn report(name: &str, status: u16, superseded: bool) -> Report {
+    let mut report = Report::new(name.to_owned(), 10.0, status, status < 400);
+    report.retried = true;
+    report.superseded = superseded;
+    report
+  }
+
+  #[test]
+  fn counts_the_last_attempt_of_recovered_iterations() {
+    let mut stats = RunStats::default();
+    stats.record(&[report("Login", 401, true), report("Login", 200, false), report("Orders", 200, false)]);
+
+    assert_eq!(stats.iterations, 1);
+    assert_eq!((stats.global.total_requests, stats.global.failed_requests), (2, 0));
+    assert_eq!(stats.steps["Login"].total_requests, 1);
+    assert_eq!(stats.steps["Login"].successful_requests, 1);
+  }
+
+  #[test]
+  fn counts_the_last_attempt_of_failed_iterations() {
+    let mut stats = RunStats::default();
+    stats.record(&[report("Login", 401, true), report("Login", 401, true), report("Login", 401, false)]);
+
+    assert_eq!(stats.iterations, 1);
+    assert_eq!((stats.global.total_requests, stats.global.failed_requests), (1, 1));
+    assert_eq!(stats.steps["Login"].failed_requests, 1);
   }
 }
@@ -0,0 +1,60 @@
+/// How much is printed while running, raised by every `-q`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Quietness {
+  #[default]
+  Normal,
+  /// `-q`, without a line per request
+  Requests,
+  /// `-qq`, without a line per step either
+  Steps,
+  /// `-qqq`, with only the final summary and the errors of the iterations
+  All,
+}
+
+impl From<u8> for Quietness {
+  fn from(count: u8) -> Self {
+    match count {
+      0 => Quietness::Normal,
+      1 => Quietness::Requests,
+      2 => Quietness::Steps,
+      _ => Quietness::All,
+    }
+  }
+}
+
+/// Tells which lines are printed while running, from `-q` and
+/// `--only-errors`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Output {
+  quietness: Quietness,
+  only_errors: bool,
+}
+
+impl Output {
+  pub fn new(quietness: Quietness, only_errors: bool) -> Self {
+    Output {
+      quietness,
+      only_errors,
+    }
+  }
+
+  /// Whether every request gets a line
+  pub fn requests(&self) -> bool {
+    self.quietness < Quietness::Requests && !self.only_errors
+  }
+
+  /// Whether the steps other than requests get a line
+  pub fn steps(&self) -> bool {
+    self.quietness < Quietness::Steps
+  }
+
+  /// Whether the failed requests and the warnings get a line
+  pub fn errors(&self) -> bool {
+    self.quietness < Quietness::All
+  }
+
+  /// Whether the failed requests are printed with their headers and body
+  pub fn failure_details(&self) -> bool {
+    self.only_errors && self.errors()
+  }
+}
@@ -1,14 +1,28 @@
 use std::{
+  cell::{Cell, RefCell},
   collections::{BTreeMap, HashMap},
-  env::{current_dir, set_current_dir},
+  convert::TryFrom,
+  env::current_dir,
+  fmt,
+  ffi::OsStr,
   fs::File,
   io::Read,
-  path::PathBuf,
+  net::IpAddr,
+  ops::RangeInclusive,
+  path::{Path, PathBuf},
   str::FromStr,
+  time::Duration,
 };
 
 use path_absolutize::Absolutize;
-use serde::{Deserialize, Deserializer};
+use regex::Regex;
+use serde::de::{
+  self, DeserializeSeed, EnumAccess, Error as _, IgnoredAny, IntoDeserializer,
+  MapAccess, VariantAccess, Visitor,
+};
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use url::Url;
 
 use crate::{
   db::YamlDbDefinition,
@@ -16,47 +30,512 @@ use crate::{
 };
 
 const NITERATIONS: u64 = 1;
-const NRAMPUP: u64 = 0;
 
 fn default_iterations() -> u64 {
   NITERATIONS
 }
 
-fn default_rampup() -> u64 {
-  NRAMPUP
+fn default_increment() -> i64 {
+  1
+}
+
+fn default_metric_value() -> serde_json::Value {
+  serde_json::Value::from(1)
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BenchmarkDoc {
+  /// Name the results are labelled with
+  #[serde(default = "Default::default")]
+  pub name: Option<String>,
+  #[serde(default = "Default::default")]
+  pub description: Option<String>,
+  /// Version of the benchmark, like `1.2`, for results to tell plans apart
+  #[serde(default = "Default::default", deserialize_with = "optional_scalar")]
+  pub version: Option<String>,
   #[serde(default = "default_iterations")]
   pub iterations: u64,
-  #[serde(default = "default_rampup")]
-  pub rampup: u64,
-  #[serde(default = "Default::default", deserialize_with = "get_env")]
+  #[serde(
+    default = "Default::default",
+    deserialize_with = "duration",
+    serialize_with = "serialize_duration"
+  )]
+  pub rampup: Duration,
+  #[serde(
+    default = "Default::default",
+    deserialize_with = "get_env",
+    serialize_with = "redacted_values"
+  )]
   pub env: BTreeMap<String, String>,
   #[serde(default = "num_cpus::get")]
   pub concurrency: usize,
-  #[serde(deserialize_with = "get_databases", flatten)]
+  #[serde(default = "Default::default")]
+  pub seed: Option<u64>,
+  #[serde(default = "Default::default")]
   pub databases: BTreeMap<String, YamlDbDefinition>,
+  /// Url the relative urls of requests without `base` are joined to
+  #[serde(default = "Default::default", alias = "base")]
+  pub base_url: Option<String>,
+  #[serde(default = "Default::default")]
+  pub url_join: UrlJoin,
+  #[serde(default = "Default::default")]
+  pub urls: BTreeMap<String, BaseUrl>,
+  /// Headers sent with every request, before the ones of the request
+  #[serde(default = "Default::default")]
+  pub default_headers: BTreeMap<String, String>,
+  /// Address the connections are opened from, unless their `urls` entry
+  /// sets one
+  #[serde(default = "Default::default")]
+  pub local_address: Option<IpAddr>,
+  /// Connections opened to every url before the benchmark starts
+  #[serde(default = "Default::default")]
+  pub prewarm_connections: usize,
+  #[serde(default = "Default::default")]
+  pub success_status: SuccessStatus,
+  /// Statuses of the failures provoked on purpose, like `[429]`, counted
+  /// apart from the failed requests
+  #[serde(default = "Default::default")]
+  pub expected_failures: Option<SuccessStatus>,
+  /// Header carrying a random id generated for every request, like
+  /// `X-Request-Id`
+  #[serde(default = "Default::default")]
+  pub request_id: Option<String>,
+  /// Sends W3C trace context headers, with a trace per iteration and a span
+  /// per request
   #[serde(default = "Default::default")]
-  pub urls: BTreeMap<String, String>,
+  pub propagate_trace: bool,
+  /// Revalidates the responses with an `ETag` or `Last-Modified` already
+  /// received in the context, like a browser cache
+  #[serde(default = "Default::default")]
+  pub conditional_requests: bool,
+  /// Conditions on the custom metrics the run fails without
+  #[serde(default = "Default::default")]
+  pub thresholds: Vec<Threshold>,
   #[serde(default = "Default::default")]
   pub global: BTreeMap<String, String>,
+  /// Initial values of the store shared by all iterations
+  #[serde(default = "Default::default")]
+  pub shared: BTreeMap<String, serde_json::Value>,
+  #[serde(default = "Default::default")]
+  pub secrets: BTreeMap<String, String>,
+  /// Values masked in the logs and reports, on top of the secrets
+  #[serde(default = "Default::default")]
+  pub redact: Vec<Redaction>,
+  #[serde(default = "Default::default", deserialize_with = "absolute_paths")]
+  pub plugins: BTreeMap<String, String>,
+  #[serde(default = "Default::default")]
+  pub templates: BTreeMap<String, Vec<PlanItem>>,
+  /// Runs every virtual user iterations one after the other in the same
+  /// context, instead of giving every iteration a fresh one
+  #[serde(default = "Default::default")]
+  pub virtual_users: bool,
+  #[serde(default = "Default::default")]
+  pub before_vu: Vec<PlanItem>,
+  /// Interval between the starts of the iterations of a user
+  #[serde(
+    default = "Default::default",
+    deserialize_with = "optional_duration",
+    serialize_with = "serialize_optional_duration",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub pacing: Option<Duration>,
+  /// Times an iteration failing on an error is run again from the top, in a
+  /// fresh context
+  #[serde(default = "Default::default")]
+  pub iteration_retries: usize,
+  #[serde(default = "Default::default")]
+  pub before_each: Vec<PlanItem>,
   #[serde(default = "Default::default")]
   pub plan: Vec<PlanItem>,
+  #[serde(default = "Default::default")]
+  pub after_each: Vec<PlanItem>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Clone)]
 pub struct PlanItem {
-  #[serde(skip_serializing_if = "Option::is_none")]
   pub name: Option<String>,
-  #[serde(skip_serializing_if = "Option::is_none")]
   pub assign: Option<String>,
-  #[serde(flatten)]
+  /// Condition the item only runs under, see `Interpolator::evaluate`
+  pub when: Option<String>,
+  pub on_error: Option<OnError>,
+  /// Iterations running the item at once at most
+  pub concurrency: Option<usize>,
+  /// Times the item runs at most over the whole run
+  pub iterations: Option<usize>,
+  pub tags: Vec<String>,
   pub action: Action,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Items run when a plan item fails, and what to do afterwards
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OnError {
+  pub steps: Vec<PlanItem>,
+  #[serde(default)]
+  pub then: Recovery,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Recovery {
+  /// Stops the iteration
+  #[default]
+  Abort,
+  /// Goes on with the next item
+  Continue,
+  /// Runs the failed item once more, going on only if it succeeds
+  Retry,
+}
+
+const PLAN_ITEM_FIELDS: &[&str] = &[
+  "name",
+  "assign",
+  "tags",
+  "when",
+  "on_error",
+  "concurrency",
+  "iterations",
+  "assert",
+  "counter_inc",
+  "custom",
+  "db-query",
+  "debug",
+  "delay",
+  "exec",
+  "metric",
+  "plugin",
+  "request",
+  "script",
+  "shared_set",
+  "transaction",
+  "include",
+  "use",
+  "args",
+];
+
+/// Fields of `PLAN_ITEM_FIELDS` naming the action of the item
+const ACTION_FIELDS: &[&str] = &[
+  "assert",
+  "assign",
+  "counter_inc",
+  "custom",
+  "db-query",
+  "debug",
+  "delay",
+  "exec",
+  "metric",
+  "plugin",
+  "request",
+  "script",
+  "shared_set",
+  "transaction",
+  "include",
+  "use",
+];
+
+// Plan items are deserialized by hand rather than flattening the action into
+// the item, so errors keep pointing at the exact YAML path and line
+impl<'de> Deserialize<'de> for PlanItem {
+  fn deserialize<D>(de: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    de.deserialize_map(PlanItemVisitor)
+  }
+}
+
+struct PlanItemVisitor;
+
+impl<'de> Visitor<'de> for PlanItemVisitor {
+  type Value = PlanItem;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a plan item with a name and an action")
+  }
+
+  fn visit_map<A>(self, mut map: A) -> Result<PlanItem, A::Error>
+  where
+    A: MapAccess<'de>,
+  {
+    let mut name = None;
+    let mut assign = None;
+    let mut when = None;
+    let mut on_error = None;
+    let mut concurrency = None;
+    let mut iterations = None;
+    let mut args = None;
+    let mut tags = Vec::new();
+    let mut action = None;
+
+    while let Some(key) = map.next_key::<String>()? {
+      let parsed = match key.as_str() {
+        "name" => {
+          name = Some(map.next_value()?);
+          None
+        }
+        "tags" => {
+          tags = map.next_value()?;
+          None
+        }
+        "when" => {
+          when = Some(map.next_value()?);
+          None
+        }
+        "on_error" => {
+          on_error = Some(map.next_value()?);
+          None
+        }
+        "concurrency" => match map.next_value()? {
+          0 => return Err(de::Error::custom("`concurrency` can't be 0")),
+          slots => {
+            concurrency = Some(slots);
+            None
+          }
+        },
+        "iterations" => {
+          iterations = Some(map.next_value()?);
+          None
+        }
+        "use" => Some(Action::Use {
+          template: map.next_value()?,
+          args: serde_json::Map::new(),
+        }),
+        "args" => {
+          args = Some(map.next_value()?);
+          None
+        }
+        // `assign` either names the context key an action result is stored
+        // in, or is an action on its own
+        "assign" => match map.next_value()? {
+          AssignField::Key(key) => {
+            assign = Some(key);
+            None
+          }
+          AssignField::Action {
+            key,
+            value,
+          } => Some(Action::Assign {
+            key,
+            value,
+          }),
+        },
+        variant if ACTION_FIELDS.contains(&variant) => {
+          Some(map.next_value_seed(ActionSeed(variant))?)
+        }
+        unknown => {
+          return Err(de::Error::unknown_field(unknown, PLAN_ITEM_FIELDS))
+        }
+      };
+
+      if let Some(parsed) = parsed {
+        if action.is_some() {
+          return Err(de::Error::custom(format!(
+            "unexpected action `{key}`, plan items take a single action"
+          )));
+        }
+        action = Some(parsed);
+      }
+    }
+
+    let mut action = action.ok_or_else(|| {
+      let expected: Vec<_> =
+        ACTION_FIELDS.iter().map(|field| format!("`{field}`")).collect();
+      let (last, others) = expected.split_last().unwrap();
+      de::Error::custom(format!(
+        "missing action, expected one of {} or {}",
+        others.join(", "),
+        last
+      ))
+    })?;
+
+    // Template arguments sit next to `use` rather than inside it
+    if let Some(args) = args {
+      match &mut action {
+        Action::Use {
+          args: use_args,
+          ..
+        } => *use_args = args,
+        _ => return Err(de::Error::custom("`args` only apply to `use`")),
+      }
+    }
+
+    Ok(PlanItem {
+      name,
+      assign,
+      when,
+      on_error,
+      concurrency,
+      iterations,
+      tags,
+      action,
+    })
+  }
+}
+
+// Written back in the shape it was read, with the action as one more key of
+// the item
+impl Serialize for PlanItem {
+  fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    let mut map = ser.serialize_map(None)?;
+    if let Some(name) = &self.name {
+      map.serialize_entry("name", name)?;
+    }
+    if let Some(assign) = &self.assign {
+      map.serialize_entry("assign", assign)?;
+    }
+    if !self.tags.is_empty() {
+      map.serialize_entry("tags", &self.tags)?;
+    }
+    if let Some(when) = &self.when {
+      map.serialize_entry("when", when)?;
+    }
+    if let Some(on_error) = &self.on_error {
+      map.serialize_entry("on_error", on_error)?;
+    }
+    if let Some(concurrency) = &self.concurrency {
+      map.serialize_entry("concurrency", concurrency)?;
+    }
+    if let Some(iterations) = &self.iterations {
+      map.serialize_entry("iterations", iterations)?;
+    }
+
+    match &self.action {
+      Action::Use {
+        template,
+        args,
+      } => {
+        map.serialize_entry("use", template)?;
+        if !args.is_empty() {
+          map.serialize_entry("args", args)?;
+        }
+      }
+      action => {
+        let action = serde_json::to_value(action).map_err(S::Error::custom)?;
+        if let serde_json::Value::Object(action) = action {
+          for (key, value) in action {
+            map.serialize_entry(&key, &value)?;
+          }
+        }
+      }
+    }
+    map.end()
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AssignField {
+  Key(String),
+  Action {
+    key: String,
+    value: serde_json::Value,
+  },
+}
+
+/// Deserializes the value of a plan item key as the `Action` variant named
+/// by that key.
+struct ActionSeed<'a>(&'a str);
+
+impl<'de, 'a> DeserializeSeed<'de> for ActionSeed<'a> {
+  type Value = Action;
+
+  fn deserialize<D>(self, de: D) -> Result<Action, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    Action::deserialize(VariantDeserializer {
+      variant: self.0,
+      de,
+    })
+  }
+}
+
+struct VariantDeserializer<'a, D> {
+  variant: &'a str,
+  de: D,
+}
+
+impl<'de, 'a, D> Deserializer<'de> for VariantDeserializer<'a, D>
+where
+  D: Deserializer<'de>,
+{
+  type Error = D::Error;
+
+  fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, D::Error>
+  where
+    V: Visitor<'de>,
+  {
+    visitor.visit_enum(self)
+  }
+
+  serde::forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf option unit unit_struct newtype_struct seq tuple
+    tuple_struct map struct enum identifier ignored_any
+  }
+}
+
+impl<'de, 'a, D> EnumAccess<'de> for VariantDeserializer<'a, D>
+where
+  D: Deserializer<'de>,
+{
+  type Error = D::Error;
+  type Variant = Self;
+
+  fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), D::Error>
+  where
+    V: DeserializeSeed<'de>,
+  {
+    let variant = seed.deserialize(self.variant.into_deserializer())?;
+    Ok((variant, self))
+  }
+}
+
+impl<'de, 'a, D> VariantAccess<'de> for VariantDeserializer<'a, D>
+where
+  D: Deserializer<'de>,
+{
+  type Error = D::Error;
+
+  fn unit_variant(self) -> Result<(), D::Error> {
+    IgnoredAny::deserialize(self.de).map(|_| ())
+  }
+
+  fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, D::Error>
+  where
+    T: DeserializeSeed<'de>,
+  {
+    seed.deserialize(self.de)
+  }
+
+  fn tuple_variant<V>(
+    self,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, D::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.de.deserialize_tuple(len, visitor)
+  }
+
+  fn struct_variant<V>(
+    self,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, D::Error>
+  where
+    V: Visitor<'de>,
+  {
+    self.de.deserialize_struct("", fields, visitor)
+  }
+}
+
+// Only built once, while parsing, so requests aren't worth boxing
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub enum Action {
   Assert {
@@ -67,137 +546,252 @@ pub enum Action {
     key: String,
     value: serde_json::Value,
   },
+  /// Adds `by` to a counter of the shared store
+  #[serde(rename = "counter_inc")]
+  CounterInc {
+    key: String,
+    #[serde(default = "default_increment")]
+    by: i64,
+  },
+  Custom {
+    command: String,
+    #[serde(default)]
+    args: serde_json::Value,
+  },
   DbQuery {
     target: String,
     query: String,
     #[serde(default = "Default::default", deserialize_with = "with_items")]
     with_items: Option<WithItems>,
+    /// Rows kept at most, the rest of the results are dropped
+    max_rows: Option<usize>,
   },
-  Delay {
-    seconds: u64,
+  /// Prints the given context keys, or the whole context when empty
+  Debug {
+    #[serde(default)]
+    keys: Vec<String>,
   },
+  Delay(DelaySpec),
   Exec {
     command: String,
+    /// Time the command may run before being killed
+    #[serde(
+      default,
+      deserialize_with = "optional_duration",
+      serialize_with = "serialize_optional_duration",
+      skip_serializing_if = "Option::is_none"
+    )]
+    timeout: Option<Duration>,
+    /// Starts the command without waiting for it to finish
+    #[serde(default, rename = "async")]
+    detach: bool,
+    /// Fails the iteration when the command exits with a non-zero code
+    #[serde(default)]
+    fail_on_nonzero: bool,
+    #[serde(default)]
+    shell: Shell,
+  },
+  /// Records a sample of a custom metric, aggregated in the summary
+  Metric {
+    name: String,
+    #[serde(rename = "type")]
+    kind: MetricKind,
+    /// Number, or string interpolated into one
+    #[serde(default = "default_metric_value")]
+    value: serde_json::Value,
+  },
+  Plugin {
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
   },
   Request {
     #[serde(skip_serializing_if = "Option::is_none")]
     base: Option<String>,
     url: String,
+    /// Values of the `:name` segments of the url, percent-encoded
+    #[serde(default, serialize_with = "sorted")]
+    path_params: HashMap<String, String>,
+    /// Hostname presented to the IP address of the url, for TLS and routing
+    #[serde(default)]
+    sni: Option<String>,
     #[serde(default = "Default::default")]
     time: f64,
     #[serde(default = "default_method")]
     method: String,
-    #[serde(default = "Default::default")]
+    #[serde(default = "Default::default", serialize_with = "sorted")]
     headers: HashMap<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     body: Option<String>,
+    /// File streamed as the body, instead of `body`
+    #[serde(default, deserialize_with = "absolute_path")]
+    body_file: Option<String>,
+    /// Drops the response body unread, by default unless assigned
+    #[serde(default)]
+    discard_body: Option<bool>,
+    /// Stops reading the response body past this size
+    #[serde(default)]
+    max_body_bytes: Option<usize>,
+    /// Aborts the request past this response size, failing it
+    #[serde(default)]
+    max_response_bytes: Option<usize>,
+    /// Overrides the benchmark `success_status`
+    #[serde(default)]
+    success_status: Option<SuccessStatus>,
+    /// Overrides the benchmark `expected_failures`
+    #[serde(default)]
+    expected_failures: Option<SuccessStatus>,
+    /// Media type the response must have to succeed, like `application/*`
+    #[serde(default)]
+    expect_content_type: Option<String>,
+    /// Follows the redirects by hand to record every hop
+    #[serde(default)]
+    capture_redirects: Option<RedirectCapture>,
+    /// Streams the response body into a file
+    #[serde(default)]
+    download: Option<Download>,
+    /// Parts of the response kept in the value assigned, without buffering
+    /// the body
+    #[serde(default)]
+    assign_only: Option<AssignOnly>,
     #[serde(default = "Default::default", deserialize_with = "with_items")]
     with_items: Option<WithItems>,
   },
+  Script {
+    code: String,
+  },
+  /// Sets a value of the shared store
+  #[serde(rename = "shared_set")]
+  SharedSet {
+    key: String,
+    value: serde_json::Value,
+  },
+  /// Items reported as a whole besides their own reports
+  Transaction(Vec<PlanItem>),
+  /// Included files, several when the path is a glob pattern
   #[serde(deserialize_with = "include_doc_deser")]
-  Include(IncludeDoc),
+  Include(Vec<IncludeDoc>),
+  /// Instance of a template, deserialized along with its sibling `args`
+  #[serde(skip)]
+  Use {
+    template: String,
+    args: serde_json::Map<String, serde_json::Value>,
+  },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct WithItems {
   pub shuffle: bool,
   pub pick: Pick,
+  pub strategy: Option<Strategy>,
+  pub resample: Resample,
   pub items: Vec<serde_yaml::Value>,
 }
 
+/// Raw "with_items" block. Exactly one data source must be given: a `path`
+/// to a CSV/YAML file, a `start`/`stop` range or inline `items`.
 #[derive(Debug, Clone, Deserialize)]
-#[serde(untagged)]
-pub enum WithItemsType {
-  File {
-    path: String,
-    #[serde(default = "Default::default")]
-    shuffle: bool,
-    #[serde(default = "Default::default")]
-    pick: Pick,
-  },
-  Range {
-    start: usize,
-    stop: usize,
-    step: usize,
-    #[serde(default = "Default::default")]
-    shuffle: bool,
-    #[serde(default = "Default::default")]
-    pick: Pick,
-  },
-  Direct {
-    items: Vec<BTreeMap<String, serde_yaml::Value>>,
-    #[serde(default = "Default::default")]
-    shuffle: bool,
-    #[serde(default = "Default::default")]
-    pick: Pick,
-  },
+#[serde(deny_unknown_fields)]
+struct WithItemsDoc {
+  path: Option<String>,
+  start: Option<usize>,
+  stop: Option<usize>,
+  #[serde(default = "default_step")]
+  step: usize,
+  items: Option<Vec<BTreeMap<String, serde_yaml::Value>>>,
+  #[serde(default = "Default::default")]
+  shuffle: bool,
+  #[serde(default = "Default::default")]
+  pick: Pick,
+  #[serde(default = "Default::default")]
+  strategy: Option<Strategy>,
+  #[serde(default = "Default::default")]
+  resample: Resample,
+}
+
+fn default_step() -> usize {
+  1
 }
 
 fn with_items<'de, D>(de: D) -> Result<Option<WithItems>, D::Error>
 where
   D: Deserializer<'de>,
 {
-  let items: WithItemsType =
-    serde_yaml::from_value(Deserialize::deserialize(de)?).unwrap();
-  match items {
-    WithItemsType::File {
-      path,
-      shuffle,
-      pick,
+  let doc = WithItemsDoc::deserialize(de)?;
+  let items: Vec<serde_yaml::Value> = match doc {
+    WithItemsDoc {
+      path: Some(path),
+      start: None,
+      stop: None,
+      items: None,
+      ..
     } => {
-      let path = PathBuf::from_str(&path).unwrap();
-      let items = match serde_yaml::from_str::<FileType>(
-        path.extension().unwrap().to_str().unwrap(),
-      )
-      .unwrap()
-      {
-        FileType::Csv => read_csv_file_as_yml(&path),
-        FileType::Yaml | FileType::Yml => read_file_as_yml_array(&path),
-      };
-      pick.validate(&items);
-      Ok(Some(WithItems {
-        items,
-        pick,
-        shuffle,
-      }))
-    }
-    WithItemsType::Range {
-      start,
-      stop,
+      let path = resolve_path(&path);
+      let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+      match serde_yaml::from_str::<FileType>(&extension) {
+        Ok(FileType::Csv) => read_csv_file_as_yml(&path),
+        Ok(FileType::Yaml | FileType::Yml) => read_file_as_yml_array(&path),
+        Err(_) => {
+          return Err(D::Error::custom(format!(
+            "unsupported file type '{}', expected csv, yml or yaml",
+            path.display()
+          )))
+        }
+      }
+    }
+    WithItemsDoc {
+      path: None,
+      start: Some(start),
+      stop: Some(stop),
+      items: None,
       step,
-      shuffle,
-      pick,
+      ..
     } => {
-      let items: Vec<serde_yaml::Value> = (start..stop)
+      if step == 0 {
+        return Err(D::Error::custom("step should be greater than 0"));
+      }
+      (start..stop)
         .step_by(step)
         .map(|n| serde_yaml::Value::Number(serde_yaml::Number::from(n)))
-        .collect();
-      pick.validate(&items);
-      Ok(Some(WithItems {
-        items,
-        pick,
-        shuffle,
-      }))
-    }
-    WithItemsType::Direct {
-      items,
-      shuffle,
-      pick,
-    } => {
-      let items: Vec<serde_yaml::Value> =
-        serde_json::from_str(&serde_json::to_string(&items).unwrap()).unwrap();
-      pick.validate(&items);
-      Ok(Some(WithItems {
-        items,
-        pick,
-        shuffle,
-      }))
+        .collect()
     }
-  }
+    WithItemsDoc {
+      path: None,
+      start: None,
+      stop: None,
+      items: Some(items),
+      ..
+    } => items
+      .into_iter()
+      .map(|item| {
+        serde_yaml::Value::Mapping(
+          item.into_iter().map(|(k, v)| (k.into(), v)).collect(),
+        )
+      })
+      .collect(),
+    _ => {
+      return Err(D::Error::custom(
+        "expected exactly one of `path`, `start`/`stop` or `items`",
+      ))
+    }
+  };
+
+  doc.pick.validate(&items).map_err(D::Error::custom)?;
+  Ok(Some(WithItems {
+    items,
+    pick: doc.pick,
+    shuffle: doc.shuffle,
+    strategy: doc.strategy,
+    resample: doc.resample,
+  }))
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(rename = "lowercase")]
+#[serde(rename_all = "lowercase")]
 enum FileType {
   Csv,
   Yml,
@@ -205,68 +799,985 @@ enum FileType {
 }
 
 /// Parses "pick" option, which tells the app how many rows of data
-/// it should take from the data source.
-#[derive(Default, Debug, Clone, Copy, Deserialize)]
-pub struct Pick(i64);
+/// it should take from the data source: a number of items, or a percentage
+/// of them, like `10%`, rounded up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pick {
+  Count(usize),
+  Percent(f64),
+}
+
+impl Default for Pick {
+  fn default() -> Self {
+    Pick::Count(0)
+  }
+}
 
 impl Pick {
-  pub fn validate(&self, with_items: &[serde_yaml::Value]) {
-    if self.0.is_negative() {
-      panic!("pick option should not be negative, but was {}", self.0);
-    } else if self.0 as usize > with_items.len() {
-      panic!(
+  pub fn validate(
+    &self,
+    with_items: &[serde_yaml::Value],
+  ) -> Result<(), String> {
+    match *self {
+      Pick::Count(count) if count > with_items.len() => Err(format!(
         "pick option should not be greater than the provided items, but was {}",
-        self.0
-      );
+        count
+      )),
+      _ => Ok(()),
     }
   }
 
-  pub fn inner(&self) -> usize {
-    self.0 as usize
+  /// Number of items picked out of `len`
+  pub fn count(&self, len: usize) -> usize {
+    match *self {
+      Pick::Count(count) => count,
+      Pick::Percent(percent) => (len as f64 * percent / 100.0).ceil() as usize,
+    }
   }
 }
 
-#[derive(Debug, Clone)]
+impl<'de> Deserialize<'de> for Pick {
+  fn deserialize<D>(de: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawPick {
+      Count(i64),
+      Text(String),
+    }
+
+    match RawPick::deserialize(de)? {
+      RawPick::Count(count) if count.is_negative() => Err(D::Error::custom(
+        format!("pick option should not be negative, but was {count}"),
+      )),
+      RawPick::Count(count) => Ok(Pick::Count(count as usize)),
+      RawPick::Text(text) => text
+        .trim()
+        .strip_suffix('%')
+        .and_then(|percent| percent.trim().parse::<f64>().ok())
+        .filter(|percent| *percent > 0.0 && *percent <= 100.0)
+        .map(Pick::Percent)
+        .ok_or_else(|| {
+          D::Error::custom(format!(
+            "invalid pick '{text}', expected a number of items or a \
+             percentage between 0% and 100%"
+          ))
+        }),
+    }
+  }
+}
+
+impl Serialize for Pick {
+  fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      Pick::Count(count) => ser.serialize_u64(*count as u64),
+      Pick::Percent(percent) => ser.serialize_str(&format!("{percent}%")),
+    }
+  }
+}
+
+/// Parses "resample" option, which tells the app when the items are drawn
+/// out of the data source
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resample {
+  /// Draws them once per iteration, the request running again within it,
+  /// like when retried, takes the same items
+  PerIteration,
+  /// Draws them on every execution of the request
+  #[default]
+  PerRequest,
+}
+
+/// Parses "strategy" option, which tells the app how the data source is
+/// consumed across iterations. When missing, every execution goes through
+/// all the items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+  /// Takes the next items on every execution, wrapping around at the end
+  Circular,
+  /// Takes the next items on every execution, stopping the run at the end
+  Once,
+  /// Samples items with replacement on every execution
+  Random,
+}
+
+/// Parses "delay" items: a fixed duration in `seconds`, or a random number of
+/// milliseconds, uniform between `min` and `max` or normal around `mean`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "DelayDoc")]
+pub enum DelaySpec {
+  Fixed(Duration),
+  Uniform { min: Duration, max: Duration },
+  Normal { mean: Duration, stddev: Duration },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DelayDoc {
+  #[serde(default, deserialize_with = "optional_duration")]
+  seconds: Option<Duration>,
+  min: Option<u64>,
+  max: Option<u64>,
+  mean: Option<u64>,
+  stddev: Option<u64>,
+}
+
+impl TryFrom<DelayDoc> for DelaySpec {
+  type Error = String;
+
+  fn try_from(doc: DelayDoc) -> Result<Self, Self::Error> {
+    match doc {
+      DelayDoc {
+        seconds: Some(seconds),
+        min: None,
+        max: None,
+        mean: None,
+        stddev: None,
+      } => Ok(DelaySpec::Fixed(seconds)),
+      DelayDoc {
+        seconds: None,
+        min: Some(min),
+        max: Some(max),
+        mean: None,
+        stddev: None,
+      } if min <= max => Ok(DelaySpec::Uniform {
+        min: Duration::from_millis(min),
+        max: Duration::from_millis(max),
+      }),
+      DelayDoc {
+        seconds: None,
+        min: None,
+        max: None,
+        mean: Some(mean),
+        stddev,
+      } => Ok(DelaySpec::Normal {
+        mean: Duration::from_millis(mean),
+        stddev: Duration::from_millis(stddev.unwrap_or_default()),
+      }),
+      _ => Err(
+        "expected either seconds, min and max (min <= max) or mean and stddev"
+          .to_owned(),
+      ),
+    }
+  }
+}
+
+impl Serialize for DelaySpec {
+  fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    let millis = |duration: &Duration| duration.as_millis() as u64;
+    let mut map = ser.serialize_map(None)?;
+    match self {
+      DelaySpec::Fixed(duration) => {
+        map.serialize_entry("seconds", &format_duration(*duration))?;
+      }
+      DelaySpec::Uniform {
+        min,
+        max,
+      } => {
+        map.serialize_entry("min", &millis(min))?;
+        map.serialize_entry("max", &millis(max))?;
+      }
+      DelaySpec::Normal {
+        mean,
+        stddev,
+      } => {
+        map.serialize_entry("mean", &millis(mean))?;
+        map.serialize_entry("stddev", &millis(stddev))?;
+      }
+    }
+    map.end()
+  }
+}
+
+impl fmt::Display for DelaySpec {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DelaySpec::Fixed(duration) => write!(f, "{}s", duration.as_secs_f64()),
+      DelaySpec::Uniform {
+        min,
+        max,
+      } => write!(f, "{}ms to {}ms", min.as_millis(), max.as_millis()),
+      DelaySpec::Normal {
+        mean,
+        stddev,
+      } => write!(f, "{}ms ± {}ms", mean.as_millis(), stddev.as_millis()),
+    }
+  }
+}
+
+/// Parses "download" option of requests
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Download {
+  /// File the body is written to, interpolated
+  pub path: String,
+  /// Expected SHA-256 of the body, in hex
+  #[serde(default)]
+  pub sha256: Option<String>,
+}
+
+/// Parses "assign_only" option of requests, the parts of the response kept
+/// in the assigned value
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssignOnly {
+  /// Paths of the JSON body, like `data.id`, parsed as it streams in
+  #[serde(default)]
+  pub body: Vec<String>,
+  /// Header names, in any case
+  #[serde(default)]
+  pub headers: Vec<String>,
+}
+
+/// Parses "capture_redirects" option, which tells where the redirects
+/// followed by a request are recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectCapture {
+  /// Under `redirects` in the assigned value
+  Assign,
+  /// In the report of the request too, written by `--report` and `--stream`
+  Report,
+}
+
+/// Parses "type" of metrics, which tells how their samples are aggregated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+  /// Sum of the samples, e.g. items returned
+  Counter,
+  /// Last sample, e.g. a queue size
+  Gauge,
+  /// Distribution of the samples, e.g. a cart total
+  Trend,
+}
+
+/// Describes the benchmark in its results, from its `name`, `description`
+/// and `version`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Metadata {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub version: Option<String>,
+}
+
+impl Metadata {
+  pub fn is_empty(&self) -> bool {
+    *self == Metadata::default()
+  }
+}
+
+impl From<&BenchmarkDoc> for Metadata {
+  fn from(doc: &BenchmarkDoc) -> Self {
+    Metadata {
+      name: doc.name.clone(),
+      description: doc.description.clone(),
+      version: doc.version.clone(),
+    }
+  }
+}
+
+/// Parses "redact" entries, telling which values are masked in the logs
+/// and reports
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RedactionDoc")]
+pub enum Redaction {
+  /// Values of a header, sent or received, by case-insensitive name
+  Header(String),
+  /// Value of a context key, like an assigned token
+  Key(String),
+  /// Matches of a regular expression
+  Pattern(Regex),
+}
+
+impl Serialize for Redaction {
+  fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    let mut map = ser.serialize_map(Some(1))?;
+    match self {
+      Redaction::Header(header) => map.serialize_entry("header", header)?,
+      Redaction::Key(key) => map.serialize_entry("key", key)?,
+      Redaction::Pattern(pattern) => {
+        map.serialize_entry("pattern", pattern.as_str())?
+      }
+    }
+    map.end()
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedactionDoc {
+  header: Option<String>,
+  key: Option<String>,
+  pattern: Option<String>,
+}
+
+impl TryFrom<RedactionDoc> for Redaction {
+  type Error = String;
+
+  fn try_from(doc: RedactionDoc) -> Result<Self, Self::Error> {
+    match doc {
+      RedactionDoc {
+        header: Some(header),
+        key: None,
+        pattern: None,
+      } => Ok(Redaction::Header(header)),
+      RedactionDoc {
+        header: None,
+        key: Some(key),
+        pattern: None,
+      } => Ok(Redaction::Key(key)),
+      RedactionDoc {
+        header: None,
+        key: None,
+        pattern: Some(pattern),
+      } => Regex::new(&pattern)
+        .map(Redaction::Pattern)
+        .map_err(|err| format!("invalid redact pattern '{pattern}': {err}")),
+      _ => Err("expected either a header, a key or a pattern".to_owned()),
+    }
+  }
+}
+
+/// Parses "thresholds" entries, like `search_results.p95 < 500` or
+/// `checkout_ok > 99%`, checked against the custom metrics after the run.
+/// Percentages are compared to the statistic times 100.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Threshold {
+  pub expression: String,
+  pub metric: String,
+  /// Defaults to the total of counters, the value of gauges and the average
+  /// of trends
+  pub stat: Option<MetricStat>,
+  pub operator: Comparison,
+  pub value: f64,
+  pub percent: bool,
+}
+
+impl TryFrom<String> for Threshold {
+  type Error = String;
+
+  fn try_from(expression: String) -> Result<Self, Self::Error> {
+    let invalid = || {
+      format!("invalid threshold '{expression}', expected like `metric > 99%`")
+    };
+    let split = expression.find(['<', '>', '=', '!']).ok_or_else(invalid)?;
+    let (lhs, rest) = expression.split_at(split);
+    let operator = ["<=", ">=", "==", "!=", "<", ">"]
+      .iter()
+      .find(|operator| rest.starts_with(**operator))
+      .ok_or_else(invalid)?;
+    let rhs = rest[operator.len()..].trim();
+    let (rhs, percent) = match rhs.strip_suffix('%') {
+      Some(rhs) => (rhs, true),
+      None => (rhs, false),
+    };
+
+    let (metric, stat) = match lhs.trim().split_once('.') {
+      Some((metric, stat)) => (metric, Some(stat.parse()?)),
+      None => (lhs.trim(), None),
+    };
+    if metric.is_empty() {
+      return Err(invalid());
+    }
+
+    Ok(Threshold {
+      metric: metric.to_owned(),
+      stat,
+      operator: operator.parse()?,
+      value: rhs.trim().parse().map_err(|_| invalid())?,
+      percent,
+      expression,
+    })
+  }
+}
+
+impl Serialize for Threshold {
+  fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    ser.serialize_str(&self.expression)
+  }
+}
+
+/// Statistic of a metric a threshold applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricStat {
+  Total,
+  Rate,
+  Value,
+  Count,
+  Avg,
+  Min,
+  Max,
+  Median,
+  P90,
+  P95,
+  P99,
+}
+
+impl FromStr for MetricStat {
+  type Err = String;
+
+  fn from_str(stat: &str) -> Result<Self, Self::Err> {
+    Ok(match stat {
+      "total" => MetricStat::Total,
+      "rate" => MetricStat::Rate,
+      "value" => MetricStat::Value,
+      "count" => MetricStat::Count,
+      "avg" => MetricStat::Avg,
+      "min" => MetricStat::Min,
+      "max" => MetricStat::Max,
+      "median" => MetricStat::Median,
+      "p90" => MetricStat::P90,
+      "p95" => MetricStat::P95,
+      "p99" => MetricStat::P99,
+      _ => {
+        return Err(format!(
+          "unknown statistic '{stat}', expected one of total, rate, value, \
+           count, avg, min, max, median, p90, p95 or p99"
+        ))
+      }
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Eq,
+  Ne,
+}
+
+impl Comparison {
+  pub fn holds(&self, lhs: f64, rhs: f64) -> bool {
+    match self {
+      Comparison::Lt => lhs < rhs,
+      Comparison::Le => lhs <= rhs,
+      Comparison::Gt => lhs > rhs,
+      Comparison::Ge => lhs >= rhs,
+      Comparison::Eq => lhs == rhs,
+      Comparison::Ne => lhs != rhs,
+    }
+  }
+}
+
+impl FromStr for Comparison {
+  type Err = String;
+
+  fn from_str(operator: &str) -> Result<Self, Self::Err> {
+    match operator {
+      "<" => Ok(Comparison::Lt),
+      "<=" => Ok(Comparison::Le),
+      ">" => Ok(Comparison::Gt),
+      ">=" => Ok(Comparison::Ge),
+      "==" => Ok(Comparison::Eq),
+      "!=" => Ok(Comparison::Ne),
+      _ => Err(format!("unknown operator '{operator}'")),
+    }
+  }
+}
+
+/// Parses "url_join" option, which tells how the request urls are resolved
+/// against their base url
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlJoin {
+  /// Appends the url to the base path, even when it starts with `/`
+  #[default]
+  Append,
+  /// Resolves the url like a link in a page: `/a` replaces the base path
+  /// and `a` replaces its last segment
+  Standard,
+}
+
+impl UrlJoin {
+  pub fn join(&self, base: &str, url: &str) -> Result<Url, url::ParseError> {
+    match self {
+      UrlJoin::Append => {
+        let mut base = Url::parse(base)?;
+        if !base.path().ends_with('/') {
+          base.set_path(&format!("{}/", base.path()));
+        }
+        base.join(url.trim_start_matches('/'))
+      }
+      UrlJoin::Standard => Url::parse(base)?.join(url),
+    }
+  }
+}
+
+/// Entry of the `urls` map, either the url alone or along with the headers
+/// sent to it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(from = "BaseUrlDefinition")]
+pub struct BaseUrl {
+  pub url: String,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub default_headers: BTreeMap<String, String>,
+  /// Address the connections to the url are opened from
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub local_address: Option<IpAddr>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BaseUrlDefinition {
+  Url(String),
+  WithHeaders {
+    url: String,
+    #[serde(default)]
+    default_headers: BTreeMap<String, String>,
+    #[serde(default)]
+    local_address: Option<IpAddr>,
+  },
+}
+
+impl From<BaseUrlDefinition> for BaseUrl {
+  fn from(definition: BaseUrlDefinition) -> Self {
+    match definition {
+      BaseUrlDefinition::Url(url) => BaseUrl {
+        url,
+        default_headers: BTreeMap::new(),
+        local_address: None,
+      },
+      BaseUrlDefinition::WithHeaders {
+        url,
+        default_headers,
+        local_address,
+      } => BaseUrl {
+        url,
+        default_headers,
+        local_address,
+      },
+    }
+  }
+}
+
+/// Parses "success_status" option, the statuses counted as successful in
+/// the stats, like `[2xx, 304, 400-404]`. Defaults to `2xx`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuccessStatus(Vec<RangeInclusive<u16>>);
+
+impl Default for SuccessStatus {
+  fn default() -> Self {
+    SuccessStatus(vec![200..=299])
+  }
+}
+
+impl SuccessStatus {
+  pub fn matches(&self, status: u16) -> bool {
+    self.0.iter().any(|range| range.contains(&status))
+  }
+}
+
+fn parse_status_range(status: &str) -> Result<RangeInclusive<u16>, String> {
+  let invalid = || format!("invalid status '{status}'");
+  let parse = |code: &str| code.trim().parse::<u16>().map_err(|_| invalid());
+
+  if let Some(class) = status.strip_suffix("xx") {
+    let class = parse(class)?;
+    return Ok(class * 100..=class * 100 + 99);
+  }
+  match status.split_once('-') {
+    Some((start, stop)) => Ok(parse(start)?..=parse(stop)?),
+    None => parse(status).map(|code| code..=code),
+  }
+}
+
+impl<'de> Deserialize<'de> for SuccessStatus {
+  fn deserialize<D>(de: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+      Code(u16),
+      Pattern(String),
+    }
+
+    Vec::<Entry>::deserialize(de)?
+      .into_iter()
+      .map(|entry| match entry {
+        Entry::Code(code) => Ok(code..=code),
+        Entry::Pattern(pattern) => parse_status_range(&pattern),
+      })
+      .collect::<Result<_, _>>()
+      .map(SuccessStatus)
+      .map_err(D::Error::custom)
+  }
+}
+
+// Written back as the shortest patterns, like `[2xx, 304, 400-404]`
+impl Serialize for SuccessStatus {
+  fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    ser.collect_seq(self.0.iter().map(|range| {
+      let (start, end) = (*range.start(), *range.end());
+      if start == end {
+        serde_json::Value::from(start)
+      } else if start % 100 == 0 && end == start + 99 {
+        serde_json::Value::from(format!("{}xx", start / 100))
+      } else {
+        serde_json::Value::from(format!("{start}-{end}"))
+      }
+    }))
+  }
+}
+
+/// Parses "shell" option of exec items, the shell commands are run with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+  Sh,
+  #[default]
+  Bash,
+  Pwsh,
+  /// Splits the command into a program and its arguments, and runs it
+  /// directly
+  None,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct IncludeDoc {
   pub path: String,
   pub doc: BenchmarkDoc,
 }
 
-fn include_doc_deser<'de, D>(de: D) -> Result<IncludeDoc, D::Error>
+fn include_doc_deser<'de, D>(de: D) -> Result<Vec<IncludeDoc>, D::Error>
 where
   D: Deserializer<'de>,
 {
-  let mut path: String = Deserialize::deserialize(de)?;
-
-  let cwd = current_dir().unwrap();
-  // Need to calculate and set directory in case we are using relative paths that point to another directory
-  if path.starts_with('.') {
-    let mut new_dir = cwd.clone();
-    new_dir.extend(PathBuf::from(&path).parent().unwrap());
-    let mut new_path = new_dir.clone();
-    new_path.push(PathBuf::from(&path).components().next_back().unwrap());
-    // If working with relative paths, we need to fix the include file path.
-    // We will try to read a file relative to the parent directory of the
-    // newly set directory if we don't change anything
-    path = pathdiff::diff_paths(new_path, &new_dir)
-      .unwrap()
-      .to_string_lossy()
-      .to_string();
-
-    set_current_dir(new_dir).unwrap();
-  };
+  let pattern: String = Deserialize::deserialize(de)?;
+  if !pattern.contains(['*', '?', '[']) {
+    return Ok(vec![include_doc(pattern).map_err(D::Error::custom)?]);
+  }
+
+  // Matches are sorted so the included plans run in a deterministic order,
+  // and kept relative to the directory they were searched from
+  let base = base_dir();
+  let base_pattern = Path::new(&glob::Pattern::escape(&base.to_string_lossy()))
+    .join(&pattern)
+    .to_string_lossy()
+    .into_owned();
+  let mut paths = glob::glob(&base_pattern)
+    .map_err(D::Error::custom)?
+    .filter_map(Result::ok)
+    .filter(|path| path.is_file())
+    .map(|path| match path.strip_prefix(&base) {
+      Ok(relative) => relative.to_path_buf(),
+      Err(_) => path,
+    })
+    .collect::<Vec<_>>();
+  paths.sort();
+  if paths.is_empty() {
+    return Err(D::Error::custom(format!("no files match include {pattern}")));
+  }
+
+  paths
+    .into_iter()
+    .map(|path| {
+      let path = path.to_string_lossy().to_string();
+      // Keep relative matches relative to the directory they were found in
+      let path = if pattern.starts_with("./") && !path.starts_with('.') {
+        format!("./{path}")
+      } else {
+        path
+      };
+      include_doc(path).map_err(D::Error::custom)
+    })
+    .collect()
+}
 
-  let doc = include_doc(&path);
-  // Reset current directory so we can still use relative paths in successive include items after recursing down
-  set_current_dir(cwd).unwrap();
+/// Reads an included file. Files included with a `./` or `../` path have
+/// their own relative paths resolved from their directory, and are labelled
+/// with their file name. The others share the directory of the file including
+/// them.
+fn include_doc(path: String) -> Result<IncludeDoc, String> {
+  let file = resolve_path(&path);
+  if !path.starts_with('.') {
+    let doc = parse_doc(&file, &path)?;
+    return Ok(IncludeDoc {
+      path,
+      doc,
+    });
+  }
+
+  let name = file.file_name().unwrap().to_string_lossy().into_owned();
+  let dir = file.parent().unwrap().to_path_buf();
+  let doc = with_base_dir(dir, || parse_doc(&file, &name))?;
   Ok(IncludeDoc {
-    path,
+    path: name,
     doc,
   })
 }
 
-pub fn include_doc(path: &str) -> BenchmarkDoc {
-  serde_yaml::from_reader(get_file(&path)).unwrap()
+thread_local! {
+  /// Directory the relative paths of the file being parsed are resolved from
+  static BASE_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+  /// Environment the overlay documents of the files apply for
+  static ENVIRONMENT: RefCell<Option<String>> = const { RefCell::new(None) };
+  /// Files being parsed, from the benchmark file to the innermost include
+  static INCLUDE_CHAIN: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+  static MAX_INCLUDE_DEPTH: Cell<usize> = const { Cell::new(DEFAULT_MAX_INCLUDE_DEPTH) };
+}
+
+pub const DEFAULT_MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Sets how deep includes can be nested in the files read afterwards
+pub fn set_max_include_depth(depth: usize) {
+  MAX_INCLUDE_DEPTH.with(|max| max.set(depth));
+}
+
+/// Selects the documents setting this `environment`, in the files read
+/// afterwards, see `merge_documents`
+pub fn select_environment(environment: Option<String>) {
+  ENVIRONMENT.with(|selected| *selected.borrow_mut() = environment);
+}
+
+/// Directory of the file being parsed, the working directory outside of
+/// `read_benchmark_doc`
+fn base_dir() -> PathBuf {
+  BASE_DIR
+    .with(|dir| dir.borrow().clone())
+    .unwrap_or_else(|| current_dir().unwrap())
+}
+
+/// Resolves a path written in the file being parsed
+fn resolve_path(path: &str) -> PathBuf {
+  base_dir().join(path).absolutize().unwrap().to_path_buf()
+}
+
+/// Puts back the base directory it replaced when dropped, so it's restored
+/// even when parsing panics
+struct BaseDirGuard(Option<PathBuf>);
+
+impl Drop for BaseDirGuard {
+  fn drop(&mut self) {
+    let outer = self.0.take();
+    BASE_DIR.with(|base| *base.borrow_mut() = outer);
+  }
+}
+
+fn with_base_dir<T>(dir: PathBuf, parse: impl FnOnce() -> T) -> T {
+  let _outer = BaseDirGuard(BASE_DIR.with(|base| base.replace(Some(dir))));
+  parse()
+}
+
+/// Reads a benchmark file, resolving the relative paths it holds from its
+/// directory, whatever the working directory. Errors are formatted as
+/// `file:line:column: path: message`, pointing at the YAML node that didn't
+/// match the schema.
+pub fn read_benchmark_doc<S: AsRef<OsStr> + ?Sized>(
+  path: &S,
+) -> Result<BenchmarkDoc, String> {
+  let file = Path::new(path).absolutize().unwrap().to_path_buf();
+  let dir = file.parent().unwrap().to_path_buf();
+  let name = Path::new(path).display().to_string();
+  INCLUDE_CHAIN.with(|chain| chain.borrow_mut().clear());
+  with_base_dir(dir, || parse_doc(&file, &name))
+}
+
+/// Parses a file, unless it's already being parsed further up the includes
+/// or they are nested too deep
+fn parse_doc(path: &Path, name: &str) -> Result<BenchmarkDoc, String> {
+  let chain = INCLUDE_CHAIN.with(|chain| chain.borrow().clone());
+  // Files are named from the benchmark directory
+  let describe = |chain: &[PathBuf]| {
+    let root = chain.first().and_then(|file| file.parent());
+    chain
+      .iter()
+      .chain([path.to_path_buf()].iter())
+      .map(|file| match root.and_then(|root| file.strip_prefix(root).ok()) {
+        Some(relative) => relative.display().to_string(),
+        None => file.display().to_string(),
+      })
+      .collect::<Vec<_>>()
+      .join(" -> ")
+  };
+  if chain.iter().any(|file| file == path) {
+    return Err(format!("include cycle: {}", describe(&chain)));
+  }
+  let max_depth = MAX_INCLUDE_DEPTH.with(Cell::get);
+  if chain.len() > max_depth {
+    return Err(format!(
+      "includes nested deeper than --max-include-depth {}: {}",
+      max_depth,
+      describe(&chain)
+    ));
+  }
+
+  INCLUDE_CHAIN.with(|chain| chain.borrow_mut().push(path.to_path_buf()));
+  let doc = parse_file(path, name);
+  INCLUDE_CHAIN.with(|chain| chain.borrow_mut().pop());
+  doc
+}
+
+fn parse_file(path: &Path, name: &str) -> Result<BenchmarkDoc, String> {
+  let located = |err: serde_yaml::Error| {
+    let message = err.to_string();
+    match err.location() {
+      Some(location) => {
+        let suffix =
+          format!(" at line {} column {}", location.line(), location.column());
+        format!(
+          "{}:{}:{}: {}",
+          name,
+          location.line(),
+          location.column(),
+          message.strip_suffix(&suffix).unwrap_or(&message)
+        )
+      }
+      None => format!("{name}: {message}"),
+    }
+  };
+
+  let mut text = String::new();
+  get_file(path)
+    .read_to_string(&mut text)
+    .map_err(|err| format!("{name}: {err}"))?;
+  let documents = serde_yaml::Deserializer::from_str(&text)
+    .map(serde_yaml::Value::deserialize)
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(located)?;
+
+  // A single document is parsed as is, so errors point at their line
+  if documents.len() < 2 {
+    return serde_yaml::from_str(&text).map_err(located);
+  }
+  let merged =
+    merge_documents(documents).map_err(|err| format!("{name}: {err}"))?;
+  serde_yaml::from_value(merged).map_err(located)
+}
+
+/// Merges the `---` separated documents of a file in order. Later documents
+/// override the settings of the earlier ones and add their `plan` after
+/// theirs, as scenarios run one after the other. Documents setting an
+/// `environment` are overlays, only applied when it's the selected one.
+fn merge_documents(
+  documents: Vec<serde_yaml::Value>,
+) -> Result<serde_yaml::Value, String> {
+  let selected = ENVIRONMENT.with(|environment| environment.borrow().clone());
+  let mut environments = Vec::new();
+  let mut merged = serde_yaml::Mapping::new();
+
+  for document in documents {
+    let mut document = match document {
+      serde_yaml::Value::Mapping(document) => document,
+      serde_yaml::Value::Null => continue,
+      _ => return Err("expected every document to be a mapping".to_owned()),
+    };
+    if let Some(environment) = document.remove("environment") {
+      let environment = environment
+        .as_str()
+        .ok_or("expected `environment` to be a string")?
+        .to_owned();
+      let applies = selected.as_ref() == Some(&environment);
+      environments.push(environment);
+      if !applies {
+        continue;
+      }
+    }
+    merge_mapping(&mut merged, document, true);
+  }
+
+  match selected {
+    Some(selected)
+      if !environments.is_empty() && !environments.contains(&selected) =>
+    {
+      Err(format!(
+        "unknown environment '{}', expected one of {}",
+        selected,
+        environments.join(", ")
+      ))
+    }
+    _ => Ok(serde_yaml::Value::Mapping(merged)),
+  }
+}
+
+fn merge_mapping(
+  base: &mut serde_yaml::Mapping,
+  overlay: serde_yaml::Mapping,
+  top_level: bool,
+) {
+  use serde_yaml::Value as Yaml;
+
+  for (key, value) in overlay {
+    let scenario = top_level && key == "plan";
+    match (base.get_mut(&key), value) {
+      (Some(Yaml::Sequence(plan)), Yaml::Sequence(items)) if scenario => {
+        plan.extend(items)
+      }
+      (Some(Yaml::Mapping(inner)), Yaml::Mapping(value)) => {
+        merge_mapping(inner, value, false)
+      }
+      (_, value) => {
+        base.insert(key, value);
+      }
+    }
+  }
+}
+
+/// Resolves a path from the directory of the file it appears in, so included
+/// files can reference their neighbours
+fn absolute_path<'de, D>(de: D) -> Result<Option<String>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let path: String = Deserialize::deserialize(de)?;
+  Ok(Some(resolve_path(&path).to_string_lossy().to_string()))
+}
+
+fn absolute_paths<'de, D>(de: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  let paths: BTreeMap<String, String> = Deserialize::deserialize(de)?;
+  Ok(
+    paths
+      .into_iter()
+      .map(|(name, path)| {
+        (name, resolve_path(&path).to_string_lossy().to_string())
+      })
+      .collect(),
+  )
+}
+
+/// Reads a scalar, like `1.2` or `v2`, as a string
+fn optional_scalar<'de, D>(de: D) -> Result<Option<String>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  match Deserialize::deserialize(de)? {
+    serde_yaml::Value::Null => Ok(None),
+    serde_yaml::Value::String(value) => Ok(Some(value)),
+    serde_yaml::Value::Number(value) => Ok(Some(value.to_string())),
+    serde_yaml::Value::Bool(value) => Ok(Some(value.to_string())),
+    _ => Err(D::Error::custom("expected a string or a number")),
+  }
 }
 
 fn get_env<'de, D>(de: D) -> Result<BTreeMap<String, String>, D::Error>
@@ -274,7 +1785,7 @@ where
   D: Deserializer<'de>,
 {
   let path: String = Deserialize::deserialize(de)?;
-  let env_file = PathBuf::from(path).absolutize().unwrap().to_path_buf();
+  let env_file = resolve_path(&path);
   let env = if let Ok(true) = env_file.try_exists() {
     let mut buffer = String::new();
     if let Ok(mut file) = File::open(env_file) {
@@ -284,43 +1795,125 @@ where
     }
     buffer
       .lines()
+      .filter(|s| !s.trim().is_empty())
       .map(|s| {
         s.split_once('=')
           .map(|(k, v)| (k.to_owned(), v.to_owned()))
           .or_else(|| {
             let mut split = s.split_whitespace();
-            Some((
-              split.next().expect(".env key before whitespace").to_owned(),
-              split.next().expect(".env value after whitespace").to_owned(),
-            ))
+            Some((split.next()?.to_owned(), split.next()?.to_owned()))
+          })
+          .ok_or_else(|| {
+            D::Error::custom(format!("invalid line in .env file: '{s}'"))
           })
-          .unwrap()
       })
-      .collect::<BTreeMap<String, String>>()
+      .collect::<Result<BTreeMap<String, String>, D::Error>>()?
   } else {
     BTreeMap::new()
   };
   Ok(env)
 }
 
-fn get_databases<'de, D>(
+/// Parses a duration like `500ms`, `2.5s`, `1m` or `1h`. Bare numbers are
+/// seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+  let duration = duration.trim();
+  let split = duration
+    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+    .unwrap_or(duration.len());
+  let (value, unit) = duration.split_at(split);
+  let value: f64 =
+    value.parse().map_err(|_| format!("invalid duration '{duration}'"))?;
+  let seconds = match unit.trim() {
+    "ms" => value / 1_000.0,
+    "" | "s" => value,
+    "m" => value * 60.0,
+    "h" => value * 3_600.0,
+    _ => {
+      return Err(format!(
+        "invalid duration '{duration}', expected a unit like ms, s, m or h"
+      ))
+    }
+  };
+  Ok(Duration::from_secs_f64(seconds))
+}
+
+pub(crate) fn duration<'de, D>(de: D) -> Result<Duration, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum RawDuration {
+    Seconds(f64),
+    Text(String),
+  }
+
+  match RawDuration::deserialize(de)? {
+    RawDuration::Seconds(seconds) => {
+      Duration::try_from_secs_f64(seconds).map_err(D::Error::custom)
+    }
+    RawDuration::Text(text) => parse_duration(&text).map_err(D::Error::custom),
+  }
+}
+
+pub(crate) fn optional_duration<'de, D>(
   de: D,
-) -> Result<BTreeMap<String, YamlDbDefinition>, D::Error>
+) -> Result<Option<Duration>, D::Error>
 where
   D: Deserializer<'de>,
 {
-  let map: HashMap<String, HashMap<String, serde_yaml::Value>> =
-    Deserialize::deserialize(de)?;
+  duration(de).map(Some)
+}
 
-  Ok(
-    map
-      .get("database")
-      .cloned()
-      .unwrap_or_else(HashMap::new)
-      .iter()
-      .map(|(k, v)| (k.clone(), serde_yaml::from_value(v.clone()).unwrap()))
-      .collect(),
-  )
+/// Writes a duration the way `parse_duration` reads it, like `500ms` or `2s`
+pub fn format_duration(duration: Duration) -> String {
+  if duration.subsec_nanos() == 0 {
+    format!("{}s", duration.as_secs())
+  } else if duration.subsec_nanos().is_multiple_of(1_000_000) {
+    format!("{}ms", duration.as_millis())
+  } else {
+    format!("{}s", duration.as_secs_f64())
+  }
+}
+
+pub(crate) fn serialize_duration<S>(
+  duration: &Duration,
+  ser: S,
+) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  ser.serialize_str(&format_duration(*duration))
+}
+
+pub(crate) fn serialize_optional_duration<S>(
+  duration: &Option<Duration>,
+  ser: S,
+) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  duration.map(format_duration).serialize(ser)
+}
+
+/// Writes a map with its keys sorted, so it reads the same on every run
+fn sorted<S>(map: &HashMap<String, String>, ser: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  map.iter().collect::<BTreeMap<_, _>>().serialize(ser)
+}
+
+/// Writes the names of a map only, as its values may be secrets
+fn redacted_values<S>(
+  map: &BTreeMap<String, String>,
+  ser: S,
+) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  ser.collect_map(map.keys().map(|key| (key, "[REDACTED]")))
 }
 
 fn default_method() -> String {
@@ -328,4 +1921,310 @@ fn default_method() -> String {
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+  use super::*;
+
+  #[test]
+  fn reports_path_of_invalid_values() {
+    let doc = "plan:\n  - name: Wait\n    delay:\n      min: 1s\n";
+    let err = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap_err();
+
+    assert!(err.to_string().starts_with("plan[0].delay.min: invalid type"));
+    assert_eq!(err.location().unwrap().line(), 4);
+  }
+
+  #[test]
+  fn reports_unknown_actions() {
+    let doc = "plan:\n  - name: Fetch\n    reqest:\n      url: /\n";
+    let err = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap_err();
+
+    assert!(err.to_string().starts_with("plan[0]: unknown field `reqest`"));
+  }
+
+  #[test]
+  fn reports_missing_actions() {
+    let doc = "plan:\n  - name: Nothing\n";
+    let err = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap_err();
+
+    assert!(err.to_string().starts_with(
+      "plan[0]: missing action, expected one of `assert`, `assign`, `counter_inc`, `custom`, `db-query`, `debug`, `delay`, `exec`, `metric`, `plugin`, `request`, `script`, `shared_set`, `transaction`, `include` or `use`"
+    ), "{}", err);
+    assert!(ACTION_FIELDS.iter().all(|field| PLAN_ITEM_FIELDS.contains(field)));
+  }
+
+  #[test]
+  fn joins_urls() {
+    let join = |mode: UrlJoin, base, url| mode.join(base, url).unwrap();
+
+    assert_eq!(
+      join(UrlJoin::Append, "http://h/api", "/users?q=a/b").as_str(),
+      "http://h/api/users?q=a/b"
+    );
+    assert_eq!(
+      join(UrlJoin::Append, "http://h/api/", "users").as_str(),
+      "http://h/api/users"
+    );
+    assert_eq!(join(UrlJoin::Standard, "http://h/api/v1", "/x").as_str(), "http://h/x");
+    assert_eq!(join(UrlJoin::Standard, "http://h/api/v1", "x").as_str(), "http://h/api/x");
+    assert_eq!(join(UrlJoin::Append, "http://h/api", "http://o/x").as_str(), "http://o/x");
+  }
+
+  #[test]
+  fn parses_durations() {
+    assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("2.5s"), Ok(Duration::from_millis(2_500)));
+    assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+    assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+    assert!(parse_duration("3 days").is_err());
+    assert!(parse_duration("ms").is_err());
+  }
+
+  #[test]
+  fn parses_sub_second_delays() {
+    let doc = "plan:\n  - name: Wait\n    delay:\n      seconds: 500ms\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(
+      doc.plan[0].action,
+      Action::Delay(DelaySpec::Fixed(d)) if d == Duration::from_millis(500)
+    ));
+  }
+
+  #[test]
+  fn parses_transactions() {
+    let doc = "plan:\n  - name: Checkout\n    transaction:\n      - name: Wait\n        delay:\n          seconds: 1\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(&doc.plan[0].action, Action::Transaction(items) if items.len() == 1));
+  }
+
+  #[test]
+  fn parses_path_params() {
+    let doc = "plan:\n  - name: User\n    request:\n      url: /users/:id\n      path_params:\n        id: '{{ id }}'\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(
+      &doc.plan[0].action,
+      Action::Request { path_params, .. } if path_params["id"] == "{{ id }}"
+    ));
+  }
+
+  #[test]
+  fn parses_metadata() {
+    let doc = "name: Checkout\nversion: 1.2\nplan: []\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert_eq!(
+      Metadata::from(&doc),
+      Metadata {
+        name: Some("Checkout".to_owned()),
+        description: None,
+        version: Some("1.2".to_owned()),
+      }
+    );
+  }
+
+  #[test]
+  fn parses_redactions() {
+    let doc = "redact:\n  - header: Authorization\n  - pattern: 'sk_\\w+'\nplan: []\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(
+      &doc.redact[0],
+      Redaction::Header(name) if name == "Authorization"
+    ));
+    assert!(matches!(
+      &doc.redact[1],
+      Redaction::Pattern(pattern) if pattern.is_match("sk_1")
+    ));
+
+    let doc = "redact:\n  - key: token\n    header: Cookie\nplan: []\n";
+    assert!(serde_yaml::from_str::<BenchmarkDoc>(doc).is_err());
+    let doc = "redact:\n  - pattern: '('\nplan: []\n";
+    assert!(serde_yaml::from_str::<BenchmarkDoc>(doc).is_err());
+  }
+
+  #[test]
+  fn parses_metrics() {
+    let doc = "plan:\n  - metric:\n      name: orders\n      type: counter\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(
+      &doc.plan[0].action,
+      Action::Metric { kind: MetricKind::Counter, value, .. } if value == 1
+    ));
+  }
+
+  #[test]
+  fn parses_thresholds() {
+    let threshold = Threshold::try_from("checkout_ok.avg > 99%".to_owned());
+
+    assert_eq!(
+      threshold,
+      Ok(Threshold {
+        expression: "checkout_ok.avg > 99%".to_owned(),
+        metric: "checkout_ok".to_owned(),
+        stat: Some(MetricStat::Avg),
+        operator: Comparison::Gt,
+        value: 99.0,
+        percent: true,
+      })
+    );
+    let threshold = Threshold::try_from("orders<=5".to_owned()).unwrap();
+    assert_eq!((threshold.stat, threshold.operator), (None, Comparison::Le));
+    assert!(Threshold::try_from("orders.p42 > 1".to_owned()).is_err());
+    assert!(Threshold::try_from("orders 5".to_owned()).is_err());
+  }
+
+  #[test]
+  fn parses_assign_actions_and_keys() {
+    let doc = "plan:\n  - assign:\n      key: foo\n      value: 1\n  - exec:\n      command: ls\n    assign: bar\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(&doc.plan[0].action, Action::Assign { key, .. } if key == "foo"));
+    assert_eq!(doc.plan[1].assign.as_deref(), Some("bar"));
+  }
+
+  #[test]
+  fn parses_expected_failures() {
+    let doc = "expected_failures: [429, 5xx]\nplan: []\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+    let expected = doc.expected_failures.unwrap();
+
+    assert!(expected.matches(429));
+    assert!(expected.matches(503));
+    assert!(!expected.matches(404));
+  }
+
+  #[test]
+  fn parses_debug_actions() {
+    let doc = "plan:\n  - debug:\n      keys: [user.id, token]\n  - debug: {}\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert!(matches!(&doc.plan[0].action, Action::Debug { keys } if keys == &["user.id", "token"]));
+    assert!(matches!(&doc.plan[1].action, Action::Debug { keys } if keys.is_empty()));
+  }
+
+  #[test]
+  fn writes_docs_back() {
+    let doc = "rampup: 1.5
+success_status: [2xx, 304, 400-404]
+plan:
+  - name: Wait
+    tags: [slow]
+    delay:
+      min: 10
+      max: 20
+  - name: Login
+    use: login
+    args:
+      user: bob
+";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+    let mut written = serde_json::to_value(&doc).unwrap();
+
+    assert_eq!(written["rampup"], "1500ms");
+    assert_eq!(written["success_status"], serde_json::json!(["2xx", 304, "400-404"]));
+    assert_eq!(written["plan"][0], serde_json::json!({"name": "Wait", "tags": ["slow"], "delay": {"min": 10, "max": 20}}));
+    assert_eq!(written["plan"][1], serde_json::json!({"name": "Login", "use": "login", "args": {"user": "bob"}}));
+
+    // The env file is written as the names of its variables, not a path
+    written.as_object_mut().unwrap().remove("env");
+    let read = serde_yaml::to_string(&written).unwrap();
+    let read = serde_yaml::from_str::<BenchmarkDoc>(&read).unwrap();
+    assert_eq!(read.rampup, Duration::from_millis(1500));
+    assert!(matches!(read.plan[0].action, Action::Delay(DelaySpec::Uniform { .. })));
+  }
+
+  #[test]
+  fn parses_section_limits() {
+    let doc = "plan:\n  - name: Admin\n    concurrency: 2\n    iterations: 10\n    request:\n      url: /admin\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+
+    assert_eq!(doc.plan[0].concurrency, Some(2));
+    assert_eq!(doc.plan[0].iterations, Some(10));
+
+    let doc = "plan:\n  - name: Admin\n    concurrency: 0\n    request:\n      url: /admin\n";
+    let err = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap_err();
+    assert!(err.to_string().contains("`concurrency` can't be 0"));
+  }
+
+  #[test]
+  fn merges_documents() {
+    let doc = "iterations: 2\nglobal:\n  a: '1'\n  b: '1'\nplan:\n  - name: First\n    assign: {key: x, value: 1}\n---\nglobal:\n  b: '2'\nplan:\n  - name: Second\n    assign: {key: x, value: 2}\n---\nenvironment: staging\niterations: 5\n";
+    let documents = serde_yaml::Deserializer::from_str(doc).map(serde_yaml::Value::deserialize).collect::<Result<Vec<_>, _>>().unwrap();
+
+    let merged = merge_documents(documents.clone()).unwrap();
+    let merged = serde_yaml::from_value::<BenchmarkDoc>(merged).unwrap();
+    assert_eq!(merged.iterations, 2);
+    assert_eq!(merged.global["a"], "1");
+    assert_eq!(merged.global["b"], "2");
+    let names: Vec<_> = merged.plan.iter().map(|item| item.name.clone().unwrap()).collect();
+    assert_eq!(names, ["First", "Second"]);
+
+    select_environment(Some("staging".to_owned()));
+    let merged = merge_documents(documents.clone()).unwrap();
+    assert_eq!(serde_yaml::from_value::<BenchmarkDoc>(merged).unwrap().iterations, 5);
+
+    select_environment(Some("prod".to_owned()));
+    assert!(merge_documents(documents).unwrap_err().contains("unknown environment 'prod'"));
+    select_environment(None);
+  }
+
+  #[test]
+  fn parses_pick_percentages() {
+    let pick = |doc: &str| serde_yaml::from_str::<Pick>(doc);
+
+    assert_eq!(pick("3").unwrap(), Pick::Count(3));
+    assert_eq!(pick("10%").unwrap().count(25), 3);
+    assert_eq!(pick("100%").unwrap().count(25), 25);
+    assert!(pick("-1").unwrap_err().to_string().contains("should not be negative"));
+    assert!(pick("0%").is_err());
+    assert!(pick("150%").is_err());
+    assert_eq!(serde_json::to_value(pick("12.5%").unwrap()).unwrap(), "12.5%");
+
+    let doc = "plan:\n  - name: Users\n    request:\n      url: /users/{{ id }}\n      with_items:\n        items: [{id: '1'}, {id: '2'}]\n        pick: 50%\n        resample: per_iteration\n";
+    let doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+    match &doc.plan[0].action {
+      Action::Request { with_items: Some(with_items), .. } => {
+        assert_eq!(with_items.pick, Pick::Percent(50.0));
+        assert_eq!(with_items.resample, Resample::PerIteration);
+      }
+      _ => panic!("expected a request with items"),
+    }
+  }
+
+  #[test]
+  fn restores_the_base_dir() {
+    let outer = std::env::temp_dir();
+    with_base_dir(outer.clone(), || {
+      let parsed = std::panic::catch_unwind(|| {
+        with_base_dir(outer.join("inner"), || panic!("invalid file"))
+      });
+      assert!(parsed.is_err());
+      assert_eq!(base_dir(), outer);
+    });
+    assert_eq!(base_dir(), current_dir().unwrap());
+  }
+
+  #[test]
+  fn detects_include_cycles() {
+    let dir = std::env::temp_dir().join(format!("drill-includes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.yml"), "plan:\n  - include: a.yml\n").unwrap();
+    std::fs::write(dir.join("a.yml"), "plan:\n  - include: b.yml\n").unwrap();
+    std::fs::write(dir.join("b.yml"), "plan:\n  - include: a.yml\n").unwrap();
+
+    let err = read_benchmark_doc(&dir.join("main.yml")).unwrap_err();
+    assert!(err.ends_with("include cycle: main.yml -> a.yml -> b.yml -> a.yml"), "{}", err);
+
+    std::fs::write(dir.join("b.yml"), "plan:\n  - name: Wait\n    delay:\n      seconds: 1\n").unwrap();
+    set_max_include_depth(1);
+    let err = read_benchmark_doc(&dir.join("main.yml")).unwrap_err();
+    assert!(err.ends_with("deeper than --max-include-depth 1: main.yml -> a.yml -> b.yml"), "{}", err);
+
+    set_max_include_depth(DEFAULT_MAX_INCLUDE_DEPTH);
+    read_benchmark_doc(&dir.join("main.yml")).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,76 @@
+use std::fmt;
+use std::sync::Arc;
+
+use wasmi::{Engine, Linker, Module, Store};
+
+/// Custom action compiled to WebAssembly. Modules take no imports and
+/// export:
+///
+/// - `memory`: the linear memory requests and responses are copied into
+/// - `alloc(len: i32) -> i32`: reserves `len` bytes for the request
+/// - `execute(ptr: i32, len: i32) -> i64`: handles the JSON request at
+///   `ptr` and returns where its JSON response is, as `ptr << 32 | len`
+///
+/// See SYNTAX.md for the request and response documents.
+#[derive(Clone)]
+pub struct WasmPlugin {
+  path: String,
+  engine: Engine,
+  module: Arc<Module>,
+}
+
+impl fmt::Debug for WasmPlugin {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("WasmPlugin").field("path", &self.path).finish()
+  }
+}
+
+impl WasmPlugin {
+  pub fn load(path: &str) -> Result<WasmPlugin, String> {
+    let bytes = std::fs::read(path)
+      .map_err(|err| format!("Couldn't read plugin {path}: {err}"))?;
+    let engine = Engine::default();
+    let module = Module::new(&engine, &bytes[..])
+      .map_err(|err| format!("Invalid plugin {path}: {err}"))?;
+
+    Ok(WasmPlugin {
+      path: path.to_owned(),
+      engine,
+      module: Arc::new(module),
+    })
+  }
+
+  /// Runs `execute` on a fresh instance, so no state leaks between calls
+  pub fn call(&self, request: &[u8]) -> Result<Vec<u8>, String> {
+    let mut store = Store::new(&self.engine, ());
+    let linker = <Linker<()>>::new(&self.engine);
+    let instance = linker
+      .instantiate(&mut store, &self.module)
+      .and_then(|instance| instance.start(&mut store))
+      .map_err(|err| err.to_string())?;
+
+    let memory = instance
+      .get_memory(&store, "memory")
+      .ok_or("missing `memory` export")?;
+    let alloc = instance
+      .get_typed_func::<i32, i32>(&store, "alloc")
+      .map_err(|err| format!("`alloc` export: {err}"))?;
+    let execute = instance
+      .get_typed_func::<(i32, i32), i64>(&store, "execute")
+      .map_err(|err| format!("`execute` export: {err}"))?;
+
+    let len = request.len() as i32;
+    let ptr = alloc.call(&mut store, len).map_err(|err| err.to_string())?;
+    memory
+      .write(&mut store, ptr as u32 as usize, request)
+      .map_err(|err| err.to_string())?;
+
+    let packed =
+      execute.call(&mut store, (ptr, len)).map_err(|err| err.to_string())?;
+    let (ptr, len) = ((packed as u64 >> 32) as usize, packed as u32 as usize);
+    let mut response = vec![0; len];
+    memory.read(&store, ptr, &mut response).map_err(|err| err.to_string())?;
+
+    Ok(response)
+  }
+}
@@ -1,14 +1,31 @@
+use std::collections::BTreeSet;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 
 use path_absolutize::Absolutize;
+use serde::Deserialize;
+
+/// Files opened so far, missing ones included, for `--watch` to know what
+/// a benchmark reads
+static OPENED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Files opened since the last call
+pub fn take_opened_files() -> BTreeSet<PathBuf> {
+  mem::take(&mut OPENED.lock().unwrap_or_else(PoisonError::into_inner))
+}
 
 pub fn get_file<S: AsRef<OsStr> + ?Sized>(filepath: &S) -> File {
   // Create a path to the desired file
 
   let path = Path::new(filepath).absolutize().unwrap();
+  OPENED
+    .lock()
+    .unwrap_or_else(PoisonError::into_inner)
+    .insert(path.to_path_buf());
 
   // Open the path in read-only mode, returns `io::Result<File>`
   match File::open(&path) {
@@ -32,33 +49,19 @@ pub fn read_file<S: AsRef<OsStr> + ?Sized>(filepath: &S) -> String {
   content
 }
 
-pub fn read_file_as_yml<S: AsRef<OsStr> + ?Sized>(
-  filepath: &S,
-) -> serde_yaml::Value {
-  serde_yaml::from_reader(get_file(filepath)).unwrap()
-}
-
-pub fn read_yaml_doc_accessor<'a>(
-  doc: &'a serde_yaml::Value,
-  accessor: &str,
-) -> &'a serde_yaml::Value {
-  match doc.get(accessor) {
-    Some(items) => items,
-    None => {
-      println!("Node missing on config: {accessor}");
-      println!("Exiting.");
-      std::process::exit(1)
-    }
-  }
-}
-
+/// Reads the items of a YAML file, those of all its `---` separated
+/// documents in order
 #[allow(dead_code)]
 pub fn read_file_as_yml_array<S: AsRef<OsStr> + ?Sized>(
   filepath: &S,
 ) -> Vec<serde_yaml::Value> {
   let file = get_file(filepath);
   let reader = BufReader::new(file);
-  serde_yaml::from_reader(reader).unwrap()
+  serde_yaml::Deserializer::from_reader(reader)
+    .flat_map(|document| {
+      Vec::<serde_yaml::Value>::deserialize(document).unwrap()
+    })
+    .collect()
 }
 
 pub fn read_csv_file_as_yml<S: AsRef<OsStr> + ?Sized>(
@@ -0,0 +1,256 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use colored::*;
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::net::TcpStream;
+use tokio::runtime;
+
+use crate::import::{
+  is_recorded_header, is_static_asset, is_supported_method, warn, write_plan,
+  PlanDoc, PlanStep, RequestStep,
+};
+
+// Only meaningful for a single connection, never forwarded by proxies
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+  "connection",
+  "keep-alive",
+  "proxy-authenticate",
+  "proxy-authorization",
+  "te",
+  "trailer",
+  "transfer-encoding",
+  "upgrade",
+];
+
+struct Recorder {
+  doc: Mutex<PlanDoc>,
+  client: reqwest::Client,
+  skip_static: bool,
+  warned_https: AtomicBool,
+}
+
+/// Runs a local HTTP proxy, forwarding and recording every request sent
+/// through it until Ctrl-C is pressed, then writes them as a plan. HTTPS
+/// traffic is tunnelled as is, so it can't be recorded.
+pub fn run(port: u16, skip_static: bool, output: Option<String>) {
+  let recorder = Arc::new(Recorder {
+    doc: Mutex::new(PlanDoc::new("Recorded with drill record".to_owned())),
+    client: reqwest::Client::builder()
+      .redirect(reqwest::redirect::Policy::none())
+      .build()
+      .unwrap(),
+    skip_static,
+    warned_https: AtomicBool::new(false),
+  });
+
+  let rt = runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+  rt.block_on(serve(port, recorder.clone()));
+
+  let doc = recorder.doc.lock().unwrap();
+  write_plan(&doc, output);
+}
+
+async fn serve(port: u16, recorder: Arc<Recorder>) {
+  let addr = SocketAddr::from(([127, 0, 0, 1], port));
+  let make_service = make_service_fn(move |_| {
+    let recorder = recorder.clone();
+    async move {
+      Ok::<_, Infallible>(service_fn(move |req| {
+        proxy(recorder.clone(), req)
+      }))
+    }
+  });
+
+  let server = match Server::try_bind(&addr) {
+    Ok(builder) => builder.serve(make_service),
+    Err(err) => {
+      eprintln!("{} Can't listen on {}: {}", "ERROR:".red().bold(), addr, err);
+      std::process::exit(1);
+    }
+  };
+
+  eprintln!(
+    "{} {}, press Ctrl-C to stop",
+    "Recording through proxy".green(),
+    format!("http://{addr}").cyan()
+  );
+
+  let shutdown = async {
+    let _ = tokio::signal::ctrl_c().await;
+  };
+  if let Err(err) = server.with_graceful_shutdown(shutdown).await {
+    warn(format!("Proxy stopped: {err}"));
+  }
+}
+
+async fn proxy(
+  recorder: Arc<Recorder>,
+  req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+  if req.method() == Method::CONNECT {
+    return Ok(recorder.tunnel(req));
+  }
+
+  let url = req.uri().to_string();
+  if req.uri().scheme().is_none() {
+    return Ok(error_response(
+      StatusCode::BAD_REQUEST,
+      format!("Expected an absolute url, got {url}"),
+    ));
+  }
+
+  let (parts, body) = req.into_parts();
+  let body = match hyper::body::to_bytes(body).await {
+    Ok(body) => body,
+    Err(err) => {
+      return Ok(error_response(StatusCode::BAD_REQUEST, err.to_string()))
+    }
+  };
+
+  let mut headers = parts.headers.clone();
+  strip_hop_by_hop(&mut headers);
+  let response = recorder
+    .client
+    .request(parts.method.clone(), &url)
+    .headers(headers)
+    .body(body.clone())
+    .send()
+    .await;
+
+  let response = match response {
+    Ok(response) => response,
+    Err(err) => {
+      return Ok(error_response(StatusCode::BAD_GATEWAY, err.to_string()))
+    }
+  };
+
+  let status = response.status();
+  let mut headers = response.headers().clone();
+  strip_hop_by_hop(&mut headers);
+  let mime_type = headers
+    .get(CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default()
+    .to_owned();
+
+  println!("{:width$} {}", parts.method.as_str().green(), url, width = 7);
+  recorder.record(&parts.method, &url, &parts.headers, &body, &mime_type);
+
+  let body = match response.bytes().await {
+    Ok(body) => body,
+    Err(err) => {
+      return Ok(error_response(StatusCode::BAD_GATEWAY, err.to_string()))
+    }
+  };
+
+  let mut proxied = Response::new(Body::from(body));
+  *proxied.status_mut() = status;
+  *proxied.headers_mut() = headers;
+  Ok(proxied)
+}
+
+impl Recorder {
+  /// Opens a blind tunnel to the requested host, for HTTPS traffic
+  fn tunnel(&self, req: Request<Body>) -> Response<Body> {
+    let authority = match req.uri().authority() {
+      Some(authority) => authority.to_string(),
+      None => {
+        return error_response(
+          StatusCode::BAD_REQUEST,
+          "CONNECT needs a host and port".to_owned(),
+        )
+      }
+    };
+
+    if !self.warned_https.swap(true, Ordering::SeqCst) {
+      warn(format!(
+        "Tunnelling HTTPS traffic to {authority} without recording it, \
+         only plain HTTP requests are recorded"
+      ));
+    }
+
+    tokio::spawn(async move {
+      let upgraded = match hyper::upgrade::on(req).await {
+        Ok(upgraded) => upgraded,
+        Err(err) => return warn(format!("Tunnel to {authority} failed: {err}")),
+      };
+      match TcpStream::connect(&authority).await {
+        Ok(mut server) => {
+          let mut client = upgraded;
+          let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+        }
+        Err(err) => warn(format!("Can't connect to {authority}: {err}")),
+      }
+    });
+
+    Response::new(Body::empty())
+  }
+
+  fn record(
+    &self,
+    method: &Method,
+    url: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    mime_type: &str,
+  ) {
+    if self.skip_static && is_static_asset(url, mime_type) {
+      return;
+    }
+
+    let method = method.as_str().to_uppercase();
+    if !is_supported_method(&method) {
+      warn(format!("Skipping {method} {url}: unsupported method"));
+      return;
+    }
+
+    let mut doc = self.doc.lock().unwrap();
+    let (base, url) = match doc.split_url(url) {
+      Some((base, url)) => (Some(base), url),
+      None => (None, url.to_owned()),
+    };
+    let mut step = RequestStep {
+      base,
+      url,
+      method,
+      ..Default::default()
+    };
+
+    for (name, value) in headers.iter() {
+      let name = name.as_str();
+      let keep = name == "cookie" || is_recorded_header(name);
+      if keep && !HOP_BY_HOP_HEADERS.contains(&name) {
+        if let Ok(value) = value.to_str() {
+          step.headers.insert(name.to_owned(), value.to_owned());
+        }
+      }
+    }
+
+    if !body.is_empty() {
+      step.body = Some(String::from_utf8_lossy(body).into_owned());
+    }
+
+    doc.plan.push(PlanStep {
+      name: step.default_name(),
+      request: step,
+    });
+  }
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+  for name in HOP_BY_HOP_HEADERS.iter() {
+    headers.remove(*name);
+  }
+  headers.remove("proxy-connection");
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+  let mut response = Response::new(Body::from(message));
+  *response.status_mut() = status;
+  response
+}
@@ -1,15 +1,24 @@
-use crate::reader;
+use crate::args::ListFormat;
+use crate::parse::{Action, BenchmarkDoc, PlanItem};
+use crate::benchmark::read_benchmark_file;
 use colored::*;
+use serde::Serialize;
 use std::collections::HashSet;
+use std::fmt;
 
 #[derive(Debug)]
 pub struct Tags {
   pub tags: HashSet<String>,
   pub skip_tags: HashSet<String>,
+  pub expression: Option<TagExpr>,
 }
 
 impl Tags {
-  pub fn new(tags_option: Vec<String>, skip_tags_option: Vec<String>) -> Self {
+  pub fn new(
+    tags_option: Vec<String>,
+    skip_tags_option: Vec<String>,
+    expression_option: Option<String>,
+  ) -> Self {
     let tags: HashSet<String> = tags_option.into_iter().collect();
     let skip_tags: HashSet<String> = skip_tags_option.into_iter().collect();
 
@@ -17,109 +26,450 @@ impl Tags {
       panic!("`tags` and `skip-tags` must not contain the same values!");
     }
 
+    let expression = expression_option.map(|expression| {
+      TagExpr::parse(&expression).unwrap_or_else(|err| {
+        eprintln!("{} Invalid --tags expression: {}", "ERROR:".red(), err);
+        std::process::exit(1)
+      })
+    });
+
     Tags {
       tags,
       skip_tags,
+      expression,
     }
   }
 
-  pub fn should_skip_item(&self, item: &serde_yaml::Value) -> bool {
-    match item.as_mapping().unwrap().get("tags").unwrap().as_sequence() {
-      Some(item_tags_raw) => {
-        if item_tags_raw.is_empty() {
-          return false;
-        }
+  pub fn should_skip(&self, item_tags: &[String]) -> bool {
+    let item_tags: HashSet<String> = item_tags.iter().cloned().collect();
 
-        let item_tags: HashSet<String> = item_tags_raw
-          .iter()
-          .map(|t| t.clone().as_str().unwrap().to_owned())
-          .collect();
+    if !self.skip_tags.is_disjoint(&item_tags) {
+      return true;
+    }
 
-        if !self.skip_tags.is_disjoint(&item_tags) {
-          return true;
-        }
+    let never_requested = self.tags.contains("never")
+      || self.expression.as_ref().is_some_and(|expr| expr.mentions("never"));
+    if item_tags.contains("never") && !never_requested {
+      return true;
+    }
 
-        if item_tags.contains("never") && !self.tags.contains("never") {
-          return true;
-        }
-        if !self.tags.is_disjoint(&item_tags) {
-          return false;
-        }
+    if item_tags.contains("always") {
+      return false;
+    }
 
-        if item_tags.contains("always") {
-          return false;
-        }
-        if item_tags.contains("never") {
-          return true;
+    match &self.expression {
+      // Expressions select untagged items too, e.g. `not slow`
+      Some(expression) => !expression.matches(&item_tags),
+      // Without `--include-tags`, every item runs but the skipped ones
+      None => {
+        !item_tags.is_empty()
+          && !self.tags.is_empty()
+          && self.tags.is_disjoint(&item_tags)
+      }
+    }
+  }
+
+  /// Drops the skipped items from the plan, the hooks, the templates and
+  /// the included files
+  pub fn filter_doc(&self, doc: &mut BenchmarkDoc) {
+    self.filter_included_doc(doc, &[]);
+  }
+
+  /// Filters a file included by items with the `inherited` tags, which its
+  /// items have on top of their own
+  fn filter_included_doc(&self, doc: &mut BenchmarkDoc, inherited: &[String]) {
+    self.filter_plan(&mut doc.before_vu, inherited);
+    self.filter_plan(&mut doc.before_each, inherited);
+    self.filter_plan(&mut doc.plan, inherited);
+    self.filter_plan(&mut doc.after_each, inherited);
+    for items in doc.templates.values_mut() {
+      self.filter_plan(items, inherited);
+    }
+  }
+
+  fn filter_plan(&self, plan: &mut Vec<PlanItem>, inherited: &[String]) {
+    let tags = |item_tags: &[String]| -> Vec<String> {
+      inherited.iter().chain(item_tags).cloned().collect()
+    };
+    plan.retain(|item| !self.should_skip(&tags(&item.tags)));
+    for item in plan.iter_mut() {
+      // Nested items have the tags of the item holding them
+      let item_tags = tags(&item.tags);
+      if let Action::Include(includes) = &mut item.action {
+        for include in includes.iter_mut() {
+          self.filter_included_doc(&mut include.doc, &item_tags);
         }
-        true
       }
-      None => false,
+      if let Action::Transaction(items) = &mut item.action {
+        self.filter_plan(items, &item_tags);
+      }
+      if let Some(on_error) = &mut item.on_error {
+        self.filter_plan(&mut on_error.steps, &item_tags);
+      }
     }
   }
 }
 
-pub fn list_benchmark_file_tasks(benchmark_file: &str, tags: &Tags) {
-  let docs = reader::read_file_as_yml(benchmark_file);
-  let items = reader::read_yaml_doc_accessor(&docs[0], "plan");
+/// Boolean expression over the item tags, like `(smoke or critical) and not
+/// slow`. `not` binds tighter than `and`, which binds tighter than `or`.
+#[derive(Debug, PartialEq)]
+pub enum TagExpr {
+  Tag(String),
+  Not(Box<TagExpr>),
+  And(Box<TagExpr>, Box<TagExpr>),
+  Or(Box<TagExpr>, Box<TagExpr>),
+}
 
-  println!();
+impl TagExpr {
+  pub fn parse(expression: &str) -> Result<TagExpr, String> {
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut pos = 0;
+    let expr = parse_or(&tokens, &mut pos)?;
+
+    match tokens.get(pos) {
+      None => Ok(expr),
+      Some(token) => Err(format!("unexpected '{token}'")),
+    }
+  }
+
+  pub fn matches(&self, tags: &HashSet<String>) -> bool {
+    match self {
+      TagExpr::Tag(tag) => tags.contains(tag),
+      TagExpr::Not(expr) => !expr.matches(tags),
+      TagExpr::And(left, right) => left.matches(tags) && right.matches(tags),
+      TagExpr::Or(left, right) => left.matches(tags) || right.matches(tags),
+    }
+  }
+
+  /// Whether the tag appears anywhere in the expression
+  pub fn mentions(&self, tag: &str) -> bool {
+    match self {
+      TagExpr::Tag(name) => name == tag,
+      TagExpr::Not(expr) => expr.mentions(tag),
+      TagExpr::And(left, right) | TagExpr::Or(left, right) => {
+        left.mentions(tag) || right.mentions(tag)
+      }
+    }
+  }
+}
+
+impl fmt::Display for TagExpr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TagExpr::Tag(tag) => write!(f, "{tag}"),
+      TagExpr::Not(expr) => write!(f, "not {expr}"),
+      TagExpr::And(left, right) => write!(f, "({left} and {right})"),
+      TagExpr::Or(left, right) => write!(f, "({left} or {right})"),
+    }
+  }
+}
+
+fn parse_or(tokens: &[&str], pos: &mut usize) -> Result<TagExpr, String> {
+  let mut expr = parse_and(tokens, pos)?;
+  while tokens.get(*pos) == Some(&"or") {
+    *pos += 1;
+    expr = TagExpr::Or(Box::new(expr), Box::new(parse_and(tokens, pos)?));
+  }
+  Ok(expr)
+}
+
+fn parse_and(tokens: &[&str], pos: &mut usize) -> Result<TagExpr, String> {
+  let mut expr = parse_not(tokens, pos)?;
+  while tokens.get(*pos) == Some(&"and") {
+    *pos += 1;
+    expr = TagExpr::And(Box::new(expr), Box::new(parse_not(tokens, pos)?));
+  }
+  Ok(expr)
+}
+
+fn parse_not(tokens: &[&str], pos: &mut usize) -> Result<TagExpr, String> {
+  let token = tokens.get(*pos).ok_or("unexpected end of expression")?;
+  *pos += 1;
+
+  match *token {
+    "not" => Ok(TagExpr::Not(Box::new(parse_not(tokens, pos)?))),
+    "(" => {
+      let expr = parse_or(tokens, pos)?;
+      if tokens.get(*pos) != Some(&")") {
+        return Err("missing ')'".to_owned());
+      }
+      *pos += 1;
+      Ok(expr)
+    }
+    ")" | "and" | "or" => Err(format!("unexpected '{token}'")),
+    tag => Ok(TagExpr::Tag(tag.to_owned())),
+  }
+}
+
+/// Item of the plan listed by `--list-tasks`, with the items it runs
+#[derive(Serialize)]
+struct Task<'a> {
+  name: Option<&'a str>,
+  tags: &'a [String],
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  tasks: Vec<Task<'a>>,
+}
+
+#[derive(Serialize)]
+struct TaskListing<'a> {
+  tags: Vec<&'a String>,
+  skip_tags: Vec<&'a String>,
+  expression: Option<String>,
+  tasks: Vec<Task<'a>>,
+}
+
+#[derive(Serialize)]
+struct TagListing<'a> {
+  tags: Vec<&'a str>,
+}
+
+pub fn list_benchmark_file_tasks(
+  benchmark_file: &str,
+  tags: &Tags,
+  format: ListFormat,
+) {
+  let mut doc = read_benchmark_file(benchmark_file);
+  tags.filter_doc(&mut doc);
 
   let mut include_tags: Vec<_> = tags.tags.iter().collect();
   include_tags.sort();
-  println!(
-    "{:width$} {:width2$?}",
-    "Tags".green(),
-    &tags,
-    width = 15,
-    width2 = 25
-  );
-
   let mut skip_tags: Vec<_> = tags.skip_tags.iter().collect();
   skip_tags.sort();
-  println!(
-    "{:width$} {:width2$?}",
-    "Skip-Tags".green(),
-    &tags,
-    width = 15,
-    width2 = 25
-  );
-
-  let items: Vec<_> = items
-    .as_sequence()
-    .unwrap()
-    .iter()
-    .filter(|item| !tags.should_skip_item(item))
-    .collect();
 
-  if items.is_empty() {
-    println!("{}", "No items".red());
-    std::process::exit(1)
+  if format != ListFormat::Text {
+    print_listing(
+      &TaskListing {
+        tags: include_tags,
+        skip_tags,
+        expression: tags.expression.as_ref().map(ToString::to_string),
+        tasks: tasks(&doc.plan),
+      },
+      format,
+    );
+    if doc.plan.is_empty() {
+      std::process::exit(1)
+    }
+    return;
   }
 
-  println!("{}", serde_yaml::to_string(&items).unwrap())
-}
+  println!();
 
-pub fn list_benchmark_file_tags(benchmark_file: &str) {
-  let docs = reader::read_file_as_yml(benchmark_file);
-  let items =
-    reader::read_yaml_doc_accessor(&docs[0], "plan").as_sequence().unwrap();
+  println!("{:width$} {:?}", "Tags".green(), &include_tags, width = 15);
+
+  println!("{:width$} {:?}", "Skip-Tags".green(), &skip_tags, width = 15);
+
+  if let Some(expression) = &tags.expression {
+    println!("{:width$} {}", "Expression".green(), expression, width = 15);
+  }
 
   println!();
 
-  if items.is_empty() {
+  if doc.plan.is_empty() {
     println!("{}", "No items".red());
     std::process::exit(1)
   }
-  let mut tags: HashSet<&str> = HashSet::new();
-  for item in items {
-    if let Some(item_tags_raw) = item.get("tags") {
-      let item_tags_raw = item_tags_raw.as_sequence().unwrap();
-      tags.extend(item_tags_raw.iter().map(|t| t.as_str().unwrap()));
+
+  print_tasks(&doc.plan, 0);
+}
+
+fn tasks(plan: &[PlanItem]) -> Vec<Task<'_>> {
+  plan
+    .iter()
+    .map(|item| Task {
+      name: item.name.as_deref(),
+      tags: &item.tags,
+      tasks: match &item.action {
+        Action::Include(includes) => includes
+          .iter()
+          .flat_map(|include| tasks(&include.doc.plan))
+          .collect(),
+        Action::Transaction(items) => tasks(items),
+        _ => Vec::new(),
+      },
+    })
+    .collect()
+}
+
+fn print_tasks(plan: &[PlanItem], depth: usize) {
+  for item in plan {
+    println!(
+      "{:indent$}{:width$} {:?}",
+      "",
+      item.name.as_deref().unwrap_or_default().green(),
+      &item.tags,
+      indent = depth * 2,
+      width = 25
+    );
+    match &item.action {
+      Action::Include(includes) => {
+        for include in includes {
+          print_tasks(&include.doc.plan, depth + 1);
+        }
+      }
+      Action::Transaction(items) => print_tasks(items, depth + 1),
+      _ => {}
     }
   }
+}
+
+pub fn list_benchmark_file_tags(benchmark_file: &str, format: ListFormat) {
+  let doc = read_benchmark_file(benchmark_file);
 
+  let mut tags: HashSet<&str> = HashSet::new();
+  collect_tags(&doc.plan, &mut tags);
   let mut tags: Vec<_> = tags.into_iter().collect();
   tags.sort_unstable();
+
+  if format != ListFormat::Text {
+    print_listing(&TagListing { tags }, format);
+    if doc.plan.is_empty() {
+      std::process::exit(1)
+    }
+    return;
+  }
+
+  println!();
+
+  if doc.plan.is_empty() {
+    println!("{}", "No items".red());
+    std::process::exit(1)
+  }
   println!("{:width$} {:?}", "Tags".green(), &tags, width = 15);
 }
+
+/// Prints a listing for tools, as JSON or YAML
+fn print_listing<T: Serialize>(listing: &T, format: ListFormat) {
+  let printed = match format {
+    ListFormat::Yaml => serde_yaml::to_string(listing).unwrap(),
+    _ => serde_json::to_string_pretty(listing).unwrap() + "\n",
+  };
+  print!("{printed}");
+}
+
+fn collect_tags<'a>(plan: &'a [PlanItem], tags: &mut HashSet<&'a str>) {
+  for item in plan {
+    tags.extend(item.tags.iter().map(String::as_str));
+    match &item.action {
+      Action::Include(includes) => {
+        for include in includes {
+          collect_tags(&include.doc.plan, tags);
+        }
+      }
+      Action::Transaction(items) => collect_tags(items, tags),
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn names(plan: &[PlanItem]) -> Vec<&str> {
+    plan.iter().map(|item| item.name.as_deref().unwrap_or_default()).collect()
+  }
+
+  fn tag_set(tags: &[&str]) -> HashSet<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+  }
+
+  fn item_tags(tags: &[&str]) -> Vec<String> {
+    tags.iter().map(|tag| tag.to_string()).collect()
+  }
+
+  fn expression(expression: &str) -> Tags {
+    Tags::new(vec![], vec![], Some(expression.to_owned()))
+  }
+
+  #[test]
+  fn parses_with_precedence() {
+    let expr = TagExpr::parse("a or b and not c").unwrap();
+    assert_eq!(expr.to_string(), "(a or (b and not c))");
+
+    let expr = TagExpr::parse("not a and b or c").unwrap();
+    assert_eq!(expr.to_string(), "((not a and b) or c)");
+
+    let expr = TagExpr::parse("a and b and c").unwrap();
+    assert_eq!(expr.to_string(), "((a and b) and c)");
+  }
+
+  #[test]
+  fn parses_parentheses() {
+    let expr = TagExpr::parse("(smoke or critical) and not slow").unwrap();
+    assert_eq!(expr.to_string(), "((smoke or critical) and not slow)");
+
+    let expr = TagExpr::parse("not(a or b)").unwrap();
+    assert_eq!(expr, TagExpr::Not(Box::new(TagExpr::Or(Box::new(TagExpr::Tag("a".to_owned())), Box::new(TagExpr::Tag("b".to_owned()))))));
+
+    assert_eq!(TagExpr::parse("((a))"), Ok(TagExpr::Tag("a".to_owned())));
+  }
+
+  #[test]
+  fn matches_tags() {
+    let expr = TagExpr::parse("(smoke or critical) and not slow").unwrap();
+
+    assert!(expr.matches(&tag_set(&["smoke"])));
+    assert!(expr.matches(&tag_set(&["critical", "api"])));
+    assert!(!expr.matches(&tag_set(&["smoke", "slow"])));
+    assert!(!expr.matches(&tag_set(&[])));
+    assert!(TagExpr::parse("not not a").unwrap().matches(&tag_set(&["a"])));
+  }
+
+  #[test]
+  fn reports_malformed_expressions() {
+    assert_eq!(TagExpr::parse(""), Err("unexpected end of expression".to_owned()));
+    assert_eq!(TagExpr::parse("a and"), Err("unexpected end of expression".to_owned()));
+    assert_eq!(TagExpr::parse("a or or b"), Err("unexpected 'or'".to_owned()));
+    assert_eq!(TagExpr::parse("(a or b"), Err("missing ')'".to_owned()));
+    assert_eq!(TagExpr::parse("a b"), Err("unexpected 'b'".to_owned()));
+    assert_eq!(TagExpr::parse("a)"), Err("unexpected ')'".to_owned()));
+    assert_eq!(TagExpr::parse("not"), Err("unexpected end of expression".to_owned()));
+  }
+
+  #[test]
+  fn judges_untagged_items_by_the_expression() {
+    assert!(expression("smoke").should_skip(&[]));
+    assert!(!expression("not slow").should_skip(&[]));
+    assert!(expression("not slow").should_skip(&item_tags(&["slow"])));
+
+    // Include tags only filter tagged items
+    let tags = Tags::new(vec!["smoke".to_owned()], vec![], None);
+    assert!(!tags.should_skip(&[]));
+    assert!(!tags.should_skip(&item_tags(&["smoke"])));
+    assert!(tags.should_skip(&item_tags(&["slow"])));
+  }
+
+  #[test]
+  fn handles_never_and_always() {
+    let unfiltered = Tags::new(vec![], vec![], None);
+    assert!(unfiltered.should_skip(&item_tags(&["never"])));
+    assert!(!unfiltered.should_skip(&item_tags(&["always"])));
+
+    // `always` items run whatever the expression, unless skipped
+    assert!(!expression("smoke").should_skip(&item_tags(&["always"])));
+    assert!(!expression("not always").should_skip(&item_tags(&["always"])));
+    let skipped = Tags::new(vec![], vec!["always".to_owned()], None);
+    assert!(skipped.should_skip(&item_tags(&["always"])));
+
+    // `never` items only run when asked for by name
+    assert!(expression("smoke").should_skip(&item_tags(&["never", "smoke"])));
+    assert!(!expression("never").should_skip(&item_tags(&["never"])));
+    assert!(!expression("smoke or never").should_skip(&item_tags(&["never", "smoke"])));
+    let included = Tags::new(vec!["never".to_owned()], vec![], None);
+    assert!(!included.should_skip(&item_tags(&["never"])));
+  }
+
+  #[test]
+  fn keeps_untagged_items_of_tagged_transactions() {
+    let doc = "plan:\n  - name: Checkout\n    tags: [smoke]\n    transaction:\n      - name: Cart\n        request:\n          url: /cart\n      - name: Pay\n        tags: [slow]\n        request:\n          url: /pay\n  - name: Home\n    request:\n      url: /\n";
+    let mut doc = serde_yaml::from_str::<BenchmarkDoc>(doc).unwrap();
+    Tags::new(vec![], vec![], Some("smoke and not slow".to_owned())).filter_doc(&mut doc);
+
+    assert_eq!(names(&doc.plan), ["Checkout"]);
+    match &doc.plan[0].action {
+      Action::Transaction(items) => assert_eq!(names(items), ["Cart"]),
+      _ => panic!("expected a transaction"),
+    }
+  }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use colored::*;
+
+use crate::actions::Report;
+use crate::benchmark::{probe, Benchmark, Pool};
+use crate::config::Config;
+use crate::RunStats;
+
+const PROBE_DURATION: Duration = Duration::from_secs(10);
+/// Span of the windows `SaturationWatch` compares
+const SATURATION_WINDOW: Duration = Duration::from_secs(5);
+const MAX_CONCURRENCY: u64 = 4096;
+/// Probes failing more requests than this are over the target too
+const MAX_FAILED_RATIO: f64 = 0.01;
+
+/// Outcome of running the benchmark at a given concurrency for a while
+struct Probe {
+  concurrency: u64,
+  stats: RunStats,
+  duration: f64,
+}
+
+impl Probe {
+  fn p99(&self) -> f64 {
+    self.stats.global.value_at_quantile(0.99)
+  }
+
+  fn throughput(&self) -> f64 {
+    self.stats.global.total_requests as f64 / self.duration
+  }
+
+  fn within(&self, target: Duration) -> bool {
+    let global = &self.stats.global;
+    let failed = global.failed_requests as f64 / global.total_requests as f64;
+    global.total_requests > 0
+      && failed <= MAX_FAILED_RATIO
+      && self.p99() <= target.as_secs_f64() * 1_000.0
+  }
+}
+
+/// Searches the highest concurrency keeping the p99 of the requests under
+/// `target`, doubling it until the target is missed and then bisecting, and
+/// returns the stats of the best probe. Rampup and virtual users don't
+/// apply, every probe starts all its iterations at once.
+pub async fn find_capacity(
+  benchmark: &Arc<Benchmark>,
+  pool: &Pool,
+  config: &Arc<Config>,
+  target: Duration,
+  probe_duration: Option<Duration>,
+) -> (RunStats, f64) {
+  let probe_duration = probe_duration.unwrap_or(PROBE_DURATION);
+  let config = Arc::new(Config {
+    rampup: Duration::ZERO,
+    ..(**config).clone()
+  });
+
+  let mut best: Option<Probe> = None;
+  let mut last: Option<Probe> = None;
+  // Lowest concurrency found over the target
+  let mut over: Option<u64> = None;
+  let mut concurrency = 1;
+  let mut iterations = 0;
+
+  while !config.is_halted() {
+    let begin = Instant::now();
+    let stats = probe(
+      benchmark,
+      pool,
+      &config,
+      concurrency,
+      probe_duration,
+      iterations,
+    )
+    .await;
+    iterations += stats.iterations as u64;
+    let probe = Probe {
+      concurrency,
+      stats,
+      duration: begin.elapsed().as_secs_f64(),
+    };
+
+    let within = probe.within(target);
+    println!(
+      "{:width$} {} {} {}",
+      format!("Concurrency {concurrency}").yellow(),
+      format!("p99 {}", config.time_unit.format(probe.p99())).purple(),
+      format!("{:.2} [#/sec]", probe.throughput()).purple(),
+      if within { "within target".green() } else { "over target".red() },
+      width = 25
+    );
+
+    if within {
+      best = Some(probe);
+    } else {
+      over = Some(concurrency);
+      last = Some(probe);
+    }
+
+    let low = best.as_ref().map_or(0, |best| best.concurrency);
+    concurrency = match over {
+      None if low >= MAX_CONCURRENCY => break,
+      None => (low * 2).min(MAX_CONCURRENCY),
+      // Bisects down to a 5% precision
+      Some(high) if high - low <= (low / 20).max(1) => break,
+      Some(high) => (low + high) / 2,
+    };
+  }
+
+  println!();
+  match best {
+    Some(best) => {
+      println!(
+        "{} {} concurrent iterations, {} at p99 {}",
+        "Capacity".yellow(),
+        best.concurrency.to_string().purple(),
+        format!("{:.2} [#/sec]", best.throughput()).purple(),
+        config.time_unit.format(best.p99()).purple()
+      );
+      (best.stats, best.duration)
+    }
+    None => {
+      eprintln!(
+        "{} Even a single iteration at a time is over the target p99",
+        "WARNING:".yellow().bold()
+      );
+      match last {
+        Some(last) => (last.stats, last.duration),
+        None => (RunStats::default(), 0.0),
+      }
+    }
+  }
+}
+
+/// Window of a run before the latency degraded
+struct Knee {
+  elapsed: f64,
+  p99: f64,
+  throughput: f64,
+}
+
+/// Compares the p99 of the requests over consecutive windows of the run, for
+/// `--stop-on-saturation`. The best window is the warm baseline, and the run
+/// is saturated once a window gets `factor` times slower than it.
+pub struct SaturationWatch {
+  factor: f64,
+  run_start: Instant,
+  window_start: Instant,
+  window: RunStats,
+  baseline: Option<f64>,
+  knee: Option<Knee>,
+}
+
+impl SaturationWatch {
+  pub fn new(factor: f64) -> Self {
+    SaturationWatch {
+      factor,
+      run_start: Instant::now(),
+      window_start: Instant::now(),
+      window: RunStats::default(),
+      baseline: None,
+      knee: None,
+    }
+  }
+
+  /// Records the reports of an iteration, and tells whether the run got
+  /// saturated with the window they end, printing the knee point if so
+  pub fn record(&mut self, reports: &[Report], config: &Config) -> bool {
+    self.window.record(reports);
+    let duration = self.window_start.elapsed();
+    if duration < SATURATION_WINDOW || self.window.global.total_requests == 0
+    {
+      return false;
+    }
+
+    let window = std::mem::take(&mut self.window);
+    self.window_start = Instant::now();
+    let p99 = window.global.value_at_quantile(0.99);
+    let window = Knee {
+      elapsed: self.run_start.elapsed().as_secs_f64(),
+      p99,
+      throughput: window.global.total_requests as f64
+        / duration.as_secs_f64(),
+    };
+
+    let baseline = match self.baseline {
+      Some(baseline) if p99 > baseline * self.factor => baseline,
+      baseline => {
+        self.baseline = Some(baseline.map_or(p99, |best| best.min(p99)));
+        self.knee = Some(window);
+        return false;
+      }
+    };
+
+    eprintln!(
+      "{} Saturated after {:.1}s, the p99 of {} is over {} times the {} \
+       baseline",
+      "WARNING:".yellow().bold(),
+      window.elapsed,
+      config.time_unit.format(window.p99),
+      self.factor,
+      config.time_unit.format(baseline)
+    );
+    if let Some(knee) = &self.knee {
+      println!(
+        "{} {} into the run, {} at p99 {}",
+        "Knee point".yellow(),
+        format!("{:.1}s", knee.elapsed).purple(),
+        format!("{:.2} [#/sec]", knee.throughput).purple(),
+        config.time_unit.format(knee.p99).purple()
+      );
+    }
+    true
+  }
+}
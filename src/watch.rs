@@ -0,0 +1,141 @@
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use colored::*;
+use futures::future::{self, Either};
+use path_absolutize::Absolutize;
+use tokio::{runtime, time::sleep};
+
+use crate::args::FlattenedCli;
+use crate::benchmark::{self, wait_for_interruption};
+use crate::checker;
+use crate::parse::read_benchmark_doc;
+use crate::reader::take_opened_files;
+
+/// Interval the watched files are checked at
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Time given to editors to finish writing a file before running again
+const SETTLE_DELAY: Duration = Duration::from_millis(100);
+
+/// Runs the benchmark, and again whenever one of the files it reads changes,
+/// until interrupted. A benchmark that can't be read is reported without
+/// running, and watched until fixed.
+pub fn run(args: &FlattenedCli) -> ! {
+  loop {
+    let files = match load(&args.benchmark_file) {
+      Ok(files) => {
+        run_once(args);
+        files
+      }
+      Err((files, err)) => {
+        println!("{} {}", "ERROR:".red().bold(), err);
+        files
+      }
+    };
+
+    println!(
+      "{}",
+      format!("Watching {} files, Ctrl-C to stop", files.len()).dimmed()
+    );
+    wait_for_change(&files);
+    println!();
+  }
+}
+
+/// Reads the benchmark as a run would, returning the files it read, even
+/// when it couldn't be read
+fn load(
+  benchmark_file: &str,
+) -> Result<BTreeSet<PathBuf>, (BTreeSet<PathBuf>, String)> {
+  let path = Path::new(benchmark_file).absolutize().unwrap().to_path_buf();
+  take_opened_files();
+
+  // Missing files panic while parsing, their message is reported instead
+  let hook = panic::take_hook();
+  panic::set_hook(Box::new(|_| {}));
+  let loaded = panic::catch_unwind(|| read_benchmark_doc(&path));
+  panic::set_hook(hook);
+
+  let mut files = take_opened_files();
+  files.insert(path);
+  match loaded {
+    Ok(Ok(_)) => Ok(files),
+    Ok(Err(err)) => Err((files, err)),
+    Err(panic) => Err((files, panic_message(panic))),
+  }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+  match panic.downcast::<String>() {
+    Ok(message) => *message,
+    Err(panic) => match panic.downcast::<&str>() {
+      Ok(message) => message.to_string(),
+      Err(_) => "couldn't read the benchmark".to_owned(),
+    },
+  }
+}
+
+/// Runs the benchmark and sums it up in a line. It passes when no request
+/// failed, no iteration errored and the thresholds held.
+fn run_once(args: &FlattenedCli) {
+  let start = Instant::now();
+  let result = benchmark::execute(args);
+  let thresholds_passed = checker::check_thresholds(
+    &result.stats,
+    &result.thresholds,
+    result.duration,
+  );
+
+  let global = &result.stats.global;
+  let passed =
+    global.failed_requests == 0 && result.errors == 0 && thresholds_passed;
+  let outcome = if passed {
+    "PASS".green().bold()
+  } else {
+    "FAIL".red().bold()
+  };
+  let thresholds = if thresholds_passed {
+    ""
+  } else {
+    ", thresholds failed"
+  };
+  println!(
+    "\n{} {} requests, {} failed, {} iterations errored{} in {:.2}s",
+    outcome,
+    global.total_requests,
+    global.failed_requests,
+    result.errors,
+    thresholds,
+    start.elapsed().as_secs_f64()
+  );
+}
+
+/// Waits for one of the files to be modified, created or removed, exiting
+/// when interrupted
+fn wait_for_change(files: &BTreeSet<PathBuf>) {
+  let modified = |files: &BTreeSet<PathBuf>| -> Vec<Option<SystemTime>> {
+    files
+      .iter()
+      .map(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok())
+      .collect()
+  };
+  let before = modified(files);
+
+  let rt = runtime::Builder::new_current_thread().enable_all().build();
+  rt.unwrap().block_on(async {
+    let changed = Box::pin(async {
+      while modified(files) == before {
+        sleep(POLL_INTERVAL).await;
+      }
+      sleep(SETTLE_DELAY).await;
+    });
+    let interrupted = Box::pin(wait_for_interruption());
+    if let Either::Right(_) = future::select(changed, interrupted).await {
+      std::process::exit(130);
+    }
+  });
+}
@@ -1,6 +1,35 @@
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{prelude::*, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hdrhistogram::Histogram;
+use path_absolutize::Absolutize;
+use serde::{Deserialize, Serialize};
+
+use crate::actions::Report;
+use crate::args::{DnsMode, FlattenedCli};
+use crate::config::Config;
+use crate::parse::{format_duration, BenchmarkDoc, Metadata};
+
+/// Format of the files written by `--report`. Bump `REPORT_VERSION` on
+/// incompatible changes.
+#[derive(Serialize, Deserialize)]
+pub struct ReportFile {
+  pub version: u32,
+  pub benchmark: String,
+  /// Name, description and version of the benchmark, when it sets them
+  #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+  pub metadata: Metadata,
+  /// Unix time the run started at
+  pub started_at: u64,
+  pub duration: f64,
+  pub reports: Vec<Report>,
+}
+
+pub const REPORT_VERSION: u32 = 1;
 
 pub fn write_file(filepath: &str, content: String) {
   let path = Path::new(filepath);
@@ -15,3 +44,241 @@ pub fn write_file(filepath: &str, content: String) {
     panic!("couldn't write to {}: {:?}", display, why);
   }
 }
+
+/// Creates the directory of `--out-dir` under `parent`, named after the UTC
+/// time the run starts at, like `20261016-083000`
+pub fn create_run_dir(parent: &str) -> PathBuf {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+  let name = utc_timestamp(now.as_secs());
+  if let Err(why) = fs::create_dir_all(parent) {
+    panic!("couldn't create {}: {:?}", parent, why);
+  }
+  // The benchmark may run from its own directory
+  let parent = Path::new(parent).absolutize().unwrap();
+
+  // Runs started within the same second get a suffix
+  let mut suffix = 0;
+  loop {
+    let dir = match suffix {
+      0 => parent.join(&name),
+      suffix => parent.join(format!("{name}-{suffix}")),
+    };
+    match fs::create_dir(&dir) {
+      Ok(()) => return dir,
+      Err(why) if why.kind() == ErrorKind::AlreadyExists => suffix += 1,
+      Err(why) => panic!("couldn't create {}: {:?}", dir.display(), why),
+    }
+  }
+}
+
+/// Formats a Unix time as `YYYYMMDD-HHMMSS`, in UTC
+fn utc_timestamp(secs: u64) -> String {
+  let (days, secs) = (secs / 86_400, secs % 86_400);
+  // Civil date of the days since 1970-01-01, see
+  // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+  let z = days + 719_468;
+  let era = z / 146_097;
+  let doe = z % 146_097;
+  let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + u64::from(month <= 2);
+
+  format!(
+    "{:04}{:02}{:02}-{:02}{:02}{:02}",
+    year,
+    month,
+    day,
+    secs / 3_600,
+    secs % 3_600 / 60,
+    secs % 60
+  )
+}
+
+/// Writes a latency histogram, recorded in microseconds, in the percentile
+/// format of HdrHistogram, in milliseconds
+pub fn write_hgrm(filepath: &str, hist: &Histogram<u64>) {
+  let mut content = format!(
+    "{:>12} {:>14} {:>10} {:>14}\n\n",
+    "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+  );
+  let mut total = 0;
+  for value in hist.iter_quantiles(5) {
+    total += value.count_since_last_iteration();
+    let quantile = value.quantile_iterated_to();
+    write!(
+      content,
+      "{:12.3} {:14.12} {:10}",
+      value.value_iterated_to() as f64 / 1_000.0,
+      quantile,
+      total
+    )
+    .unwrap();
+    if quantile < 1.0 {
+      write!(content, " {:14.2}", 1.0 / (1.0 - quantile)).unwrap();
+    }
+    content.push('\n');
+  }
+  writeln!(
+    content,
+    "#[Mean    = {:12.3}, StdDeviation   = {:12.3}]",
+    hist.mean() / 1_000.0,
+    hist.stdev() / 1_000.0
+  )
+  .unwrap();
+  writeln!(
+    content,
+    "#[Max     = {:12.3}, Total count    = {:12}]",
+    hist.max() as f64 / 1_000.0,
+    hist.len()
+  )
+  .unwrap();
+
+  write_file(filepath, content);
+}
+
+/// Configuration of a run as written by `--config-snapshot`, with its keys
+/// sorted so two snapshots can be diffed
+#[derive(Serialize)]
+struct ConfigSnapshot<'a> {
+  benchmark: &'a str,
+  /// Benchmark file with its includes resolved, the items left by the tags,
+  /// and the values overridden by the command line
+  doc: BenchmarkDoc,
+  options: SnapshotOptions<'a>,
+}
+
+/// Command line options changing what the run does
+#[derive(Serialize)]
+struct SnapshotOptions<'a> {
+  environment: Option<&'a str>,
+  tags: &'a [String],
+  skip_tags: &'a [String],
+  tag_expression: Option<&'a str>,
+  timeout: String,
+  dns: DnsMode,
+  ipv4: bool,
+  ipv6: bool,
+  relaxed_interpolations: bool,
+  no_check_certificate: bool,
+  body_limit: Option<usize>,
+  slow_threshold: Option<String>,
+  sharded: bool,
+  report: Option<&'a str>,
+}
+
+/// Writes the configuration a run was given, with the seed picked for it
+/// and the secrets hidden
+pub fn write_config_snapshot(
+  filepath: &str,
+  doc: &BenchmarkDoc,
+  config: &Config,
+  args: &FlattenedCli,
+) {
+  let mut doc = doc.clone();
+  doc.iterations = config.iterations;
+  doc.concurrency = config.concurrency as usize;
+  doc.seed = Some(config.seed);
+  doc.global = config.global.clone();
+
+  let snapshot = ConfigSnapshot {
+    benchmark: &args.benchmark_file,
+    doc,
+    options: SnapshotOptions {
+      environment: args.environment.as_deref(),
+      tags: &args.tags,
+      skip_tags: &args.skip_tags_option,
+      tag_expression: args.tag_expression_option.as_deref(),
+      timeout: format_duration(config.timeout),
+      dns: args.dns,
+      ipv4: args.ipv4,
+      ipv6: args.ipv6,
+      relaxed_interpolations: args.relaxed_interpolations,
+      no_check_certificate: args.no_check_certificate,
+      body_limit: args.body_limit,
+      slow_threshold: args.slow_threshold_option.map(format_duration),
+      sharded: args.sharded,
+      report: args.report_path_option.as_deref(),
+    },
+  };
+  // Going through JSON sorts the keys, and writes enums as keys, not YAML tags
+  let snapshot = serde_json::to_value(snapshot).unwrap();
+  let content = serde_yaml::to_string(&snapshot).unwrap();
+
+  write_file(filepath, config.redact(&content));
+}
+
+/// Writes the reports of a run along with its metadata, as YAML
+pub fn write_report(
+  filepath: &str,
+  benchmark: &str,
+  metadata: &Metadata,
+  reports: Vec<Report>,
+  duration: f64,
+) {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+  let report = ReportFile {
+    version: REPORT_VERSION,
+    benchmark: benchmark.to_owned(),
+    metadata: metadata.clone(),
+    started_at: (now.as_secs_f64() - duration) as u64,
+    duration,
+    reports,
+  };
+
+  write_file(filepath, serde_yaml::to_string(&report).unwrap());
+}
+
+/// Opens the file every report gets appended to, as a JSON line, while the
+/// benchmark runs
+pub fn create_stream(filepath: &str) -> BufWriter<File> {
+  match File::create(filepath) {
+    Err(why) => panic!("couldn't create {}: {:?}", filepath, why),
+    Ok(file) => BufWriter::new(file),
+  }
+}
+
+pub fn stream_reports(stream: &mut BufWriter<File>, reports: &[Report]) {
+  for report in reports {
+    let written = serde_json::to_writer(&mut *stream, report)
+      .map_err(std::io::Error::from)
+      .and_then(|_| stream.write_all(b"\n"));
+    if let Err(why) = written {
+      panic!("couldn't stream reports: {:?}", why);
+    }
+  }
+}
+
+/// Line of `--request-log`, written as soon as the request completes
+#[derive(Serialize)]
+pub struct RequestLogLine<'a> {
+  /// Unix time, in seconds, the request started at
+  pub timestamp: f64,
+  pub name: &'a str,
+  pub method: &'a str,
+  pub url: &'a str,
+  /// Missing when no response was received
+  pub status: Option<u16>,
+  pub duration: f64,
+  /// Size of the response body, declared when it was discarded
+  pub bytes: Option<usize>,
+  pub error: Option<String>,
+}
+
+pub fn log_request(log: &Mutex<BufWriter<File>>, line: &RequestLogLine) {
+  let mut log = log.lock().unwrap_or_else(PoisonError::into_inner);
+  let written = serde_json::to_writer(&mut *log, line)
+    .map_err(std::io::Error::from)
+    .and_then(|_| log.write_all(b"\n"));
+  if let Err(why) = written {
+    panic!("couldn't write the request log: {:?}", why);
+  }
+}
+
+pub fn flush_stream(stream: &mut BufWriter<File>) {
+  if let Err(why) = stream.flush() {
+    panic!("couldn't stream reports: {:?}", why);
+  }
+}